@@ -0,0 +1,9 @@
+CREATE TABLE roles_old (
+    id TEXT NOT NULL PRIMARY KEY,
+    discord_id INTEGER NOT NULL
+);
+
+INSERT OR IGNORE INTO roles_old (id, discord_id) SELECT id, discord_id FROM roles;
+
+DROP TABLE roles;
+ALTER TABLE roles_old RENAME TO roles;
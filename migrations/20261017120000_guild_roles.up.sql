@@ -0,0 +1,12 @@
+-- role mappings are now per guild, existing mappings get guild id 0 and are assigned on startup
+CREATE TABLE roles_new (
+    guild_id INTEGER NOT NULL,
+    id TEXT NOT NULL,
+    discord_id INTEGER NOT NULL,
+    PRIMARY KEY (guild_id, id)
+);
+
+INSERT INTO roles_new (guild_id, id, discord_id) SELECT 0, id, discord_id FROM roles;
+
+DROP TABLE roles;
+ALTER TABLE roles_new RENAME TO roles;
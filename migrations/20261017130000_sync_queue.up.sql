@@ -0,0 +1,9 @@
+-- role syncs that failed because the server was unreachable, retried by a background worker
+CREATE TABLE sync_queue (
+    account_id INTEGER NOT NULL PRIMARY KEY,
+    keep TEXT NOT NULL, -- json array of globed role ids
+    remove TEXT NOT NULL, -- json array of globed role ids
+    queued_at INTEGER NOT NULL, -- unix timestamp of the latest change
+    attempts INTEGER NOT NULL DEFAULT 0,
+    version INTEGER NOT NULL DEFAULT 0 -- bumped on every change, so the worker doesn't drop newer entries
+);
@@ -0,0 +1,6 @@
+-- globed roles of every account as of the last successful sync, used to skip syncs that wouldn't change anything
+CREATE TABLE synced_roles (
+    account_id INTEGER NOT NULL PRIMARY KEY,
+    roles TEXT NOT NULL, -- json array of globed role ids
+    synced_at INTEGER NOT NULL -- unix timestamp
+);
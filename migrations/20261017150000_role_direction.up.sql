@@ -0,0 +1,2 @@
+-- which way a role mapping is synced: 'discord_to_globed', 'globed_to_discord' or 'both'
+ALTER TABLE roles ADD COLUMN direction TEXT NOT NULL DEFAULT 'discord_to_globed';
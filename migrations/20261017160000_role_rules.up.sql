@@ -0,0 +1,2 @@
+-- optional rule deciding who gets the globed role, like `11 OR 12` or `11 AND NOT 13`, replaces the check for discord_id
+ALTER TABLE roles ADD COLUMN rule TEXT;
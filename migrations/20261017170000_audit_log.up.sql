@@ -0,0 +1,16 @@
+-- who did what and when, for links, unlinks, role mapping changes and sync failures
+CREATE TABLE audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    created_at INTEGER NOT NULL, -- unix timestamp
+    action TEXT NOT NULL,
+    actor_id INTEGER, -- discord user that did it, null for automatic actions
+    target_id INTEGER, -- discord user it was done to
+    account_id INTEGER, -- gd account involved
+    guild_id INTEGER,
+    details TEXT
+);
+
+CREATE INDEX audit_log_actor ON audit_log (actor_id);
+CREATE INDEX audit_log_target ON audit_log (target_id);
+CREATE INDEX audit_log_account ON audit_log (account_id);
+CREATE INDEX audit_log_action ON audit_log (action);
@@ -0,0 +1,10 @@
+CREATE TABLE linked_users_active (
+    id INTEGER NOT NULL PRIMARY KEY, -- discord id
+    gd_account_id INTEGER UNIQUE NOT NULL
+);
+
+INSERT INTO linked_users_active (id, gd_account_id)
+    SELECT id, gd_account_id FROM linked_users WHERE unlinked_at IS NULL;
+
+DROP TABLE linked_users;
+ALTER TABLE linked_users_active RENAME TO linked_users;
@@ -0,0 +1,19 @@
+-- links are kept after unlinking, only rows with no unlinked_at are active
+CREATE TABLE linked_users_history (
+    row_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    id INTEGER NOT NULL, -- discord id
+    gd_account_id INTEGER NOT NULL,
+    linked_at INTEGER NOT NULL, -- unix timestamp
+    unlinked_at INTEGER, -- unix timestamp
+    unlink_reason TEXT -- 'self', 'admin', 'left_guild', 'banned' or 'transferred'
+);
+
+-- when existing links were made is unknown, the time of the migration is the best guess
+INSERT INTO linked_users_history (id, gd_account_id, linked_at)
+    SELECT id, gd_account_id, CAST(strftime('%s', 'now') AS INTEGER) FROM linked_users;
+
+DROP TABLE linked_users;
+ALTER TABLE linked_users_history RENAME TO linked_users;
+
+CREATE UNIQUE INDEX linked_users_active_id ON linked_users (id) WHERE unlinked_at IS NULL;
+CREATE UNIQUE INDEX linked_users_active_account ON linked_users (gd_account_id) WHERE unlinked_at IS NULL;
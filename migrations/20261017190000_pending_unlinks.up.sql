@@ -0,0 +1,6 @@
+-- linked users who left every guild, unlinked once the grace period is over unless they rejoin
+CREATE TABLE pending_unlinks (
+    user_id INTEGER NOT NULL PRIMARY KEY, -- discord id
+    guild_id INTEGER NOT NULL, -- guild they left last
+    left_at INTEGER NOT NULL -- unix timestamp
+);
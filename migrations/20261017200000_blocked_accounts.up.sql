@@ -0,0 +1,10 @@
+-- GD accounts that can't be linked, added by admins or when a linked member gets banned
+CREATE TABLE blocked_accounts (
+    account_id INTEGER NOT NULL PRIMARY KEY,
+    blocked_at INTEGER NOT NULL, -- unix timestamp
+    blocked_by INTEGER, -- discord user that blocked it, null when it came from a ban
+    banned_user_id INTEGER, -- discord user whose ban blocked it, the block is lifted when they are unbanned
+    reason TEXT
+);
+
+CREATE INDEX blocked_accounts_banned_user ON blocked_accounts (banned_user_id);
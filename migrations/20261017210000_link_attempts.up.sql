@@ -0,0 +1,8 @@
+-- failed /link attempts, used to slow down guessing link codes
+CREATE TABLE link_attempts (
+    kind TEXT NOT NULL, -- 'user' for a discord id, 'username' for a lowercase GD username
+    subject TEXT NOT NULL,
+    failures INTEGER NOT NULL,
+    last_failure_at INTEGER NOT NULL, -- unix timestamp
+    PRIMARY KEY (kind, subject)
+);
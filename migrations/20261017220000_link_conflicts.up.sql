@@ -0,0 +1,10 @@
+-- attempts to link a GD account that is already linked to someone else
+CREATE TABLE link_conflicts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    created_at INTEGER NOT NULL, -- unix timestamp
+    account_id INTEGER NOT NULL, -- gd account that was claimed
+    claimant_id INTEGER NOT NULL, -- discord user that tried to link it
+    owner_id INTEGER NOT NULL -- discord user it is linked to
+);
+
+CREATE INDEX link_conflicts_account ON link_conflicts (account_id, created_at);
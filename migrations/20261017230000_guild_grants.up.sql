@@ -0,0 +1,9 @@
+-- globed roles each guild grants to an account, as of the last sync built in that guild
+CREATE TABLE guild_grants (
+    guild_id INTEGER NOT NULL,
+    account_id INTEGER NOT NULL,
+    role_id TEXT NOT NULL, -- globed role id
+    PRIMARY KEY (guild_id, account_id, role_id)
+);
+
+CREATE INDEX guild_grants_account ON guild_grants (account_id);
@@ -0,0 +1 @@
+ALTER TABLE link_attempts DROP COLUMN alerted;
@@ -0,0 +1,2 @@
+-- set once the log channel was alerted about these failures, so that it happens only once
+ALTER TABLE link_attempts ADD COLUMN alerted INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,238 @@
+//! Audit log of links, unlinks, role mapping changes and sync failures, stored in the `audit_log` table.
+
+use log::warn;
+use time::OffsetDateTime;
+
+use crate::{
+    db::AuditLogEntry,
+    serenity::{GuildId, UserId},
+    state::BotState,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum AuditAction {
+    #[name = "Link"]
+    Link,
+    #[name = "Unlink"]
+    Unlink,
+    #[name = "Admin link"]
+    AdminLink,
+    #[name = "Admin unlink"]
+    AdminUnlink,
+    #[name = "Link transferred"]
+    Transfer,
+    #[name = "Member left"]
+    MemberLeft,
+    #[name = "Member rejoined"]
+    MemberRejoined,
+    #[name = "Role mapping added"]
+    RoleAdd,
+    #[name = "Role mapping removed"]
+    RoleRemove,
+    #[name = "Role rule changed"]
+    RoleRule,
+    #[name = "Sync failed"]
+    SyncFailed,
+    #[name = "Member banned"]
+    MemberBanned,
+    #[name = "Member unbanned"]
+    MemberUnbanned,
+    #[name = "Account blocked"]
+    AccountBlocked,
+    #[name = "Account unblocked"]
+    AccountUnblocked,
+    #[name = "Link lockout"]
+    LinkLockout,
+    #[name = "Link lockout cleared"]
+    LinkUnlock,
+    #[name = "Account contested"]
+    LinkContested,
+}
+
+impl AuditAction {
+    pub fn from_db(value: &str) -> Option<Self> {
+        Some(match value {
+            "link" => Self::Link,
+            "unlink" => Self::Unlink,
+            "admin_link" => Self::AdminLink,
+            "admin_unlink" => Self::AdminUnlink,
+            "transfer" => Self::Transfer,
+            "member_left" => Self::MemberLeft,
+            "member_rejoined" => Self::MemberRejoined,
+            "role_add" => Self::RoleAdd,
+            "role_remove" => Self::RoleRemove,
+            "role_rule" => Self::RoleRule,
+            "sync_failed" => Self::SyncFailed,
+            "member_banned" => Self::MemberBanned,
+            "member_unbanned" => Self::MemberUnbanned,
+            "account_blocked" => Self::AccountBlocked,
+            "account_unblocked" => Self::AccountUnblocked,
+            "link_lockout" => Self::LinkLockout,
+            "link_unlock" => Self::LinkUnlock,
+            "link_contested" => Self::LinkContested,
+            _ => return None,
+        })
+    }
+
+    pub fn as_db(self) -> &'static str {
+        match self {
+            Self::Link => "link",
+            Self::Unlink => "unlink",
+            Self::AdminLink => "admin_link",
+            Self::AdminUnlink => "admin_unlink",
+            Self::Transfer => "transfer",
+            Self::MemberLeft => "member_left",
+            Self::MemberRejoined => "member_rejoined",
+            Self::RoleAdd => "role_add",
+            Self::RoleRemove => "role_remove",
+            Self::RoleRule => "role_rule",
+            Self::SyncFailed => "sync_failed",
+            Self::MemberBanned => "member_banned",
+            Self::MemberUnbanned => "member_unbanned",
+            Self::AccountBlocked => "account_blocked",
+            Self::AccountUnblocked => "account_unblocked",
+            Self::LinkLockout => "link_lockout",
+            Self::LinkUnlock => "link_unlock",
+            Self::LinkContested => "link_contested",
+        }
+    }
+}
+
+/// An action to record, built with the setters below.
+#[derive(Clone, Debug)]
+pub struct AuditEvent {
+    pub action: AuditAction,
+    pub actor: Option<UserId>,
+    pub target: Option<UserId>,
+    pub account_id: Option<i32>,
+    pub guild_id: Option<GuildId>,
+    pub details: Option<String>,
+}
+
+impl AuditEvent {
+    pub fn new(action: AuditAction) -> Self {
+        Self {
+            action,
+            actor: None,
+            target: None,
+            account_id: None,
+            guild_id: None,
+            details: None,
+        }
+    }
+
+    pub fn actor(mut self, actor: UserId) -> Self {
+        self.actor = Some(actor);
+        self
+    }
+
+    pub fn target(mut self, target: UserId) -> Self {
+        self.target = Some(target);
+        self
+    }
+
+    pub fn account(mut self, account_id: impl Into<Option<i32>>) -> Self {
+        self.account_id = account_id.into();
+        self
+    }
+
+    pub fn guild(mut self, guild_id: impl Into<Option<GuildId>>) -> Self {
+        self.guild_id = guild_id.into();
+        self
+    }
+
+    pub fn details(mut self, details: impl Into<String>) -> Self {
+        self.details = Some(details.into());
+        self
+    }
+}
+
+/// Which entries `/admin audit` shows, unset fields match everything.
+#[derive(Clone, Debug, Default)]
+pub struct AuditFilter {
+    /// Matches entries where the user is either the actor or the target.
+    pub user: Option<UserId>,
+    pub account_id: Option<i32>,
+    pub action: Option<AuditAction>,
+}
+
+impl BotState {
+    /// Records an action in the audit log and queues it for the log channel.
+    /// Failing to do so is only logged, so it never fails the action itself.
+    pub async fn audit(&self, event: AuditEvent) {
+        if let Err(e) = self.insert_audit_event(&event).await {
+            warn!("Failed to write to the audit log: {e} ({event:?})");
+        }
+
+        self.post_to_log_channel(&event);
+    }
+
+    async fn insert_audit_event(&self, event: &AuditEvent) -> Result<(), sqlx::Error> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let action = event.action.as_db();
+        let actor_id = event.actor.map(|x| x.get() as i64);
+        let target_id = event.target.map(|x| x.get() as i64);
+        let account_id = event.account_id.map(i64::from);
+        let guild_id = event.guild_id.map(|x| x.get() as i64);
+
+        sqlx::query!(
+            "INSERT INTO audit_log (created_at, action, actor_id, target_id, account_id, guild_id, details) VALUES (?, ?, ?, ?, ?, ?, ?)",
+            now,
+            action,
+            actor_id,
+            target_id,
+            account_id,
+            guild_id,
+            event.details
+        )
+        .execute(&self.database)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns one page of matching audit log entries, newest first, and how many entries match in total.
+    /// Pages start at 0.
+    pub async fn query_audit_log(
+        &self,
+        filter: &AuditFilter,
+        page: u32,
+        page_size: u32,
+    ) -> Result<(Vec<AuditLogEntry>, i64), sqlx::Error> {
+        let user = filter.user.map(|x| x.get() as i64);
+        let account_id = filter.account_id.map(i64::from);
+        let action = filter.action.map(AuditAction::as_db);
+        let limit = i64::from(page_size);
+        let offset = i64::from(page) * limit;
+
+        let entries = sqlx::query_as!(
+            AuditLogEntry,
+            "SELECT * FROM audit_log
+            WHERE (?1 IS NULL OR actor_id = ?1 OR target_id = ?1)
+            AND (?2 IS NULL OR account_id = ?2)
+            AND (?3 IS NULL OR action = ?3)
+            ORDER BY id DESC LIMIT ?4 OFFSET ?5",
+            user,
+            account_id,
+            action,
+            limit,
+            offset
+        )
+        .fetch_all(&self.database)
+        .await?;
+
+        let total = sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM audit_log
+            WHERE (?1 IS NULL OR actor_id = ?1 OR target_id = ?1)
+            AND (?2 IS NULL OR account_id = ?2)
+            AND (?3 IS NULL OR action = ?3)",
+            user,
+            account_id,
+            action
+        )
+        .fetch_one(&self.database)
+        .await?;
+
+        Ok((entries, total))
+    }
+}
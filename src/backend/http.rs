@@ -0,0 +1,203 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use log::{error, warn};
+use reqwest::StatusCode;
+
+use super::{GlobedBackend, RoleSyncRequestData, UserLookupResponse, UserRolesResponse};
+use crate::{
+    config::ServerConfig,
+    state::{LinkError, RoleSyncError},
+};
+
+/// Talks to a real central server over HTTP, using `/gsp/lookup`, `/gsp/sync_roles` and `/gsp/roles`.
+pub struct HttpBackend {
+    pub http_client: reqwest::Client,
+    pub base_url: String,
+    pub server_password: String,
+}
+
+impl HttpBackend {
+    pub fn new(config: &ServerConfig) -> reqwest::Result<Self> {
+        let http_client = reqwest::Client::builder()
+            .user_agent(format!(
+                "globed-game-server/discord-bot-{}",
+                env!("CARGO_PKG_VERSION")
+            ))
+            .timeout(config.request_timeout)
+            .build()?;
+
+        Ok(Self {
+            http_client,
+            base_url: config.base_url.clone(),
+            server_password: config.password.clone(),
+        })
+    }
+}
+
+// delay from the `Retry-After` header of a 429 response, only the delay in seconds form is supported, not an http date
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    response
+        .headers()
+        .get("Retry-After")
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.trim().parse().ok())
+        .map(Duration::from_secs)
+}
+
+#[async_trait]
+impl GlobedBackend for HttpBackend {
+    async fn lookup_user(
+        &self,
+        username: &str,
+        link_code: Option<u32>,
+    ) -> Result<UserLookupResponse, LinkError> {
+        let bypass_verification = link_code.is_none();
+
+        let mut url = format!(
+            "{}/gsp/lookup?username={}&link_code={}",
+            self.base_url,
+            username,
+            link_code.unwrap_or(0)
+        );
+
+        if bypass_verification {
+            url += "&bypass=true";
+        }
+
+        let response = match self
+            .http_client
+            .get(url)
+            .header("Authorization", &self.server_password)
+            .send()
+            .await
+        {
+            Ok(resp) => resp,
+            Err(e) => {
+                return Err(LinkError::ServerRequest(e));
+            }
+        };
+
+        let status = response.status();
+        if !status.is_success() {
+            if status == StatusCode::NOT_FOUND {
+                return Err(LinkError::UserNotFound);
+            }
+
+            let message = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "<no message>".to_owned());
+
+            return Err(LinkError::ServerInternalError(status, message));
+        }
+
+        let json = response.text().await.unwrap_or_default();
+        match serde_json::from_str(&json) {
+            Ok(x) => Ok(x),
+            Err(err) => Err(LinkError::ServerMalformedResponse(err, json)),
+        }
+    }
+
+    async fn sync_roles(&self, data: &RoleSyncRequestData) -> Result<(), RoleSyncError> {
+        let body: String = match serde_json::to_string(data) {
+            Ok(x) => x,
+            Err(err) => {
+                error!("This should never fail: {err}");
+
+                #[cfg(debug_assertions)]
+                unreachable!();
+                #[cfg(not(debug_assertions))]
+                return Err(RoleSyncError::InternalError(
+                    "internal error in serializing data",
+                ));
+            }
+        };
+
+        let response = match self
+            .http_client
+            .post(format!("{}/gsp/sync_roles", self.base_url))
+            .header("Authorization", &self.server_password)
+            .header("Content-Type", "application/json")
+            .body(body)
+            .send()
+            .await
+        {
+            Ok(resp) => resp,
+            Err(e) => {
+                return Err(RoleSyncError::ServerRequest(e));
+            }
+        };
+
+        let status = response.status();
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = retry_after(&response);
+
+            warn!("Role update was rate limited, retry after: {retry_after:?}");
+
+            return Err(RoleSyncError::RateLimited(retry_after));
+        }
+
+        if !status.is_success() {
+            let message = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "<no message>".to_owned());
+
+            warn!(
+                "Role update failed: code {}, message: {}",
+                status.as_u16(),
+                message
+            );
+
+            return Err(RoleSyncError::ServerUpdate((status, message)));
+        }
+
+        // success!
+        Ok(())
+    }
+
+    async fn get_roles(&self, account_id: i32) -> Result<Vec<String>, RoleSyncError> {
+        let response = match self
+            .http_client
+            .get(format!(
+                "{}/gsp/roles?account_id={account_id}",
+                self.base_url
+            ))
+            .header("Authorization", &self.server_password)
+            .send()
+            .await
+        {
+            Ok(resp) => resp,
+            Err(e) => {
+                return Err(RoleSyncError::ServerRequest(e));
+            }
+        };
+
+        let status = response.status();
+
+        // the server doesn't know accounts that never had any roles
+        if status == StatusCode::NOT_FOUND {
+            return Ok(Vec::new());
+        }
+
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            return Err(RoleSyncError::RateLimited(retry_after(&response)));
+        }
+
+        if !status.is_success() {
+            let message = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "<no message>".to_owned());
+
+            return Err(RoleSyncError::ServerUpdate((status, message)));
+        }
+
+        let json = response.text().await.unwrap_or_default();
+        match serde_json::from_str::<UserRolesResponse>(&json) {
+            Ok(x) => Ok(x.roles),
+            Err(err) => Err(RoleSyncError::ServerMalformedResponse(err, json)),
+        }
+    }
+}
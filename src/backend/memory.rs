@@ -0,0 +1,153 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+
+use async_trait::async_trait;
+use parking_lot::Mutex as SyncMutex;
+use reqwest::StatusCode;
+
+use super::{GlobedBackend, RoleSyncRequestData, UserLookupResponse};
+use crate::state::{LinkError, RoleSyncError};
+
+struct MemoryUser {
+    account_id: i32,
+    name: String,
+    link_code: u32,
+}
+
+/// In-memory stand-in for the central server, for exercising link and sync logic without a live server.
+/// Records every sync request it receives and applies it to its own role table.
+#[derive(Default)]
+pub struct MemoryBackend {
+    users: SyncMutex<Vec<MemoryUser>>,
+    roles: SyncMutex<HashMap<i32, BTreeSet<String>>>,
+    sync_requests: SyncMutex<Vec<RoleSyncRequestData>>,
+    sync_failures: SyncMutex<VecDeque<RoleSyncError>>,
+    lookup_failures: SyncMutex<VecDeque<LinkError>>,
+    rejected_roles: SyncMutex<Vec<String>>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a GD account that can be linked with the given link code.
+    pub fn add_user(&self, account_id: i32, name: &str, link_code: u32) {
+        self.users.lock().push(MemoryUser {
+            account_id,
+            name: name.to_owned(),
+            link_code,
+        });
+    }
+
+    /// Makes the next sync request fail with the given status, can be called multiple times to fail several requests.
+    pub fn fail_next_sync(&self, status: StatusCode, message: &str) {
+        self.fail_next_sync_with(RoleSyncError::ServerUpdate((status, message.to_owned())));
+    }
+
+    /// Makes the next sync request fail with the given error.
+    pub fn fail_next_sync_with(&self, error: RoleSyncError) {
+        self.sync_failures.lock().push_back(error);
+    }
+
+    /// Makes the next user lookup fail with the given error.
+    pub fn fail_next_lookup(&self, error: LinkError) {
+        self.lookup_failures.lock().push_back(error);
+    }
+
+    /// Makes every sync request that mentions this role fail with 400, like the server does for unknown roles.
+    pub fn reject_role(&self, role: &str) {
+        self.rejected_roles.lock().push(role.to_owned());
+    }
+
+    /// Returns all successful sync requests received so far.
+    pub fn sync_requests(&self) -> Vec<RoleSyncRequestData> {
+        self.sync_requests.lock().clone()
+    }
+
+    /// Returns and clears all successful sync requests received so far.
+    pub fn take_sync_requests(&self) -> Vec<RoleSyncRequestData> {
+        std::mem::take(&mut *self.sync_requests.lock())
+    }
+
+    /// Replaces the roles of an account, like staff changing them on the server would.
+    pub fn set_account_roles(&self, account_id: i32, roles: &[&str]) {
+        self.roles
+            .lock()
+            .insert(account_id, roles.iter().map(|x| (*x).to_owned()).collect());
+    }
+
+    /// Returns the roles an account currently has, sorted.
+    pub fn account_roles(&self, account_id: i32) -> Vec<String> {
+        self.roles
+            .lock()
+            .get(&account_id)
+            .map(|roles| roles.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl GlobedBackend for MemoryBackend {
+    async fn lookup_user(
+        &self,
+        username: &str,
+        link_code: Option<u32>,
+    ) -> Result<UserLookupResponse, LinkError> {
+        if let Some(failure) = self.lookup_failures.lock().pop_front() {
+            return Err(failure);
+        }
+
+        self.users
+            .lock()
+            .iter()
+            .find(|user| {
+                user.name.eq_ignore_ascii_case(username)
+                    && link_code.is_none_or(|code| code == user.link_code)
+            })
+            .map(|user| UserLookupResponse {
+                account_id: user.account_id,
+                name: user.name.clone(),
+            })
+            .ok_or(LinkError::UserNotFound)
+    }
+
+    async fn sync_roles(&self, data: &RoleSyncRequestData) -> Result<(), RoleSyncError> {
+        if let Some(failure) = self.sync_failures.lock().pop_front() {
+            return Err(failure);
+        }
+
+        let rejected = self.rejected_roles.lock();
+        if let Some(role) = data
+            .users
+            .iter()
+            .flat_map(|user| user.keep.iter().chain(user.remove.iter()))
+            .find(|role| rejected.contains(role))
+        {
+            return Err(RoleSyncError::ServerUpdate((
+                StatusCode::BAD_REQUEST,
+                format!("unknown role: {role}"),
+            )));
+        }
+
+        drop(rejected);
+
+        let mut roles = self.roles.lock();
+        for user in &data.users {
+            let roles = roles.entry(user.account_id).or_default();
+
+            for role in &user.remove {
+                roles.remove(role);
+            }
+
+            roles.extend(user.keep.iter().cloned());
+        }
+
+        self.sync_requests.lock().push(data.clone());
+
+        Ok(())
+    }
+
+    async fn get_roles(&self, account_id: i32) -> Result<Vec<String>, RoleSyncError> {
+        Ok(self.account_roles(account_id))
+    }
+}
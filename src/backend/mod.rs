@@ -0,0 +1,52 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::state::{LinkError, RoleSyncError};
+
+mod http;
+mod memory;
+
+pub use http::HttpBackend;
+pub use memory::MemoryBackend;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoleSyncRequest {
+    pub account_id: i32,
+    pub keep: Vec<String>,
+    pub remove: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoleSyncRequestData {
+    pub users: Vec<RoleSyncRequest>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserLookupResponse {
+    pub account_id: i32,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserRolesResponse {
+    pub account_id: i32,
+    pub roles: Vec<String>,
+}
+
+/// The protocol the bot uses to talk to the Globed central server.
+#[async_trait]
+pub trait GlobedBackend: Send + Sync {
+    /// Looks up a GD account by username, verifying it with the link code.
+    /// If `link_code` is `None`, verification is bypassed.
+    async fn lookup_user(
+        &self,
+        username: &str,
+        link_code: Option<u32>,
+    ) -> Result<UserLookupResponse, LinkError>;
+
+    /// Updates the roles of all users in the request.
+    async fn sync_roles(&self, data: &RoleSyncRequestData) -> Result<(), RoleSyncError>;
+
+    /// Fetches the roles an account currently has on the server, including ones granted in-game.
+    async fn get_roles(&self, account_id: i32) -> Result<Vec<String>, RoleSyncError>;
+}
@@ -0,0 +1,176 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::Arc,
+};
+
+use log::{error, info, warn};
+use tokio::task::JoinHandle;
+
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    db::{LinkedUser, Role},
+    serenity::{GuildId, Member},
+    state::{BotState, RoleSyncError, RoleSyncRequestData},
+};
+
+impl BotState {
+    /// Syncs roles after a change on Discord. If batching is enabled, the member is only marked as pending,
+    /// and synced together with other changed members once the batch window passes.
+    pub async fn request_auto_sync(&self, member: &Member) -> Result<(), RoleSyncError> {
+        if self.config.sync.batch_window.is_zero() {
+            return self.auto_sync_roles(member).await;
+        }
+
+        let mut pending = self.pending_syncs.lock();
+        let was_empty = pending.is_empty();
+
+        // a newer update of the same member replaces the older one
+        pending.insert((member.guild_id, member.user.id), member.clone());
+
+        if was_empty {
+            self.pending_sync_notify.notify_one();
+        }
+
+        Ok(())
+    }
+
+    pub fn pending_sync_count(&self) -> usize {
+        self.pending_syncs.lock().len()
+    }
+
+    /// Syncs all pending members in a single request, returns how many linked users were synced.
+    /// If the server rejects the batch, members are retried one by one so that one bad request doesn't fail everyone.
+    pub async fn flush_pending_syncs(&self) -> Result<usize, RoleSyncError> {
+        let mut pending: Vec<Member> = std::mem::take(&mut *self.pending_syncs.lock())
+            .into_values()
+            .collect();
+
+        if pending.is_empty() {
+            return Ok(0);
+        }
+
+        pending.sort_by_key(|m| (m.guild_id, m.user.id));
+
+        let data = match self.make_batch_request(&pending).await {
+            Ok(x) => x,
+            Err(e) => {
+                // nothing was sent yet, try everyone again with the next batch
+                self.requeue_pending_syncs(pending);
+                return Err(e);
+            }
+        };
+
+        if data.users.is_empty() {
+            return Ok(0);
+        }
+
+        let err = match self.send_or_queue_sync_roles_req(&data).await {
+            Ok(()) => return Ok(data.users.len()),
+            Err(e) if data.users.len() == 1 => return Err(e),
+            Err(e) => e,
+        };
+
+        warn!("Batched sync was rejected, retrying users one by one: {err}");
+
+        let mut synced = 0;
+
+        for user in data.users {
+            let account_id = user.account_id;
+
+            match self
+                .send_or_queue_sync_roles_req(&RoleSyncRequestData { users: vec![user] })
+                .await
+            {
+                Ok(()) => synced += 1,
+                Err(e) => {
+                    error!("Failed to sync roles of {account_id} from a batch: {e}");
+
+                    self.audit(
+                        AuditEvent::new(AuditAction::SyncFailed)
+                            .account(account_id)
+                            .details(format!("batched sync rejected: {e}")),
+                    )
+                    .await;
+                }
+            }
+        }
+
+        Ok(synced)
+    }
+
+    // builds one request for all linked members of a batch
+    async fn make_batch_request(
+        &self,
+        members: &[Member],
+    ) -> Result<RoleSyncRequestData, RoleSyncError> {
+        let mut guild_roles: HashMap<GuildId, Vec<Role>> = HashMap::new();
+        let mut data = RoleSyncRequestData::default();
+
+        for member in members {
+            let Some(account_id) = self.get_linked_gd_account(member.user.id).await? else {
+                continue;
+            };
+
+            let roles = match guild_roles.entry(member.guild_id) {
+                Entry::Occupied(e) => e.into_mut(),
+                Entry::Vacant(e) => e.insert(self.get_guild_roles(member.guild_id).await?),
+            };
+
+            let linked_user = LinkedUser {
+                id: member.user.id.get() as i64,
+                gd_account_id: account_id.get() as i64,
+            };
+
+            let mut req = self.make_role_sync_request_with(member, &linked_user, roles);
+            self.apply_guild_grants(member.guild_id, &mut req).await?;
+
+            data.users.push(req);
+        }
+
+        Ok(data)
+    }
+
+    // puts members back into the next batch, unless they were updated again in the meantime
+    fn requeue_pending_syncs(&self, members: Vec<Member>) {
+        let mut pending = self.pending_syncs.lock();
+
+        for member in members {
+            pending
+                .entry((member.guild_id, member.user.id))
+                .or_insert(member);
+        }
+
+        self.pending_sync_notify.notify_one();
+    }
+}
+
+/// Starts the background task that sends batched syncs, does nothing if batching is disabled.
+pub fn spawn_sync_batcher(state: Arc<BotState>) -> Option<JoinHandle<()>> {
+    let window = state.config.sync.batch_window;
+    if window.is_zero() {
+        return None;
+    }
+
+    Some(tokio::spawn(async move {
+        loop {
+            // wait for the first change, then give others some time to pile up
+            state.pending_sync_notify.notified().await;
+            tokio::time::sleep(window).await;
+
+            match state.flush_pending_syncs().await {
+                Ok(0) => {}
+                Ok(count) => info!("Synced roles of {count} changed members"),
+                Err(e) => {
+                    warn!("Failed to sync roles of changed members: {e}");
+
+                    state
+                        .audit(
+                            AuditEvent::new(AuditAction::SyncFailed)
+                                .details(format!("batched sync of changed members: {e}")),
+                        )
+                        .await;
+                }
+            }
+        }
+    }))
+}
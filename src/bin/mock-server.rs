@@ -0,0 +1,73 @@
+//! Local mock of the Globed central server, for running the bot without the real game server.
+//!
+//! Configured with environment variables:
+//! * `MOCK_ADDR` - address to listen on, defaults to `127.0.0.1:4201`
+//! * `MOCK_PASSWORD` - expected `Authorization` header, defaults to `password`
+//! * `MOCK_USERS` - linkable accounts, comma separated `account_id:name:link_code` entries
+//! * `MOCK_LOOKUP_STATUS`, `MOCK_SYNC_STATUS` - if set, every lookup/sync request fails with this status code
+
+use std::env;
+
+use auto_role_bot::{logger::*, mock_server::MockServer};
+use axum::http::StatusCode;
+
+fn parse_status(var: &str) -> Option<StatusCode> {
+    let value = env::var(var).ok()?;
+
+    match value
+        .parse::<u16>()
+        .ok()
+        .and_then(|x| StatusCode::from_u16(x).ok())
+    {
+        Some(status) => Some(status),
+        None => {
+            error!("{var}: invalid status code '{value}'");
+            std::process::exit(1);
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    // log as the library, so that messages from the server itself are not filtered out
+    log::set_logger(Logger::instance("auto_role_bot", false)).unwrap();
+    log::set_max_level(LogLevelFilter::Info);
+
+    let addr = env::var("MOCK_ADDR").unwrap_or_else(|_| "127.0.0.1:4201".to_owned());
+    let password = env::var("MOCK_PASSWORD").unwrap_or_else(|_| "password".to_owned());
+
+    let mut server = match MockServer::start(&addr, &password).await {
+        Ok(x) => x,
+        Err(e) => {
+            error!("Failed to start the mock server on {addr}: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    for entry in env::var("MOCK_USERS")
+        .unwrap_or_default()
+        .split(',')
+        .filter(|x| !x.is_empty())
+    {
+        let parts: Vec<&str> = entry.split(':').collect();
+
+        match parts[..] {
+            [id, name, code] => match (id.parse(), code.parse()) {
+                (Ok(id), Ok(code)) => server.add_user(id, name, code),
+                _ => {
+                    error!("MOCK_USERS: invalid entry '{entry}'");
+                    std::process::exit(1);
+                }
+            },
+            _ => {
+                error!("MOCK_USERS: invalid entry '{entry}', expected 'account_id:name:link_code'");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    server.set_lookup_status(parse_status("MOCK_LOOKUP_STATUS"));
+    server.set_sync_status(parse_status("MOCK_SYNC_STATUS"));
+
+    server.wait().await;
+}
@@ -0,0 +1,178 @@
+//! GD accounts that can't be linked, stored in the `blocked_accounts` table.
+//! Accounts are blocked by admins with `/admin blocklist`, or when a linked member gets banned.
+
+use std::time::Duration;
+
+use time::OffsetDateTime;
+
+use crate::{
+    db::{BlockedAccount, UnlinkReason},
+    serenity::{GuildId, UserId},
+    state::{BotState, RoleSyncError},
+};
+
+/// Members who left and got banned soon after still get their account blocked, as long as the link ended this recently.
+const BAN_AFTER_LEAVE_WINDOW: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Why and by whom an account is blocked.
+#[derive(Clone, Debug, Default)]
+pub struct BlockSource {
+    pub blocked_by: Option<UserId>,
+    /// User whose ban caused the block, it is lifted again when they are unbanned.
+    pub banned_user: Option<UserId>,
+    pub reason: Option<String>,
+}
+
+/// What a ban did to the banned user's link.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BanOutcome {
+    /// Nothing to block: never linked, the link ended long ago or not by leaving, or the account is now someone else's.
+    Ignored,
+    /// Still in another configured guild, so the link stays and only the roles of the banning guild were removed.
+    RolesStripped(i32),
+    /// The account was blocked, and unlinked if the link was still active.
+    Blocked { account_id: i32, unlinked: bool },
+}
+
+impl BotState {
+    pub async fn is_account_blocked(&self, account_id: i32) -> Result<bool, sqlx::Error> {
+        let row = sqlx::query!(
+            "SELECT account_id FROM blocked_accounts WHERE account_id = ?",
+            account_id
+        )
+        .fetch_optional(&self.database)
+        .await?;
+
+        Ok(row.is_some())
+    }
+
+    /// Blocks an account from being linked, returns `false` if it already was.
+    pub async fn block_account(
+        &self,
+        account_id: i32,
+        source: &BlockSource,
+    ) -> Result<bool, sqlx::Error> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let blocked_by = source.blocked_by.map(|x| x.get() as i64);
+        let banned_user_id = source.banned_user.map(|x| x.get() as i64);
+
+        let result = sqlx::query!(
+            "INSERT OR IGNORE INTO blocked_accounts (account_id, blocked_at, blocked_by, banned_user_id, reason) VALUES (?, ?, ?, ?, ?)",
+            account_id,
+            now,
+            blocked_by,
+            banned_user_id,
+            source.reason
+        )
+        .execute(&self.database)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Lets an account be linked again, returns `false` if it wasn't blocked.
+    pub async fn unblock_account(&self, account_id: i32) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM blocked_accounts WHERE account_id = ?",
+            account_id
+        )
+        .execute(&self.database)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// All blocked accounts, most recently blocked first.
+    pub async fn get_blocked_accounts(&self) -> Result<Vec<BlockedAccount>, sqlx::Error> {
+        sqlx::query_as!(
+            BlockedAccount,
+            "SELECT account_id, blocked_at, blocked_by, banned_user_id, reason FROM blocked_accounts ORDER BY blocked_at DESC, account_id"
+        )
+        .fetch_all(&self.database)
+        .await
+    }
+
+    /// Unlinks a user banned in `guild_id` and blocks the GD account they are linked to.
+    /// A link that already ended only counts if the user left the guild recently, see [`BanOutcome`].
+    /// The ban only applies to the banning guild if the user is still in another configured guild (`in_other_guild`),
+    /// just like leaving one of several guilds.
+    pub async fn ban_user(
+        &self,
+        user_id: UserId,
+        guild_id: GuildId,
+        in_other_guild: bool,
+        reason: Option<String>,
+    ) -> Result<BanOutcome, RoleSyncError> {
+        let Some(last_link) = self
+            .get_user_link_history(user_id)
+            .await?
+            .into_iter()
+            .next()
+        else {
+            return Ok(BanOutcome::Ignored);
+        };
+
+        let account_id = last_link.gd_account_id as i32;
+
+        if last_link.is_active() && in_other_guild {
+            self.strip_guild_roles(user_id, guild_id).await?;
+            return Ok(BanOutcome::RolesStripped(account_id));
+        }
+
+        if !last_link.is_active() {
+            let cutoff = OffsetDateTime::now_utc().unix_timestamp()
+                - BAN_AFTER_LEAVE_WINDOW.as_secs() as i64;
+
+            let left_recently = last_link.reason() == Some(UnlinkReason::LeftGuild)
+                && last_link.unlinked_at.is_some_and(|at| at >= cutoff);
+
+            // someone else linked the account since, they must not lose it over this ban
+            let linked_to_other = self.get_linked_discord_account(account_id).await?.is_some();
+
+            if !left_recently || linked_to_other || in_other_guild {
+                return Ok(BanOutcome::Ignored);
+            }
+        }
+
+        self.block_account(
+            account_id,
+            &BlockSource {
+                blocked_by: None,
+                banned_user: Some(user_id),
+                reason,
+            },
+        )
+        .await?;
+
+        // the member removal that comes with a ban may have unlinked them in the meantime
+        let unlinked = if last_link.is_active() {
+            match self.unlink_user(user_id, UnlinkReason::Banned).await {
+                Ok(_) => true,
+                Err(RoleSyncError::NotLinked) => false,
+                Err(e) => return Err(e),
+            }
+        } else {
+            false
+        };
+
+        Ok(BanOutcome::Blocked {
+            account_id,
+            unlinked,
+        })
+    }
+
+    /// Lifts the blocks caused by a user's ban, returns the accounts that were unblocked.
+    /// Blocks added by admins stay.
+    pub async fn unban_user(&self, user_id: UserId) -> Result<Vec<i32>, sqlx::Error> {
+        let user_id = user_id.get() as i64;
+
+        let rows = sqlx::query!(
+            "DELETE FROM blocked_accounts WHERE banned_user_id = ? RETURNING account_id",
+            user_id
+        )
+        .fetch_all(&self.database)
+        .await?;
+
+        Ok(rows.into_iter().map(|x| x.account_id as i32).collect())
+    }
+}
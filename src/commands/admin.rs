@@ -1,8 +1,32 @@
-use crate::state::LinkError;
+use std::time::{Duration, Instant};
+
+use poise::{ChoiceParameter, CreateReply};
+use tokio::sync::{watch, MutexGuard};
+
+use crate::{
+    audit::AuditFilter,
+    db::{AuditLogEntry, ContestedAccount, LinkConflict, LinkHistoryEntry},
+    state::{LinkError, SyncAllProgress, SyncAllSummary, TransferError},
+};
 
 use super::prelude::*;
 
-#[poise::command(slash_command, subcommands("link", "unlink", "sync", "syncall"))]
+#[poise::command(
+    slash_command,
+    subcommands(
+        "link",
+        "unlink",
+        "transfer",
+        "history",
+        "super::blocklist::blocklist",
+        "unlock",
+        "conflicts",
+        "super::panel::panel",
+        "sync",
+        "syncall",
+        "audit"
+    )
+)]
 pub async fn admin(_ctx: Context<'_>) -> Result<(), CommandError> {
     // unreachable
     Ok(())
@@ -24,11 +48,18 @@ pub async fn link(
 
     ctx.defer().await?;
 
-    match state
-        .add_linked_user(&ctx, member.user.id, account_id)
-        .await
-    {
+    match state.add_linked_user(member.user.id, account_id).await {
         Ok(()) => {
+            state
+                .audit(
+                    AuditEvent::new(AuditAction::AdminLink)
+                        .actor(ctx.author().id)
+                        .target(member.user.id)
+                        .account(account_id)
+                        .guild(ctx.guild_id()),
+                )
+                .await;
+
             ctx.reply("✅ Successfully linked this person.").await?;
 
             Ok(())
@@ -46,6 +77,13 @@ pub async fn link(
             Ok(())
         }
 
+        // admin links bypass verification, which is what gets throttled, so this shouldn't happen
+        Err(LinkError::TooManyAttempts(_)) => {
+            ctx.reply(":x: Too many failed link attempts, try again later.")
+                .await?;
+            Ok(())
+        }
+
         Err(LinkError::ServerRequest(err)) => {
             ctx.reply(":x: Failed to make a request to the server!")
                 .await?;
@@ -93,7 +131,8 @@ pub async fn link(
             Ok(())
         }
 
-        Err(LinkError::LinkedToOther(ident)) => {
+        Err(LinkError::LinkedToOther(linked_id)) => {
+            let ident = user_ident(&ctx, linked_id).await;
             ctx.reply(format!(
                 ":x: This Geometry Dash account is already linked to another Discord account ({}).",
                 ident
@@ -102,6 +141,13 @@ pub async fn link(
 
             Ok(())
         }
+
+        Err(LinkError::AccountBlocked) => {
+            ctx.reply(":x: This Geometry Dash account is on the blocklist. Remove it with `/admin blocklist remove` first.")
+                .await?;
+
+            Ok(())
+        }
     }
 }
 
@@ -120,8 +166,20 @@ pub async fn unlink(
 
     ctx.defer().await?;
 
-    match state.unlink_user(user.id).await {
+    let account_id = state.get_linked_gd_account(user.id).await.ok().flatten();
+
+    match state.unlink_user(user.id, UnlinkReason::Admin).await {
         Ok(()) => {
+            state
+                .audit(
+                    AuditEvent::new(AuditAction::AdminUnlink)
+                        .actor(ctx.author().id)
+                        .target(user.id)
+                        .account(account_id.map(|x| x.get()))
+                        .guild(ctx.guild_id()),
+                )
+                .await;
+
             ctx.reply("Successfully unlinked the user's account!")
                 .await?;
         }
@@ -139,11 +197,312 @@ pub async fn unlink(
     Ok(())
 }
 
+/// Move a GD account link to another user, for example after they lost their old Discord account
+#[poise::command(slash_command)]
+pub async fn transfer(
+    ctx: Context<'_>,
+    #[description = "User that is linked now, can be someone who left"] from: serenity::User,
+    #[description = "User to link instead"] mut to: serenity::Member,
+) -> Result<(), CommandError> {
+    let state = ctx.data();
+
+    if !has_manage_roles_perm(&ctx).await {
+        ctx.reply(":x: No permission").await?;
+        return Ok(());
+    }
+
+    ctx.defer().await?;
+
+    let account_id = match state.transfer_link(from.id, to.user.id).await {
+        Ok(x) => x,
+
+        Err(TransferError::NotLinked) => {
+            ctx.reply(":x: The user to transfer from is not linked to a GD account.")
+                .await?;
+            return Ok(());
+        }
+
+        Err(TransferError::AlreadyLinked) => {
+            ctx.reply(
+                ":x: The user to transfer to is already linked. Use `/admin unlink` on them first.",
+            )
+            .await?;
+            return Ok(());
+        }
+
+        Err(TransferError::AccountBlocked) => {
+            ctx.reply(":x: This Geometry Dash account is on the blocklist. Remove it with `/admin blocklist remove` first.")
+                .await?;
+            return Ok(());
+        }
+
+        Err(TransferError::SameUser) => {
+            ctx.reply(":x: Pick two different users.").await?;
+            return Ok(());
+        }
+
+        Err(TransferError::Database(err)) => {
+            ctx.reply(":x: Unknown database error has occurred.")
+                .await?;
+            bail!("database connection error: {err}");
+        }
+    };
+
+    state
+        .audit(
+            AuditEvent::new(AuditAction::Transfer)
+                .actor(ctx.author().id)
+                .target(to.user.id)
+                .account(account_id)
+                .guild(ctx.guild_id())
+                .details(format!("from <@{}>", from.id)),
+        )
+        .await;
+
+    // the roles now come from the new member, nothing was stripped in between
+    let message = match state.pull_and_sync_roles(ctx.http(), &mut to).await {
+        Ok((roles, pulled)) => {
+            format!(
+                "✅ Moved GD account {account_id} from <@{}> to <@{}>.\n\n* Synced roles: {}",
+                from.id,
+                to.user.id,
+                roles.join(", ")
+            ) + &pulled_roles_message(&pulled)
+        }
+        Err(e) => {
+            warn!("Failed to sync roles after a transfer: {e}");
+
+            format!(
+                "Moved GD account {account_id} from <@{}> to <@{}>, but role syncing failed. Try `/admin sync` on them.",
+                from.id, to.user.id
+            )
+        }
+    };
+
+    ctx.send(
+        CreateReply::default()
+            .content(message)
+            .allowed_mentions(serenity::CreateAllowedMentions::new()),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Show every GD account a user was linked to, or every user a GD account was linked to
+#[poise::command(slash_command)]
+pub async fn history(
+    ctx: Context<'_>,
+    #[description = "User to show the links of"] user: Option<serenity::User>,
+    #[description = "GD account ID to show the links of"] account_id: Option<i32>,
+) -> Result<(), CommandError> {
+    let state = ctx.data();
+
+    if !has_manage_roles_perm(&ctx).await {
+        ctx.reply(":x: No permission").await?;
+        return Ok(());
+    }
+
+    let result = match (&user, account_id) {
+        (Some(user), None) => state.get_user_link_history(user.id).await,
+        (None, Some(account_id)) => state.get_account_link_history(account_id).await,
+        _ => {
+            ctx.reply(":x: Pick either a user or an account ID.")
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let entries = match result {
+        Ok(x) => x,
+        Err(e) => {
+            ctx.reply(format!(":x: Failed to read the link history: {e}"))
+                .await?;
+            bail!("Failed to read the link history: {e}");
+        }
+    };
+
+    let message = if entries.is_empty() {
+        "Never linked.".to_owned()
+    } else {
+        entries
+            .iter()
+            .take(LINK_HISTORY_LIMIT)
+            .map(format_link_history_entry)
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    ctx.send(
+        CreateReply::default()
+            .content(message)
+            .allowed_mentions(serenity::CreateAllowedMentions::new()),
+    )
+    .await?;
+
+    Ok(())
+}
+
+const LINK_HISTORY_LIMIT: usize = 20;
+
+fn format_link_history_entry(entry: &LinkHistoryEntry) -> String {
+    let mut line = format!(
+        "* <@{}> ↔ account {}, linked <t:{}:f>",
+        entry.id, entry.gd_account_id, entry.linked_at
+    );
+
+    match (entry.unlinked_at, entry.reason()) {
+        (None, _) => line += " **(active)**",
+        (Some(at), Some(reason)) => line += &format!(", {reason} <t:{at}:f>"),
+        (Some(at), None) => line += &format!(", unlinked <t:{at}:f>"),
+    }
+
+    line
+}
+
+/// Clear the link lockout of a user or GD username after too many failed attempts
+#[poise::command(slash_command)]
+pub async fn unlock(
+    ctx: Context<'_>,
+    #[description = "User to unlock"] user: Option<serenity::User>,
+    #[description = "GD username to unlock"] username: Option<String>,
+) -> Result<(), CommandError> {
+    let state = ctx.data();
+
+    if !has_manage_roles_perm(&ctx).await {
+        ctx.reply(":x: No permission").await?;
+        return Ok(());
+    }
+
+    if user.is_none() && username.is_none() {
+        ctx.reply(":x: Pick a user, a username or both.").await?;
+        return Ok(());
+    }
+
+    let mut cleared = Vec::new();
+
+    if let Some(user) = &user {
+        match state.clear_user_link_attempts(user.id).await {
+            Ok(true) => cleared.push(format!("<@{}>", user.id)),
+            Ok(false) => {}
+            Err(e) => {
+                ctx.reply(format!(":x: Failed to clear the lockout: {e}"))
+                    .await?;
+                bail!("Failed to clear link attempts: {e}");
+            }
+        }
+    }
+
+    if let Some(username) = &username {
+        match state.clear_username_link_attempts(username).await {
+            Ok(true) => cleared.push(format!("`{username}`")),
+            Ok(false) => {}
+            Err(e) => {
+                ctx.reply(format!(":x: Failed to clear the lockout: {e}"))
+                    .await?;
+                bail!("Failed to clear link attempts: {e}");
+            }
+        }
+    }
+
+    if cleared.is_empty() {
+        ctx.reply(":x: No failed link attempts to clear.").await?;
+        return Ok(());
+    }
+
+    let cleared = cleared.join(" and ");
+
+    let mut event = AuditEvent::new(AuditAction::LinkUnlock)
+        .actor(ctx.author().id)
+        .guild(ctx.guild_id())
+        .details(format!("cleared failed link attempts of {cleared}"));
+
+    if let Some(user) = &user {
+        event = event.target(user.id);
+    }
+
+    state.audit(event).await;
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!("✅ Cleared failed link attempts of {cleared}."))
+            .allowed_mentions(serenity::CreateAllowedMentions::new()),
+    )
+    .await?;
+
+    Ok(())
+}
+
+const CONFLICTS_LIMIT: u32 = 15;
+
+/// Show GD accounts that others tried to link while already linked, or the attempts for one account
+#[poise::command(slash_command)]
+pub async fn conflicts(
+    ctx: Context<'_>,
+    #[description = "Show the attempts for this GD account ID"] account_id: Option<i32>,
+) -> Result<(), CommandError> {
+    let state = ctx.data();
+
+    if !has_manage_roles_perm(&ctx).await {
+        ctx.reply(":x: No permission").await?;
+        return Ok(());
+    }
+
+    let result = match account_id {
+        Some(account_id) => state
+            .get_link_conflicts(account_id, CONFLICTS_LIMIT)
+            .await
+            .map(|x| x.iter().map(format_link_conflict).collect::<Vec<_>>()),
+        None => state
+            .get_contested_accounts(CONFLICTS_LIMIT)
+            .await
+            .map(|x| x.iter().map(format_contested_account).collect::<Vec<_>>()),
+    };
+
+    let lines = match result {
+        Ok(x) => x,
+        Err(e) => {
+            ctx.reply(format!(":x: Failed to read link conflicts: {e}"))
+                .await?;
+            bail!("Failed to read link conflicts: {e}");
+        }
+    };
+
+    let message = if lines.is_empty() {
+        "No link conflicts recorded.".to_owned()
+    } else {
+        lines.join("\n")
+    };
+
+    ctx.send(
+        CreateReply::default()
+            .content(message)
+            .allowed_mentions(serenity::CreateAllowedMentions::new()),
+    )
+    .await?;
+
+    Ok(())
+}
+
+fn format_link_conflict(conflict: &LinkConflict) -> String {
+    format!(
+        "* <t:{}:f> <@{}> tried to link account {}, linked to <@{}>",
+        conflict.created_at, conflict.claimant_id, conflict.account_id, conflict.owner_id
+    )
+}
+
+fn format_contested_account(account: &ContestedAccount) -> String {
+    format!(
+        "* account {}: {} attempts by {} users, last <t:{}:R>",
+        account.account_id, account.attempts, account.claimants, account.last_attempt_at
+    )
+}
+
 /// Sync another user's roles to their GD account on Globed
 #[poise::command(slash_command)]
 pub async fn sync(
     ctx: Context<'_>,
-    #[description = "User to sync"] user: serenity::Member,
+    #[description = "User to sync"] mut user: serenity::Member,
 ) -> Result<(), CommandError> {
     let state = ctx.data();
 
@@ -154,11 +513,12 @@ pub async fn sync(
 
     ctx.defer().await?;
 
-    match state.sync_roles(&user).await {
-        Ok(roles) => {
+    match state.pull_and_sync_roles(ctx.http(), &mut user).await {
+        Ok((roles, pulled)) => {
             let message =
                 format!("✅ Successfully synced @{}'s roles! If they were already online on Globed, they might need to reconnect to the server to see the changes.\n\n", user.user.name)
-                + "* Synced roles: " + &roles.join(", ");
+                + "* Synced roles: " + &roles.join(", ")
+                + &pulled_roles_message(&pulled);
 
             ctx.reply(message).await?;
         }
@@ -180,7 +540,11 @@ pub async fn sync(
 
 /// Sync roles of all linked users on this server
 #[poise::command(slash_command)]
-pub async fn syncall(ctx: Context<'_>) -> Result<(), CommandError> {
+pub async fn syncall(
+    ctx: Context<'_>,
+    #[description = "Also sync members whose roles didn't change since the last sync"]
+    force: Option<bool>,
+) -> Result<(), CommandError> {
     let state = ctx.data();
 
     if !has_manage_roles_perm(&ctx).await {
@@ -188,21 +552,236 @@ pub async fn syncall(ctx: Context<'_>) -> Result<(), CommandError> {
         return Ok(());
     }
 
+    let guild_id = ctx.guild_id().ok_or(CommandError::PrivateMessages)?;
+
+    let _guard = match begin_syncall(state) {
+        Ok(guard) => guard,
+        Err(busy) => {
+            ctx.reply(busy).await?;
+            return Ok(());
+        }
+    };
+
     ctx.defer().await?;
 
-    match state.sync_all_members(ctx.http()).await {
-        Ok(count) => {
-            ctx.reply(format!("✅ Successfully synced roles of {count} people!"))
-                .await?;
+    let started = Instant::now();
+    let (progress_tx, mut progress_rx) = watch::channel(SyncAllProgress::default());
+
+    let handle = ctx
+        .reply(progress_message(&SyncAllProgress::default()))
+        .await?;
+
+    let sync = state.sync_all_members_with_progress(
+        ctx.http(),
+        guild_id,
+        force.unwrap_or(false),
+        &progress_tx,
+    );
+    tokio::pin!(sync);
+
+    // edit the reply every few seconds while the sync runs, to not hit discord rate limits
+    let mut ticker = tokio::time::interval(PROGRESS_EDIT_INTERVAL);
+    ticker.tick().await;
+
+    let result = loop {
+        tokio::select! {
+            result = &mut sync => break result,
+            _ = ticker.tick() => {
+                if !progress_rx.has_changed().unwrap_or(false) {
+                    continue;
+                }
+
+                let message = progress_message(&progress_rx.borrow_and_update());
+                if let Err(e) = handle.edit(ctx, CreateReply::default().content(message)).await {
+                    warn!("Failed to update syncall progress: {e}");
+                }
+            }
         }
+    };
 
-        Err(e) => {
-            ctx.reply(format!(":x: Error while syncing members: {e}"))
-                .await?;
+    let message = syncall_reply(&result, started.elapsed());
+
+    handle
+        .edit(ctx, CreateReply::default().content(message))
+        .await?;
+
+    if let Err(e) = result {
+        bail!("Error syncing all members: {e}");
+    }
 
-            bail!("Error syncing all members: {e}");
+    Ok(())
+}
+
+/// Takes the lock for syncing all members, or returns the reply for when another sync is already running.
+pub fn begin_syncall(state: &BotState) -> Result<MutexGuard<'_, ()>, &'static str> {
+    state
+        .try_lock_mass_sync()
+        .ok_or(":x: A sync of all members is already running, try again later.")
+}
+
+/// Final reply of `/admin syncall`.
+pub fn syncall_reply(result: &Result<SyncAllSummary, RoleSyncError>, elapsed: Duration) -> String {
+    let elapsed = format!("{:.1}s", elapsed.as_secs_f32());
+
+    match result {
+        Ok(summary) if summary.is_complete() => format!(
+            "✅ Successfully synced roles of {} people, {} were already up to date! (took {elapsed})",
+            summary.succeeded, summary.unchanged
+        ),
+
+        Ok(summary) if summary.failed == 0 => format!(
+            ":warning: Synced roles of {} people in {elapsed}, {} were already up to date, but not all members could be fetched, so some were not synced: {}",
+            summary.succeeded,
+            summary.unchanged,
+            summary.scan_error.as_ref().unwrap()
+        ),
+
+        Ok(summary) => {
+            let mut message = format!(
+                ":warning: Synced roles of {} out of {} people in {elapsed}, some chunks failed:\n{}",
+                summary.succeeded,
+                summary.succeeded + summary.failed,
+                failed_chunks_list(summary)
+            );
+
+            if let Some(error) = &summary.scan_error {
+                message += &format!(
+                    "\nNot all members could be fetched, so some were not synced: {error}"
+                );
+            }
+
+            message
         }
+
+        Err(e) => format!(":x: Error while syncing members: {e}"),
+    }
+}
+
+const AUDIT_PAGE_SIZE: u32 = 10;
+
+/// Show the audit log, optionally filtered by user, GD account or action
+#[poise::command(slash_command)]
+pub async fn audit(
+    ctx: Context<'_>,
+    #[description = "Show actions done by or to this user"] user: Option<serenity::User>,
+    #[description = "Show actions involving this GD account ID"] account_id: Option<i32>,
+    #[description = "Show only this kind of action"] action: Option<AuditAction>,
+    #[description = "Page to show, starting at 1"]
+    #[min = 1]
+    page: Option<u32>,
+) -> Result<(), CommandError> {
+    let state = ctx.data();
+
+    if !has_manage_roles_perm(&ctx).await {
+        ctx.reply(":x: No permission").await?;
+        return Ok(());
     }
 
+    let filter = AuditFilter {
+        user: user.map(|u| u.id),
+        account_id,
+        action,
+    };
+
+    let page = page.unwrap_or(1).max(1);
+
+    let (entries, total) = match state
+        .query_audit_log(&filter, page - 1, AUDIT_PAGE_SIZE)
+        .await
+    {
+        Ok(x) => x,
+        Err(e) => {
+            ctx.reply(format!(":x: Failed to read the audit log: {e}"))
+                .await?;
+            bail!("Failed to read the audit log: {e}");
+        }
+    };
+
+    let pages = (total as u32).div_ceil(AUDIT_PAGE_SIZE).max(1);
+
+    let mut message = if entries.is_empty() {
+        "No matching audit log entries.".to_owned()
+    } else {
+        entries
+            .iter()
+            .map(format_audit_entry)
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    message += &format!("\n\n-# Page {page}/{pages}, {total} entries");
+
+    // don't ping everyone mentioned in the log
+    ctx.send(
+        CreateReply::default()
+            .content(message)
+            .allowed_mentions(serenity::CreateAllowedMentions::new()),
+    )
+    .await?;
+
     Ok(())
 }
+
+fn format_audit_entry(entry: &AuditLogEntry) -> String {
+    let action = AuditAction::from_db(&entry.action)
+        .map(|a| a.name().to_owned())
+        .unwrap_or_else(|| entry.action.clone());
+
+    let mut line = format!("`#{}` <t:{}:f> **{action}**", entry.id, entry.created_at);
+
+    match entry.actor_id {
+        Some(id) => line += &format!(" by <@{id}>"),
+        None => line += " (automatic)",
+    }
+
+    if let Some(id) = entry.target_id {
+        line += &format!(", user <@{id}>");
+    }
+
+    if let Some(id) = entry.account_id {
+        line += &format!(", account {id}");
+    }
+
+    if let Some(details) = &entry.details {
+        let mut details = details.clone();
+        details.truncate(details.floor_char_boundary(80));
+        line += &format!(": {details}");
+    }
+
+    line
+}
+
+const PROGRESS_EDIT_INTERVAL: Duration = Duration::from_secs(3);
+
+fn progress_message(progress: &SyncAllProgress) -> String {
+    format!(
+        "⏳ Syncing all members..\n* Members scanned: {}\n* Linked members found: {}\n* Chunks sent: {}\n* Errors: {}",
+        progress.members_scanned, progress.linked_found, progress.chunks_sent, progress.errors
+    )
+}
+
+// lists failed chunks of a mass sync, cut off so that the message stays under the discord limit
+fn failed_chunks_list(summary: &SyncAllSummary) -> String {
+    const MAX_LISTED: usize = 10;
+
+    let mut lines: Vec<String> = summary
+        .failed_chunks
+        .iter()
+        .take(MAX_LISTED)
+        .map(|c| {
+            let mut error = c.error.to_string();
+            error.truncate(error.floor_char_boundary(150));
+
+            format!("* chunk {} ({} users): {}", c.index + 1, c.users, error)
+        })
+        .collect();
+
+    if summary.failed_chunks.len() > MAX_LISTED {
+        lines.push(format!(
+            "* ..and {} more",
+            summary.failed_chunks.len() - MAX_LISTED
+        ));
+    }
+
+    lines.join("\n")
+}
@@ -0,0 +1,180 @@
+use poise::CreateReply;
+
+use crate::{blocklist::BlockSource, db::BlockedAccount};
+
+use super::prelude::*;
+
+#[poise::command(slash_command, subcommands("list", "add", "remove"))]
+pub async fn blocklist(_ctx: Context<'_>) -> Result<(), CommandError> {
+    // unreachable
+    Ok(())
+}
+
+const BLOCKLIST_LIMIT: usize = 25;
+
+/// List GD accounts that can't be linked
+#[poise::command(slash_command)]
+pub async fn list(ctx: Context<'_>) -> Result<(), CommandError> {
+    let state = ctx.data();
+
+    if !has_manage_roles_perm(&ctx).await {
+        ctx.reply(":x: No permission").await?;
+        return Ok(());
+    }
+
+    let accounts = match state.get_blocked_accounts().await {
+        Ok(x) => x,
+        Err(e) => {
+            ctx.reply(format!(":x: Failed to read the blocklist: {e}"))
+                .await?;
+            bail!("Failed to read the blocklist: {e}");
+        }
+    };
+
+    let mut message = if accounts.is_empty() {
+        "The blocklist is empty.".to_owned()
+    } else {
+        accounts
+            .iter()
+            .take(BLOCKLIST_LIMIT)
+            .map(format_blocked_account)
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    if accounts.len() > BLOCKLIST_LIMIT {
+        message += &format!("\n* ..and {} more", accounts.len() - BLOCKLIST_LIMIT);
+    }
+
+    ctx.send(
+        CreateReply::default()
+            .content(message)
+            .allowed_mentions(serenity::CreateAllowedMentions::new()),
+    )
+    .await?;
+
+    Ok(())
+}
+
+fn format_blocked_account(entry: &BlockedAccount) -> String {
+    let mut line = format!("* account {}, <t:{}:f>", entry.account_id, entry.blocked_at);
+
+    match (entry.blocked_by, entry.banned_user_id) {
+        (Some(id), _) => line += &format!(" by <@{id}>"),
+        (None, Some(id)) => line += &format!(", ban of <@{id}>"),
+        (None, None) => {}
+    }
+
+    if let Some(reason) = &entry.reason {
+        let mut reason = reason.clone();
+        reason.truncate(reason.floor_char_boundary(80));
+        line += &format!(": {reason}");
+    }
+
+    line
+}
+
+/// Block a GD account from being linked
+#[poise::command(slash_command)]
+pub async fn add(
+    ctx: Context<'_>,
+    #[description = "GD account ID"] account_id: i32,
+    #[description = "Why the account is blocked"] reason: Option<String>,
+) -> Result<(), CommandError> {
+    let state = ctx.data();
+
+    if !has_manage_roles_perm(&ctx).await {
+        ctx.reply(":x: No permission").await?;
+        return Ok(());
+    }
+
+    let source = BlockSource {
+        blocked_by: Some(ctx.author().id),
+        banned_user: None,
+        reason: reason.clone(),
+    };
+
+    match state.block_account(account_id, &source).await {
+        Ok(true) => {
+            let mut event = AuditEvent::new(AuditAction::AccountBlocked)
+                .actor(ctx.author().id)
+                .account(account_id)
+                .guild(ctx.guild_id());
+
+            if let Some(reason) = reason {
+                event = event.details(reason);
+            }
+
+            state.audit(event).await;
+
+            // blocking only stops new links
+            let message = match state.get_linked_discord_account(account_id).await {
+                Ok(Some(user_id)) => format!(
+                    "✅ Blocked account {account_id}. It is still linked to <@{user_id}>, use `/admin unlink` to unlink it."
+                ),
+                _ => format!("✅ Blocked account {account_id}."),
+            };
+
+            ctx.send(
+                CreateReply::default()
+                    .content(message)
+                    .allowed_mentions(serenity::CreateAllowedMentions::new()),
+            )
+            .await?;
+        }
+
+        Ok(false) => {
+            ctx.reply(":x: This account is already blocked.").await?;
+        }
+
+        Err(e) => {
+            ctx.reply(format!(":x: Failed to block the account: {e}"))
+                .await?;
+            bail!("Failed to block account {account_id}: {e}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Allow a blocked GD account to be linked again
+#[poise::command(slash_command)]
+pub async fn remove(
+    ctx: Context<'_>,
+    #[description = "GD account ID"] account_id: i32,
+) -> Result<(), CommandError> {
+    let state = ctx.data();
+
+    if !has_manage_roles_perm(&ctx).await {
+        ctx.reply(":x: No permission").await?;
+        return Ok(());
+    }
+
+    match state.unblock_account(account_id).await {
+        Ok(true) => {
+            state
+                .audit(
+                    AuditEvent::new(AuditAction::AccountUnblocked)
+                        .actor(ctx.author().id)
+                        .account(account_id)
+                        .guild(ctx.guild_id()),
+                )
+                .await;
+
+            ctx.reply(format!("✅ Account {account_id} can be linked again."))
+                .await?;
+        }
+
+        Ok(false) => {
+            ctx.reply(":x: This account is not blocked.").await?;
+        }
+
+        Err(e) => {
+            ctx.reply(format!(":x: Failed to unblock the account: {e}"))
+                .await?;
+            bail!("Failed to unblock account {account_id}: {e}");
+        }
+    }
+
+    Ok(())
+}
@@ -1,4 +1,8 @@
-use crate::state::LinkError;
+use std::time::Duration;
+
+use poise::{CreateReply, Modal};
+
+use crate::state::{LinkError, UserLookupResponse};
 
 use super::prelude::*;
 
@@ -6,107 +10,335 @@ use super::prelude::*;
 #[poise::command(slash_command, guild_only = true)]
 pub async fn link(
     ctx: Context<'_>,
-    #[description = "GD username"] username: String,
-    #[description = "Link code, can be found in-game"] link_code: u32,
+    #[description = "GD username, leave empty to fill in a form instead"] username: Option<String>,
+    #[description = "Link code, can be found in-game"] link_code: Option<u32>,
 ) -> Result<(), CommandError> {
-    let state = ctx.data();
+    let member = ctx.author_member().await.unwrap().into_owned();
+
+    let (Some(username), Some(link_code)) = (username.clone(), link_code) else {
+        let defaults = LinkModal {
+            username: username.unwrap_or_default(),
+            link_code: link_code.map(|x| x.to_string()).unwrap_or_default(),
+        };
 
-    let member = ctx.author_member().await.unwrap();
+        return link_with_form(ctx, &member, defaults).await;
+    };
 
     ctx.defer().await?;
 
-    match state
-        .link_user(&ctx, &member, &username, Some(link_code))
-        .await
-    {
+    let result = link_and_audit(ctx.data(), &member, &username, link_code).await;
+    let reply = link_reply(&ctx, ctx.author().id, &result).await;
+
+    ctx.reply(reply.message).await?;
+
+    if let Some(error) = reply.error {
+        bail!("{error}");
+    }
+
+    Ok(())
+}
+
+/// Form shown by the "Link account" button.
+#[derive(Clone, Debug, Default, Modal)]
+#[name = "Link your GD account"]
+pub struct LinkModal {
+    #[name = "GD username"]
+    #[placeholder = "Your username in Geometry Dash"]
+    #[max_length = 16]
+    pub username: String,
+
+    #[name = "Link code"]
+    #[placeholder = "Shown in-game in the Globed menu"]
+    #[max_length = 10]
+    pub link_code: String,
+}
+
+impl LinkModal {
+    /// Checks the form before asking the server, returns the link code.
+    pub fn validate(&self) -> Result<u32, &'static str> {
+        let username = self.username.trim();
+
+        if username.is_empty() || !username.is_ascii() || username.len() > 16 {
+            return Err(
+                "The GD username must be 1 to 16 characters long, without special characters.",
+            );
+        }
+
+        self.link_code.trim().parse().map_err(|_| {
+            "The link code must be a number, you can find it in-game in the Globed menu."
+        })
+    }
+}
+
+/// What to tell a user after trying to link, shared by `/link` and the link form.
+pub struct LinkReply {
+    pub message: String,
+    /// Whether trying again with a different username or code could work.
+    pub retry: bool,
+    /// Logged as a command error, the user only sees a generic message.
+    pub error: Option<String>,
+}
+
+impl LinkReply {
+    fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            retry: false,
+            error: None,
+        }
+    }
+
+    fn retry(mut self) -> Self {
+        self.retry = true;
+        self
+    }
+
+    fn error(mut self, error: impl Into<String>) -> Self {
+        self.error = Some(error.into());
+        self
+    }
+}
+
+/// Links a member with a link code, recording it in the audit log.
+pub(crate) async fn link_and_audit(
+    state: &BotState,
+    member: &serenity::Member,
+    username: &str,
+    link_code: u32,
+) -> Result<(UserLookupResponse, Vec<String>), LinkError> {
+    let result = state.link_user(member, username, Some(link_code)).await;
+
+    if let Ok((user, _)) | Err(LinkError::RoleSync(_, user)) = &result {
+        state
+            .audit(
+                AuditEvent::new(AuditAction::Link)
+                    .actor(member.user.id)
+                    .target(member.user.id)
+                    .account(user.account_id)
+                    .guild(member.guild_id),
+            )
+            .await;
+    }
+
+    result
+}
+
+pub(crate) async fn link_reply(
+    cache_http: impl serenity::CacheHttp,
+    user_id: serenity::UserId,
+    result: &Result<(UserLookupResponse, Vec<String>), LinkError>,
+) -> LinkReply {
+    match result {
         Ok((user, roles)) => {
             if roles.is_empty() {
-                ctx.reply(format!(
+                LinkReply::new(format!(
                     "✅ Linked <@{}> to GD account {} ({})!",
-                    ctx.author().id,
-                    user.name,
-                    user.account_id
+                    user_id, user.name, user.account_id
                 ))
-                .await?;
             } else {
-                ctx.reply(format!(
+                LinkReply::new(format!(
                     "✅ Linked <@{}> to GD account {} ({})!\n\n* Synced roles: {}\n* Reconnect to the server to see your new roles",
-                    ctx.author().id,
+                    user_id,
                     user.name,
                     user.account_id,
                     roles.join(", ")
                 ))
-                .await?;
             }
-
-            Ok(())
         }
 
-        Err(LinkError::AlreadyLinked) => {
-            ctx.reply(":x: Already linked. Use the `/unlink` command to unlink your account.")
-                .await?;
+        Err(LinkError::AlreadyLinked) => LinkReply::new(
+            ":x: Already linked. Use the `/unlink` command to unlink your account.",
+        ),
 
-            Ok(())
+        Err(LinkError::InvalidUsername) => {
+            LinkReply::new(":x: Invalid username was provided.").retry()
         }
 
-        Err(LinkError::InvalidUsername) => {
-            ctx.reply(":x: Invalid username was provided.").await?;
-            Ok(())
+        Err(LinkError::TooManyAttempts(wait)) => {
+            let retry_at = time::OffsetDateTime::now_utc().unix_timestamp() + wait.as_secs() as i64;
+
+            LinkReply::new(format!(
+                ":x: Too many failed attempts. You can try again <t:{retry_at}:R>, if you keep having trouble, please contact the moderator team."
+            ))
         }
 
         Err(LinkError::ServerRequest(err)) => {
-            ctx.reply(":x: Failed to make a request to the server!")
-                .await?;
-
-            bail!("User lookup failed: {err}");
+            LinkReply::new(":x: Failed to make a request to the server!")
+                .error(format!("User lookup failed: {err}"))
         }
 
         Err(LinkError::ServerInternalError(status, message)) => {
-            ctx.reply(":x: Server returned an unexpected error!")
-                .await?;
-
-            bail!(
+            LinkReply::new(":x: Server returned an unexpected error!").error(format!(
                 "User lookup failed: code {}, message: {}",
                 status.as_u16(),
                 message
-            );
+            ))
         }
 
-        Err(LinkError::UserNotFound) => {
-            ctx.reply(":x: Failed to find the user by the given name. Make sure you are currently online on Globed, and that the link code is correct, and try again.").await?;
-            Ok(())
-        }
+        Err(LinkError::UserNotFound) => LinkReply::new(
+            ":x: Failed to find the user by the given name. Make sure you are currently online on Globed, and that the link code is correct, and try again.",
+        )
+        .retry(),
 
         Err(LinkError::ServerMalformedResponse(error, json)) => {
-            ctx.reply(":x: Server returned unparsable data.").await?;
-            bail!("User lookup failed: failed to parse response: {error:?}\nResponse was: {json}");
+            LinkReply::new(":x: Server returned unparsable data.").error(format!(
+                "User lookup failed: failed to parse response: {error:?}\nResponse was: {json}"
+            ))
         }
 
-        Err(LinkError::Database(err)) => {
-            ctx.reply(":x: Unknown database error has occurred.")
-                .await?;
-
-            bail!("database connection error: {err}");
-        }
+        Err(LinkError::Database(err)) => LinkReply::new(":x: Unknown database error has occurred.")
+            .error(format!("database connection error: {err}")),
 
         Err(LinkError::RoleSync(err, user)) => {
             warn!("Failed to sync roles: {err}");
 
-            ctx.reply(format!(
+            LinkReply::new(format!(
                 "Linked <@{}> to GD account {} ({}) successfully, but role syncing failed. Try to execute the `/sync` command manually, or contact staff for assistance.",
-                ctx.author().id,
+                user_id,
                 user.name,
                 user.account_id
-            )).await?;
+            ))
+        }
 
-            Ok(())
+        Err(LinkError::LinkedToOther(linked_id)) => {
+            let ident = user_ident(cache_http, *linked_id).await;
+
+            LinkReply::new(format!(
+                ":x: This Geometry Dash account is already linked to another Discord account ({}). If this is not you, please contact the moderator team.",
+                ident
+            ))
         }
 
-        Err(LinkError::LinkedToOther(ident)) => {
-            ctx.reply(format!(":x: This Geometry Dash account is already linked to another Discord account ({}). If this is not you, please contact the moderator team.", ident))
-            .await?;
+        Err(LinkError::AccountBlocked) => LinkReply::new(
+            ":x: This Geometry Dash account is blocked from being linked. If you think this is a mistake, please contact the moderator team.",
+        ),
+    }
+}
+
+/// Links a member with a filled in link form, returns the problem with the form if it can't be sent to the server.
+pub async fn submit_link_form(
+    state: &BotState,
+    cache_http: impl serenity::CacheHttp,
+    member: &serenity::Member,
+    form: &LinkModal,
+) -> Result<LinkReply, &'static str> {
+    let link_code = form.validate()?;
+    let result = link_and_audit(state, member, form.username.trim(), link_code).await;
 
-            Ok(())
+    Ok(link_reply(cache_http, member.user.id, &result).await)
+}
+
+/// How long the "Link account" button keeps working.
+pub(crate) const LINK_FORM_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+const LINK_FORM_PROMPT: &str = "Click the button below and enter your GD username and link code. The link code can be found in-game in the Globed menu.";
+
+pub(crate) fn link_button(custom_id: &str, label: &str) -> Vec<serenity::CreateActionRow> {
+    vec![serenity::CreateActionRow::Buttons(vec![
+        serenity::CreateButton::new(custom_id)
+            .label(label)
+            .style(serenity::ButtonStyle::Primary),
+    ])]
+}
+
+// shows a "Link account" button that opens the link form, until the user links or the button times out
+async fn link_with_form(
+    ctx: Context<'_>,
+    member: &serenity::Member,
+    mut defaults: LinkModal,
+) -> Result<(), CommandError> {
+    let button_id = format!("{}link", ctx.id());
+
+    let prompt = ctx
+        .send(
+            CreateReply::default()
+                .content(LINK_FORM_PROMPT)
+                .components(link_button(&button_id, "Link account"))
+                .ephemeral(true),
+        )
+        .await?;
+
+    let mut error = None;
+
+    loop {
+        let filter_id = button_id.clone();
+        let Some(press) = serenity::ComponentInteractionCollector::new(ctx)
+            .author_id(ctx.author().id)
+            .filter(move |press| press.data.custom_id == filter_id)
+            .timeout(LINK_FORM_TIMEOUT)
+            .await
+        else {
+            prompt
+                .edit(
+                    ctx,
+                    CreateReply::default()
+                        .content("The link form expired, use `/link` again.")
+                        .components(Vec::new()),
+                )
+                .await?;
+            break;
+        };
+
+        let Some(form) = poise::execute_modal_on_component_interaction::<LinkModal>(
+            ctx,
+            press,
+            Some(defaults.clone()),
+            Some(LINK_FORM_TIMEOUT),
+        )
+        .await?
+        else {
+            continue;
+        };
+
+        defaults = form.clone();
+
+        // problems with the form are shown in place, with the button to fix them
+        let reply = match submit_link_form(ctx.data(), &ctx, member, &form).await {
+            Ok(x) => x,
+            Err(problem) => {
+                prompt
+                    .edit(
+                        ctx,
+                        CreateReply::default()
+                            .content(format!(":x: {problem}"))
+                            .components(link_button(&button_id, "Try again")),
+                    )
+                    .await?;
+                continue;
+            }
+        };
+
+        reply_ephemeral(&ctx, reply.message).await?;
+
+        if reply.retry {
+            prompt
+                .edit(
+                    ctx,
+                    CreateReply::default()
+                        .content("Check your username and link code, then try again.")
+                        .components(link_button(&button_id, "Try again")),
+                )
+                .await?;
+            continue;
         }
+
+        // the outcome got its own reply, the prompt just shouldn't invite filling in the form again
+        prompt
+            .edit(
+                ctx,
+                CreateReply::default()
+                    .content("The link form is closed, use `/link` again if you need it.")
+                    .components(Vec::new()),
+            )
+            .await?;
+
+        error = reply.error;
+        break;
     }
+
+    if let Some(error) = error {
+        bail!("{error}");
+    }
+
+    Ok(())
 }
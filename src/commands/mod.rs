@@ -1,16 +1,19 @@
-use crate::serenity;
+use crate::{pull_roles::DiscordRoleChanges, serenity};
 use std::{borrow::Cow, fmt::Display};
 
 pub mod prelude;
 
 mod admin;
+mod blocklist;
 mod link;
+mod panel;
 mod role;
 mod sync;
 mod unlink;
 
-pub use admin::admin;
+pub use admin::{admin, begin_syncall, syncall_reply};
 pub use link::*;
+pub use panel::{handle_panel_interaction, panel_reply, PanelButton, PanelReply, PanelResponse};
 use poise::CreateReply;
 pub use role::role;
 pub use sync::*;
@@ -88,3 +91,40 @@ pub async fn reply_ephemeral<'a>(
     ctx.send(CreateReply::default().content(content).ephemeral(true))
         .await
 }
+
+// formats a user as `@username` if they can be found, else falls back to their user id
+pub async fn user_ident(cache_http: impl serenity::CacheHttp, user_id: serenity::UserId) -> String {
+    if let Some(cached) = cache_http.cache().and_then(|c| c.user(user_id)) {
+        return format!("@{}", cached.name);
+    }
+
+    match cache_http.http().get_user(user_id).await {
+        Ok(user) => format!("@{}", user.name),
+        Err(_) => user_id.to_string(),
+    }
+}
+
+/// Extra lines for a sync reply, listing discord roles that were changed to match Globed.
+pub fn pulled_roles_message(changes: &DiscordRoleChanges) -> String {
+    let mention = |ids: &[serenity::RoleId]| {
+        ids.iter()
+            .map(|id| format!("<@&{id}>"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let mut message = String::new();
+
+    if !changes.added.is_empty() {
+        message += &format!("\n* Roles added from Globed: {}", mention(&changes.added));
+    }
+
+    if !changes.removed.is_empty() {
+        message += &format!(
+            "\n* Roles removed to match Globed: {}",
+            mention(&changes.removed)
+        );
+    }
+
+    message
+}
@@ -0,0 +1,470 @@
+use poise::Modal;
+
+use super::{
+    link::{link_button, submit_link_form, LinkModal, LINK_FORM_TIMEOUT},
+    prelude::*,
+};
+
+/// Buttons of the onboarding panel. Their custom ids never change, so panels posted
+/// before a restart keep working.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PanelButton {
+    Link,
+    Unlink,
+    /// Shown after pressing "Unlink", so the account isn't unlinked by a misclick.
+    ConfirmUnlink,
+    Sync,
+    Status,
+}
+
+impl PanelButton {
+    pub const fn custom_id(self) -> &'static str {
+        match self {
+            Self::Link => "panel:link",
+            Self::Unlink => "panel:unlink",
+            Self::ConfirmUnlink => "panel:unlink_confirm",
+            Self::Sync => "panel:sync",
+            Self::Status => "panel:status",
+        }
+    }
+
+    pub fn from_custom_id(custom_id: &str) -> Option<Self> {
+        match custom_id {
+            "panel:link" => Some(Self::Link),
+            "panel:unlink" => Some(Self::Unlink),
+            "panel:unlink_confirm" => Some(Self::ConfirmUnlink),
+            "panel:sync" => Some(Self::Sync),
+            "panel:status" => Some(Self::Status),
+            _ => None,
+        }
+    }
+
+    fn button(self, label: &str, style: serenity::ButtonStyle) -> serenity::CreateButton {
+        serenity::CreateButton::new(self.custom_id())
+            .label(label)
+            .style(style)
+    }
+}
+
+fn panel_embed() -> serenity::CreateEmbed {
+    serenity::CreateEmbed::new()
+        .title("Globed roles")
+        .description(
+            "Link your Geometry Dash account to get your roles on Globed.\n\n\
+            * **Link** - enter your GD username and the link code shown in-game in the Globed menu\n\
+            * **Sync** - update your roles on Globed after they changed here\n\
+            * **Status** - see which account you are linked to\n\
+            * **Unlink** - unlink your GD account",
+        )
+        .colour(0x5865f2)
+}
+
+fn panel_buttons() -> Vec<serenity::CreateActionRow> {
+    use serenity::ButtonStyle;
+
+    vec![serenity::CreateActionRow::Buttons(vec![
+        PanelButton::Link.button("Link", ButtonStyle::Primary),
+        PanelButton::Sync.button("Sync", ButtonStyle::Secondary),
+        PanelButton::Status.button("Status", ButtonStyle::Secondary),
+        PanelButton::Unlink.button("Unlink", ButtonStyle::Danger),
+    ])]
+}
+
+/// Post a panel with link, unlink, sync and status buttons
+#[poise::command(slash_command)]
+pub async fn panel(
+    ctx: Context<'_>,
+    #[description = "Channel to post the panel in, defaults to this channel"]
+    #[channel_types("Text", "News")]
+    channel: Option<serenity::GuildChannel>,
+) -> Result<(), CommandError> {
+    if !has_manage_roles_perm(&ctx).await {
+        ctx.reply(":x: No permission").await?;
+        return Ok(());
+    }
+
+    let channel_id = channel.map_or(ctx.channel_id(), |x| x.id);
+
+    let result = channel_id
+        .send_message(
+            &ctx,
+            serenity::CreateMessage::new()
+                .embed(panel_embed())
+                .components(panel_buttons()),
+        )
+        .await;
+
+    match result {
+        Ok(_) => {
+            reply_ephemeral(&ctx, format!("✅ Posted the panel in <#{channel_id}>.")).await?;
+        }
+
+        Err(e) => {
+            reply_ephemeral(&ctx, format!(":x: Failed to post the panel: {e}")).await?;
+            bail!("Failed to post the panel in {channel_id}: {e}");
+        }
+    }
+
+    Ok(())
+}
+
+/// How a press of a panel button is answered. Worked out apart from the interaction itself, so that it can be tested.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PanelResponse {
+    /// Open the link form.
+    LinkForm,
+    /// An ephemeral message, with a button for the next step if there is one.
+    Message(String, Option<PanelButton>),
+    /// Replaces the message with the pressed button, so that it can't be pressed twice.
+    Update(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct PanelReply {
+    pub response: PanelResponse,
+    /// Logged as a command error, the user only sees a generic message.
+    pub error: Option<String>,
+}
+
+impl PanelReply {
+    fn new(response: PanelResponse) -> Self {
+        Self {
+            response,
+            error: None,
+        }
+    }
+
+    fn message(content: impl Into<String>) -> Self {
+        Self::new(PanelResponse::Message(content.into(), None))
+    }
+
+    fn error(mut self, error: impl Into<String>) -> Self {
+        self.error = Some(error.into());
+        self
+    }
+}
+
+// the button offered for the next step after a reply
+fn next_step_buttons(button: PanelButton) -> Vec<serenity::CreateActionRow> {
+    match button {
+        PanelButton::Link => link_button(button.custom_id(), "Try again"),
+        PanelButton::ConfirmUnlink | PanelButton::Unlink => {
+            vec![serenity::CreateActionRow::Buttons(vec![
+                button.button("Unlink", serenity::ButtonStyle::Danger)
+            ])]
+        }
+        PanelButton::Sync => vec![serenity::CreateActionRow::Buttons(vec![
+            button.button("Sync", serenity::ButtonStyle::Secondary)
+        ])],
+        PanelButton::Status => vec![serenity::CreateActionRow::Buttons(vec![
+            button.button("Status", serenity::ButtonStyle::Secondary)
+        ])],
+    }
+}
+
+/// Works out the reply to a panel button pressed by `member`, doing what the button does.
+pub async fn panel_reply(
+    state: &BotState,
+    cache_http: impl serenity::CacheHttp,
+    button: PanelButton,
+    member: &serenity::Member,
+) -> PanelReply {
+    match button {
+        PanelButton::Link => panel_link(state, member).await,
+        PanelButton::Unlink => panel_unlink(state, member).await,
+        PanelButton::ConfirmUnlink => panel_confirm_unlink(state, member).await,
+        PanelButton::Sync => panel_sync(state, cache_http.http(), member).await,
+        PanelButton::Status => panel_status(state, member).await,
+    }
+}
+
+/// Handles a press of a panel button, called from the event handler since panels outlive the command that posted them.
+/// Presses of other components are ignored.
+pub async fn handle_panel_interaction(
+    ctx: &serenity::Context,
+    state: &BotState,
+    press: &serenity::ComponentInteraction,
+) -> Result<(), CommandError> {
+    let Some(button) = PanelButton::from_custom_id(&press.data.custom_id) else {
+        return Ok(());
+    };
+
+    let Some(member) = press.member.as_ref() else {
+        return Ok(());
+    };
+
+    // anything talking to the server can take longer than discord waits for a response.
+    // the link form has to be the first response, and the unlink prompt only reads the database
+    let defer = match button {
+        PanelButton::Sync | PanelButton::Status => {
+            Some(serenity::CreateInteractionResponse::Defer(
+                serenity::CreateInteractionResponseMessage::new().ephemeral(true),
+            ))
+        }
+        // answered by updating the message with the button, see `PanelResponse::Update`
+        PanelButton::ConfirmUnlink => Some(serenity::CreateInteractionResponse::Acknowledge),
+        PanelButton::Link | PanelButton::Unlink => None,
+    };
+
+    let deferred = defer.is_some();
+    if let Some(defer) = defer {
+        press.create_response(ctx, defer).await?;
+    }
+
+    let reply = panel_reply(state, ctx, button, member).await;
+
+    match reply.response {
+        PanelResponse::LinkForm => return link_form(ctx, state, press, member).await,
+
+        PanelResponse::Message(content, next) if deferred => {
+            let next = next.map(next_step_buttons).unwrap_or_default();
+            press
+                .edit_response(ctx, deferred_edit(content, next))
+                .await?;
+        }
+
+        PanelResponse::Update(content) if deferred => {
+            press
+                .edit_response(ctx, deferred_edit(content, Vec::new()))
+                .await?;
+        }
+
+        PanelResponse::Message(content, next) => {
+            let mut message = serenity::CreateInteractionResponseMessage::new()
+                .content(content)
+                .allowed_mentions(serenity::CreateAllowedMentions::new())
+                .ephemeral(true);
+            if let Some(next) = next {
+                message = message.components(next_step_buttons(next));
+            }
+
+            press
+                .create_response(ctx, serenity::CreateInteractionResponse::Message(message))
+                .await?;
+        }
+
+        PanelResponse::Update(content) => {
+            press
+                .create_response(
+                    ctx,
+                    serenity::CreateInteractionResponse::UpdateMessage(
+                        serenity::CreateInteractionResponseMessage::new()
+                            .content(content)
+                            .components(Vec::new()),
+                    ),
+                )
+                .await?;
+        }
+    }
+
+    if let Some(error) = reply.error {
+        bail!("{error}");
+    }
+
+    Ok(())
+}
+
+// fills in the response to a deferred interaction, without pinging anyone mentioned in it
+fn deferred_edit(
+    content: String,
+    components: Vec<serenity::CreateActionRow>,
+) -> serenity::EditInteractionResponse {
+    serenity::EditInteractionResponse::new()
+        .content(content)
+        .components(components)
+        .allowed_mentions(serenity::CreateAllowedMentions::new())
+}
+
+// opens the link form, and answers its submission with the result
+async fn link_form(
+    ctx: &serenity::Context,
+    state: &BotState,
+    press: &serenity::ComponentInteraction,
+    member: &serenity::Member,
+) -> Result<(), CommandError> {
+    // unique per press, so that a form left open doesn't take the submission of another one
+    let modal_id = format!("{}:{}", PanelButton::Link.custom_id(), press.id);
+
+    press
+        .create_response(ctx, LinkModal::create(None, modal_id.clone()))
+        .await?;
+
+    let Some(submit) = serenity::ModalInteractionCollector::new(ctx)
+        .author_id(member.user.id)
+        .custom_ids(vec![modal_id])
+        .timeout(LINK_FORM_TIMEOUT)
+        .await
+    else {
+        return Ok(());
+    };
+
+    // answered through the submission, the button press was already answered by opening the form.
+    // deferred since linking asks the server
+    submit
+        .create_response(
+            ctx,
+            serenity::CreateInteractionResponse::Defer(
+                serenity::CreateInteractionResponseMessage::new().ephemeral(true),
+            ),
+        )
+        .await?;
+
+    let (content, retry, error) = match LinkModal::parse(submit.data.clone()) {
+        Ok(form) => match submit_link_form(state, ctx, member, &form).await {
+            Ok(reply) => (reply.message, reply.retry, reply.error),
+            Err(problem) => (format!(":x: {problem}"), true, None),
+        },
+        Err(e) => (
+            ":x: Failed to read the form, please try again.".to_owned(),
+            true,
+            Some(format!("Failed to parse the link form: {e}")),
+        ),
+    };
+
+    let next = if retry {
+        next_step_buttons(PanelButton::Link)
+    } else {
+        Vec::new()
+    };
+
+    submit
+        .edit_response(ctx, deferred_edit(content, next))
+        .await?;
+
+    if let Some(error) = error {
+        bail!("{error}");
+    }
+
+    Ok(())
+}
+
+async fn panel_link(state: &BotState, member: &serenity::Member) -> PanelReply {
+    match state.is_linked(member.user.id).await {
+        Ok(false) => PanelReply::new(PanelResponse::LinkForm),
+        Ok(true) => PanelReply::message(
+            ":x: Already linked. Press **Unlink** first to link another account.",
+        ),
+        Err(e) => PanelReply::message(":x: Unknown database error has occurred.")
+            .error(format!("database connection error: {e}")),
+    }
+}
+
+async fn panel_unlink(state: &BotState, member: &serenity::Member) -> PanelReply {
+    match state.get_linked_gd_account(member.user.id).await {
+        Ok(Some(account_id)) => PanelReply::new(PanelResponse::Message(
+            format!(
+                "Unlink your Discord account from GD account {account_id}? You will lose your roles on Globed."
+            ),
+            Some(PanelButton::ConfirmUnlink),
+        )),
+        Ok(None) => PanelReply::message(":x: Not currently linked to any account."),
+        Err(e) => PanelReply::message(":x: Unknown database error has occurred.").error(format!(
+            "Failed to get the linked account of {}: {e}",
+            member.user.name
+        )),
+    }
+}
+
+async fn panel_confirm_unlink(state: &BotState, member: &serenity::Member) -> PanelReply {
+    let account_id = state
+        .get_linked_gd_account(member.user.id)
+        .await
+        .ok()
+        .flatten();
+
+    match state.unlink_user(member.user.id, UnlinkReason::User).await {
+        Ok(()) => {
+            state
+                .audit(
+                    AuditEvent::new(AuditAction::Unlink)
+                        .actor(member.user.id)
+                        .target(member.user.id)
+                        .account(account_id.map(|x| x.get()))
+                        .guild(member.guild_id),
+                )
+                .await;
+
+            PanelReply::new(PanelResponse::Update("Successfully unlinked the account! If you were connected, you might have to reconnect to Globed to link again.".to_owned()))
+        }
+
+        Err(RoleSyncError::NotLinked) => PanelReply::new(PanelResponse::Update(
+            ":x: Not currently linked to any account.".to_owned(),
+        )),
+
+        Err(e) => PanelReply::new(PanelResponse::Update(
+            ":x: Failed to unlink your account due to an internal error.".to_owned(),
+        ))
+        .error(format!("Failed to unlink user ({}): {e}", member.user.name)),
+    }
+}
+
+async fn panel_sync(
+    state: &BotState,
+    http: &serenity::Http,
+    member: &serenity::Member,
+) -> PanelReply {
+    let mut member = member.clone();
+
+    match state.pull_and_sync_roles(http, &mut member).await {
+        Ok((roles, pulled)) => PanelReply::message(
+            String::from("✅ Successfully synced roles! If you were already online on Globed, please reconnect to the server to see the changes.\n\n")
+                + "* Synced roles: " + &roles.join(", ")
+                + &pulled_roles_message(&pulled),
+        ),
+
+        Err(RoleSyncError::NotLinked) => PanelReply::message(
+            ":x: Not currently linked to any account. Press **Link** to link a GD account.",
+        ),
+
+        Err(e) => PanelReply::message(":x: Failed to sync your roles due to an internal error.")
+            .error(format!("Failed to sync roles ({}): {e}", member.user.name)),
+    }
+}
+
+async fn panel_status(state: &BotState, member: &serenity::Member) -> PanelReply {
+    let history = match state.get_user_link_history(member.user.id).await {
+        Ok(x) => x,
+        Err(e) => {
+            return PanelReply::message(":x: Unknown database error has occurred.").error(format!(
+                "Failed to get the link history of {}: {e}",
+                member.user.name
+            ));
+        }
+    };
+
+    let message = match history.first() {
+        Some(link) if link.is_active() => {
+            let mut message = format!(
+                "Linked to GD account {} since <t:{}:f>.",
+                link.gd_account_id, link.linked_at
+            );
+
+            // what the server has, which can differ from what would be synced from here
+            match state.backend.get_roles(link.gd_account_id as i32).await {
+                Ok(roles) if roles.is_empty() => {
+                    message += "\n* No roles on Globed";
+                }
+                Ok(roles) => {
+                    message += &format!("\n* Roles on Globed: {}", roles.join(", "));
+                }
+                Err(e) => {
+                    warn!("Failed to get the roles of {}: {e}", member.user.name);
+                    message += "\n* Couldn't get your roles from Globed right now";
+                }
+            }
+
+            message
+        }
+
+        Some(link) => format!(
+            "Not currently linked, last linked to GD account {} until <t:{}:f> ({}). Press **Link** to link again.",
+            link.gd_account_id,
+            link.unlinked_at.unwrap_or(link.linked_at),
+            link.reason().map_or("unknown".to_owned(), |x| x.to_string())
+        ),
+
+        None => "Not linked to any account yet. Press **Link** to link your GD account.".to_owned(),
+    };
+
+    PanelReply::message(message)
+}
@@ -1,9 +1,14 @@
 // Imports typically needed for most commands
 #[allow(unused)]
-pub use super::{bail, has_admin_perm, has_manage_roles_perm, reply_ephemeral, CommandError};
+pub use super::{
+    bail, has_admin_perm, has_manage_roles_perm, pulled_roles_message, reply_ephemeral, user_ident,
+    CommandError,
+};
 
 #[allow(unused)]
 pub use crate::{
+    audit::{AuditAction, AuditEvent},
+    db::UnlinkReason,
     logger::*,
     serenity,
     state::{BotState, RoleRemoveError, RoleSyncError, RoleSyncRequest, RoleSyncRequestData},
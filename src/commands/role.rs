@@ -1,6 +1,15 @@
+use poise::ChoiceParameter;
+
 use super::prelude::*;
+use crate::{
+    db::{SyncDirection, BOOSTER_ROLE_ID},
+    rule::Rule,
+};
 
-#[poise::command(slash_command, subcommands("add", "remove", "removeid", "list"))]
+#[poise::command(
+    slash_command,
+    subcommands("add", "remove", "removeid", "set_rule", "list")
+)]
 pub async fn role(_ctx: Context<'_>) -> Result<(), CommandError> {
     // unreachable
     Ok(())
@@ -10,8 +19,10 @@ pub async fn role(_ctx: Context<'_>) -> Result<(), CommandError> {
 #[poise::command(slash_command)]
 pub async fn add(
     ctx: Context<'_>,
-    #[description = "Role to add"] role: serenity::Role,
     #[description = "Role ID on the Globed server"] globed_role_id: String,
+    #[description = "Role to add"] role: Option<serenity::Role>,
+    #[description = "Give the Globed role to server boosters instead"] booster: Option<bool>,
+    #[description = "Sync direction, Discord → Globed if not set"] direction: Option<SyncDirection>,
 ) -> Result<(), CommandError> {
     let state = ctx.data();
 
@@ -20,11 +31,49 @@ pub async fn add(
         return Ok(());
     }
 
-    match state.add_role(role.id.get() as i64, &globed_role_id).await {
+    let guild_id = ctx.guild_id().ok_or(CommandError::PrivateMessages)?;
+
+    let direction = direction.unwrap_or(SyncDirection::DiscordToGlobed);
+    let booster = booster.unwrap_or(false);
+
+    let (discord_id, mention) = match (role, booster) {
+        (Some(role), false) => (role.id.get() as i64, format!("<@&{}>", role.id)),
+        (None, true) => (BOOSTER_ROLE_ID, "Server Booster".to_owned()),
+        _ => {
+            ctx.reply(":x: Pick either a role or `booster`.").await?;
+            return Ok(());
+        }
+    };
+
+    // the bot can't make someone boost
+    if booster && direction.to_discord() {
+        ctx.reply(":x: Boosting can only be synced from Discord to Globed.")
+            .await?;
+        return Ok(());
+    }
+
+    match state
+        .add_role(guild_id, discord_id, &globed_role_id, direction)
+        .await
+    {
         Ok(()) => {
+            state
+                .audit(
+                    AuditEvent::new(AuditAction::RoleAdd)
+                        .actor(ctx.author().id)
+                        .guild(guild_id)
+                        .details(format!(
+                            "{mention} -> `{globed_role_id}` ({})",
+                            direction.name()
+                        )),
+                )
+                .await;
+
             ctx.reply(format!(
-                "✅ Successfully linked role <@&{}> to globed role `{}`.",
-                role.id, globed_role_id
+                "✅ Successfully linked {} to globed role `{}` ({}).",
+                mention,
+                globed_role_id,
+                direction.name()
             ))
             .await?
         }
@@ -50,8 +99,19 @@ pub async fn remove(
         return Ok(());
     }
 
-    match state.remove_role(role.id.get() as i64).await {
+    let guild_id = ctx.guild_id().ok_or(CommandError::PrivateMessages)?;
+
+    match state.remove_role(guild_id, role.id.get() as i64).await {
         Ok(()) => {
+            state
+                .audit(
+                    AuditEvent::new(AuditAction::RoleRemove)
+                        .actor(ctx.author().id)
+                        .guild(guild_id)
+                        .details(format!("<@&{}>", role.id)),
+                )
+                .await;
+
             ctx.reply(format!("S✅ uccessfully removed role <@&{}>.", role.id))
                 .await?;
         }
@@ -83,8 +143,22 @@ pub async fn removeid(
         return Ok(());
     }
 
-    match state.remove_role_by_globed_id(&globed_role_id).await {
+    let guild_id = ctx.guild_id().ok_or(CommandError::PrivateMessages)?;
+
+    match state
+        .remove_role_by_globed_id(guild_id, &globed_role_id)
+        .await
+    {
         Ok(()) => {
+            state
+                .audit(
+                    AuditEvent::new(AuditAction::RoleRemove)
+                        .actor(ctx.author().id)
+                        .guild(guild_id)
+                        .details(format!("`{globed_role_id}`")),
+                )
+                .await;
+
             ctx.reply(format!(
                 "✅ Successfully removed role `{}`.",
                 globed_role_id
@@ -107,6 +181,98 @@ pub async fn removeid(
     Ok(())
 }
 
+/// Set a rule deciding who gets a Globed role, like `@Supporter AND NOT @Muted` or `booster`
+#[poise::command(slash_command, rename = "rule")]
+pub async fn set_rule(
+    ctx: Context<'_>,
+    #[description = "Globed role to change"] globed_role_id: String,
+    #[description = "Roles combined with AND, OR, NOT and parentheses, leave empty to remove the rule"]
+    rule: Option<String>,
+) -> Result<(), CommandError> {
+    let state = ctx.data();
+
+    if !has_admin_perm(&ctx).await {
+        ctx.reply(":x: No permission").await?;
+        return Ok(());
+    }
+
+    let guild_id = ctx.guild_id().ok_or(CommandError::PrivateMessages)?;
+
+    let rule = match rule.as_deref().map(Rule::parse).transpose() {
+        Ok(x) => x,
+        Err(e) => {
+            ctx.reply(format!(":x: Invalid rule: {e}")).await?;
+            return Ok(());
+        }
+    };
+
+    if let Some(rule) = &rule {
+        let guild_roles: Vec<serenity::RoleId> =
+            guild_id.roles(ctx.http()).await?.into_keys().collect();
+        let unknown = rule.unknown_roles(&guild_roles);
+
+        if !unknown.is_empty() {
+            let unknown: Vec<String> = unknown.iter().map(|id| format!("`{id}`")).collect();
+
+            ctx.reply(format!(
+                ":x: Invalid rule: {} {} not roles on this server",
+                unknown.join(", "),
+                if unknown.len() == 1 { "is" } else { "are" }
+            ))
+            .await?;
+            return Ok(());
+        }
+    }
+
+    match state
+        .set_role_rule(guild_id, &globed_role_id, rule.as_ref())
+        .await
+    {
+        Ok(()) => {
+            let details = match &rule {
+                Some(rule) => format!("`{globed_role_id}`: {}", rule.to_mentions()),
+                None => format!("`{globed_role_id}`: rule removed"),
+            };
+
+            state
+                .audit(
+                    AuditEvent::new(AuditAction::RoleRule)
+                        .actor(ctx.author().id)
+                        .guild(guild_id)
+                        .details(details),
+                )
+                .await;
+
+            let message = match &rule {
+                Some(rule) => format!(
+                    "✅ Members now get `{}` if they match: {}",
+                    globed_role_id,
+                    rule.to_mentions()
+                ),
+                None => format!(
+                    "✅ Removed the rule of `{}`, it follows the linked role again.",
+                    globed_role_id
+                ),
+            };
+
+            ctx.reply(message).await?;
+        }
+
+        Err(RoleRemoveError::Database(e)) => {
+            ctx.reply(format!(":x: Failed to update the role: {e}"))
+                .await?;
+            bail!("Role rule update failed: {e}");
+        }
+
+        Err(RoleRemoveError::NotFound) => {
+            ctx.reply(":x: Role is not currently linked to any role on Globed.")
+                .await?;
+        }
+    };
+
+    Ok(())
+}
+
 /// List all linked roles
 #[poise::command(slash_command)]
 pub async fn list(ctx: Context<'_>) -> Result<(), CommandError> {
@@ -117,11 +283,24 @@ pub async fn list(ctx: Context<'_>) -> Result<(), CommandError> {
         return Ok(());
     }
 
-    match state.get_all_roles().await {
+    let guild_id = ctx.guild_id().ok_or(CommandError::PrivateMessages)?;
+
+    match state.get_guild_roles(guild_id).await {
         Ok(roles) => {
             let mut msg = "List of linked roles on this server:\n\n".to_owned();
             for role in roles {
-                msg += &format!("* <@&{}> - `{}`\n", role.discord_id, role.id);
+                msg += &format!(
+                    "* {} - `{}` ({})",
+                    role.discord_mention(),
+                    role.id,
+                    role.direction().name()
+                );
+
+                if let Some(rule) = role.rule() {
+                    msg += &format!(", rule: {}", rule.to_mentions());
+                }
+
+                msg += "\n";
             }
 
             ctx.reply(msg).await?;
@@ -4,15 +4,16 @@ use super::prelude::*;
 #[poise::command(slash_command, guild_only = true)]
 pub async fn sync(ctx: Context<'_>) -> Result<(), CommandError> {
     let state = ctx.data();
-    let member = ctx.author_member().await.unwrap();
+    let mut member = ctx.author_member().await.unwrap().into_owned();
 
     ctx.defer().await?;
 
-    match state.sync_roles(&member).await {
-        Ok(roles) => {
+    match state.pull_and_sync_roles(ctx.http(), &mut member).await {
+        Ok((roles, pulled)) => {
             let message =
                 String::from("✅ Successfully synced roles! If you were already online on Globed, please reconnect to the server to see the changes.\n\n")
-                + "* Synced roles: " + &roles.join(", ");
+                + "* Synced roles: " + &roles.join(", ")
+                + &pulled_roles_message(&pulled);
 
             ctx.reply(message).await?;
         }
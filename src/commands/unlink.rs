@@ -8,8 +8,24 @@ pub async fn unlink(ctx: Context<'_>) -> Result<(), CommandError> {
 
     ctx.defer().await?;
 
-    match state.unlink_user(member.user.id).await {
+    let account_id = state
+        .get_linked_gd_account(member.user.id)
+        .await
+        .ok()
+        .flatten();
+
+    match state.unlink_user(member.user.id, UnlinkReason::User).await {
         Ok(()) => {
+            state
+                .audit(
+                    AuditEvent::new(AuditAction::Unlink)
+                        .actor(ctx.author().id)
+                        .target(ctx.author().id)
+                        .account(account_id.map(|x| x.get()))
+                        .guild(ctx.guild_id()),
+                )
+                .await;
+
             ctx.reply("Successfully unlinked the account! If you were connected, you might have to reconnect to Globed to link again.").await?;
         }
 
@@ -0,0 +1,447 @@
+use std::{env, fmt::Display, path::PathBuf, time::Duration};
+
+use serde::Deserialize;
+
+use crate::logger::LogLevelFilter;
+
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+/// Validated bot configuration, built from the TOML config file and environment overrides.
+#[derive(Clone, Debug)]
+pub struct BotConfig {
+    pub token: String,
+    pub database_url: Option<String>,
+    pub guilds: Vec<GuildConfig>,
+    pub skip_sync_all: bool,
+    pub server: ServerConfig,
+    pub sync: SyncConfig,
+    pub log: LogConfig,
+}
+
+#[derive(Clone, Debug)]
+pub struct GuildConfig {
+    pub id: u64,
+    /// Channel where the bot posts links, unlinks, role mapping changes and sync failures.
+    pub log_channel: Option<u64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    pub base_url: String,
+    pub password: String,
+    pub request_timeout: Duration,
+}
+
+#[derive(Clone, Debug)]
+pub struct SyncConfig {
+    pub retry: RetryConfig,
+    /// How often syncs that failed because the server was unreachable are retried.
+    pub queue_interval: Duration,
+    /// How long to collect role changes before syncing them in one request, zero syncs every change right away.
+    pub batch_window: Duration,
+    /// Maximum number of users sent in one request when syncing all members of a guild.
+    pub chunk_size: usize,
+    /// How often all members are synced in the background to fix drift, zero disables it.
+    pub reconcile_interval: Duration,
+    /// How long a member who left keeps their link, their roles are removed right away. Zero unlinks immediately.
+    pub unlink_grace_period: Duration,
+}
+
+/// Exponential backoff policy for retrying failed requests to the central server.
+#[derive(Clone, Debug)]
+pub struct RetryConfig {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    pub initial_delay: Duration,
+    /// Also caps how long a `Retry-After` from the server is honored.
+    pub max_delay: Duration,
+}
+
+#[derive(Clone, Debug)]
+pub struct LogConfig {
+    pub level: LogLevelFilter,
+    pub file: bool,
+}
+
+/// All problems found while loading the config, reported together.
+#[derive(Debug)]
+pub struct ConfigError(pub Vec<String>);
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid configuration:")?;
+        for err in &self.0 {
+            write!(f, "\n  - {err}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/* Raw config, as it is written in the file */
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawConfig {
+    token: Option<String>,
+    database_url: Option<String>,
+    guilds: Vec<RawGuildConfig>,
+    skip_sync_all: Option<bool>,
+    server: RawServerConfig,
+    sync: RawSyncConfig,
+    log: RawLogConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawGuildConfig {
+    id: u64,
+    log_channel: Option<u64>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawServerConfig {
+    base_url: Option<String>,
+    password: Option<String>,
+    request_timeout_secs: Option<u64>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawSyncConfig {
+    retry: RawRetryConfig,
+    queue_interval_secs: Option<u64>,
+    batch_window_ms: Option<u64>,
+    chunk_size: Option<usize>,
+    reconcile_interval_mins: Option<u64>,
+    unlink_grace_period_mins: Option<u64>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawRetryConfig {
+    max_attempts: Option<u32>,
+    initial_delay_ms: Option<u64>,
+    max_delay_ms: Option<u64>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawLogConfig {
+    level: Option<String>,
+    file: Option<bool>,
+}
+
+impl BotConfig {
+    /// Loads the config from the file in `BOT_CONFIG` (or `config.toml`), then applies environment overrides.
+    /// A missing default config file is not an error, everything can be passed through the environment instead.
+    pub fn load() -> Result<Self, ConfigError> {
+        let (path, explicit) = match env::var("BOT_CONFIG") {
+            Ok(path) => (PathBuf::from(path), true),
+            Err(_) => (PathBuf::from(DEFAULT_CONFIG_PATH), false),
+        };
+
+        let raw = match std::fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str::<RawConfig>(&contents).map_err(|e| {
+                ConfigError(vec![format!(
+                    "failed to parse config file '{}': {e}",
+                    path.display()
+                )])
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !explicit => RawConfig::default(),
+            Err(e) => {
+                return Err(ConfigError(vec![format!(
+                    "failed to read config file '{}': {e}",
+                    path.display()
+                )]));
+            }
+        };
+
+        Self::from_raw(raw, |name| env::var(name).ok())
+    }
+
+    /// Builds the config from the contents of a config file, with environment variables looked up through `env`.
+    pub fn from_toml(
+        contents: &str,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, ConfigError> {
+        let raw = toml::from_str::<RawConfig>(contents)
+            .map_err(|e| ConfigError(vec![format!("failed to parse config file: {e}")]))?;
+
+        Self::from_raw(raw, env)
+    }
+
+    fn from_raw(
+        mut raw: RawConfig,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, ConfigError> {
+        let mut errors = Vec::new();
+
+        // apply environment overrides
+
+        if let Some(token) = env("BOT_TOKEN") {
+            raw.token = Some(token);
+        }
+
+        if let Some(url) = env("DATABASE_URL") {
+            raw.database_url = Some(url);
+        }
+
+        if let Some(url) = env("BOT_BASE_URL") {
+            raw.server.base_url = Some(url);
+        }
+
+        if let Some(password) = env("BOT_SERVER_PASSWORD") {
+            raw.server.password = Some(password);
+        }
+
+        if let Some(level) = env("BOT_LOG_LEVEL") {
+            raw.log.level = Some(level);
+        }
+
+        // comma separated list of guild ids, replaces all guilds from the config file
+        // guilds that are also in the config file keep their settings
+        if let Some(value) = env("BOT_SERVER_ID") {
+            let file_guilds = std::mem::take(&mut raw.guilds);
+
+            for id in value.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+                match id.parse() {
+                    Ok(id) => raw.guilds.push(RawGuildConfig {
+                        id,
+                        log_channel: file_guilds
+                            .iter()
+                            .find(|g| g.id == id)
+                            .and_then(|g| g.log_channel),
+                    }),
+                    Err(e) => errors.push(format!("BOT_SERVER_ID: invalid guild id '{id}': {e}")),
+                }
+            }
+        }
+
+        if let Some(value) = env("BOT_NO_FILE_LOG") {
+            match parse_flag(&value) {
+                Some(no_file) => raw.log.file = Some(!no_file),
+                None => errors.push(format!(
+                    "BOT_NO_FILE_LOG: expected a boolean, got '{value}'"
+                )),
+            }
+        }
+
+        // any value other than "0" skips, as it always did
+        if let Some(value) = env("BOT_SKIP_SYNC_ALL") {
+            raw.skip_sync_all = Some(value != "0");
+        }
+
+        // validate
+
+        let token = required(raw.token, "token", "BOT_TOKEN", &mut errors);
+
+        if raw.guilds.is_empty() {
+            errors.push(missing("guilds", "BOT_SERVER_ID"));
+        }
+
+        let mut guilds: Vec<GuildConfig> = Vec::with_capacity(raw.guilds.len());
+        for guild in raw.guilds {
+            if guild.id == 0 {
+                errors.push("guilds: guild id must not be 0".to_owned());
+            } else if guilds.iter().any(|g| g.id == guild.id) {
+                errors.push(format!(
+                    "guilds: guild {} is listed more than once",
+                    guild.id
+                ));
+            } else if guild.log_channel == Some(0) {
+                errors.push(format!(
+                    "guilds: log channel of guild {} must not be 0",
+                    guild.id
+                ));
+            } else {
+                guilds.push(GuildConfig {
+                    id: guild.id,
+                    log_channel: guild.log_channel,
+                });
+            }
+        }
+
+        let mut base_url = required(
+            raw.server.base_url,
+            "server.base_url",
+            "BOT_BASE_URL",
+            &mut errors,
+        );
+        if !base_url.is_empty()
+            && !base_url.starts_with("http://")
+            && !base_url.starts_with("https://")
+        {
+            errors.push(format!(
+                "server.base_url: expected an http:// or https:// URL, got '{base_url}'"
+            ));
+        }
+
+        while base_url.ends_with('/') {
+            base_url.pop();
+        }
+
+        let password = required(
+            raw.server.password,
+            "server.password",
+            "BOT_SERVER_PASSWORD",
+            &mut errors,
+        );
+
+        let request_timeout_secs = raw.server.request_timeout_secs.unwrap_or(30);
+        if request_timeout_secs == 0 {
+            errors.push("server.request_timeout_secs: must be greater than 0".to_owned());
+        }
+
+        let retry = &raw.sync.retry;
+        let max_attempts = retry.max_attempts.unwrap_or(4);
+        let initial_delay_ms = retry.initial_delay_ms.unwrap_or(500);
+        let max_delay_ms = retry.max_delay_ms.unwrap_or(30_000);
+
+        if max_attempts == 0 {
+            errors.push("sync.retry.max_attempts: must be at least 1".to_owned());
+        }
+
+        if max_delay_ms < initial_delay_ms {
+            errors.push(
+                "sync.retry.max_delay_ms: must not be smaller than sync.retry.initial_delay_ms"
+                    .to_owned(),
+            );
+        }
+
+        let queue_interval_secs = raw.sync.queue_interval_secs.unwrap_or(30);
+        if queue_interval_secs == 0 {
+            errors.push("sync.queue_interval_secs: must be greater than 0".to_owned());
+        }
+
+        let chunk_size = raw.sync.chunk_size.unwrap_or(200);
+        if chunk_size == 0 {
+            errors.push("sync.chunk_size: must be greater than 0".to_owned());
+        }
+
+        let reconcile_interval_mins = raw.sync.reconcile_interval_mins.unwrap_or(360);
+        let reconcile_interval = minutes(reconcile_interval_mins).unwrap_or_else(|| {
+            errors.push(format!(
+                "sync.reconcile_interval_mins: {reconcile_interval_mins} is too large, at most {MAX_MINUTES} (a year)"
+            ));
+            Duration::ZERO
+        });
+
+        let unlink_grace_period_mins = raw.sync.unlink_grace_period_mins.unwrap_or(1440);
+        let unlink_grace_period = minutes(unlink_grace_period_mins).unwrap_or_else(|| {
+            errors.push(format!(
+                "sync.unlink_grace_period_mins: {unlink_grace_period_mins} is too large, at most {MAX_MINUTES} (a year)"
+            ));
+            Duration::ZERO
+        });
+
+        let level = match raw.log.level {
+            None => {
+                if cfg!(debug_assertions) {
+                    LogLevelFilter::Trace
+                } else {
+                    LogLevelFilter::Info
+                }
+            }
+            Some(level) => parse_log_level(&level).unwrap_or_else(|| {
+                errors.push(format!(
+                    "log.level: invalid value '{level}', possible values are 'trace', 'debug', 'info', 'warn', 'error', and 'off'"
+                ));
+                LogLevelFilter::Info
+            }),
+        };
+
+        if !errors.is_empty() {
+            return Err(ConfigError(errors));
+        }
+
+        Ok(Self {
+            token,
+            database_url: raw.database_url,
+            guilds,
+            skip_sync_all: raw.skip_sync_all.unwrap_or(false),
+            server: ServerConfig {
+                base_url,
+                password,
+                request_timeout: Duration::from_secs(request_timeout_secs),
+            },
+            sync: SyncConfig {
+                retry: RetryConfig {
+                    max_attempts,
+                    initial_delay: Duration::from_millis(initial_delay_ms),
+                    max_delay: Duration::from_millis(max_delay_ms),
+                },
+                queue_interval: Duration::from_secs(queue_interval_secs),
+                batch_window: Duration::from_millis(raw.sync.batch_window_ms.unwrap_or(2000)),
+                chunk_size,
+                reconcile_interval,
+                unlink_grace_period,
+            },
+            log: LogConfig {
+                level,
+                file: raw.log.file.unwrap_or(true),
+            },
+        })
+    }
+}
+
+impl RetryConfig {
+    /// Delay before the given retry (1 for the first retry), doubling every time up to `max_delay`.
+    /// Jitter picks a random delay between half and the full value, so that retries don't all happen at once.
+    pub fn backoff_delay(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        let delay = self
+            .initial_delay
+            .saturating_mul(factor)
+            .min(self.max_delay);
+
+        let millis = delay.as_millis() as u64;
+        Duration::from_millis(fastrand::u64(millis / 2..=millis))
+    }
+}
+
+fn missing(key: &str, env_var: &str) -> String {
+    format!(
+        "{key}: not set (set it in the config file or with the '{env_var}' environment variable)"
+    )
+}
+
+fn required(value: Option<String>, key: &str, env_var: &str, errors: &mut Vec<String>) -> String {
+    match value {
+        Some(v) if !v.is_empty() => v,
+        _ => {
+            errors.push(missing(key, env_var));
+            String::new()
+        }
+    }
+}
+
+// longer than this is surely a typo, and timers that far in the future can overflow
+const MAX_MINUTES: u64 = 365 * 24 * 60;
+
+fn minutes(mins: u64) -> Option<Duration> {
+    (mins <= MAX_MINUTES).then(|| Duration::from_secs(mins * 60))
+}
+
+fn parse_flag(value: &str) -> Option<bool> {
+    match &*value.trim().to_lowercase() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" | "" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_log_level(level: &str) -> Option<LogLevelFilter> {
+    match &*level.to_lowercase() {
+        "trace" => Some(LogLevelFilter::Trace),
+        "debug" => Some(LogLevelFilter::Debug),
+        "info" => Some(LogLevelFilter::Info),
+        "warn" => Some(LogLevelFilter::Warn),
+        "error" => Some(LogLevelFilter::Error),
+        "off" | "none" => Some(LogLevelFilter::Off),
+        _ => None,
+    }
+}
@@ -1,9 +1,134 @@
 // use sqlx::prelude::FromRow;
 
+use log::warn;
+
+use crate::{
+    rule::Rule,
+    serenity::{Member, RoleId},
+};
+
+/// `discord_id` of mappings for server boosters rather than a discord role.
+pub const BOOSTER_ROLE_ID: i64 = 0;
+
 #[derive(Clone, Debug)]
 pub struct Role {
+    pub guild_id: i64,
     pub id: String,
     pub discord_id: i64,
+    pub direction: String,
+    pub rule: Option<String>,
+}
+
+impl Role {
+    /// Falls back to the column default for unknown values.
+    pub fn direction(&self) -> SyncDirection {
+        SyncDirection::from_db(&self.direction).unwrap_or_else(|| {
+            warn!(
+                "Unknown sync direction {:?} of role {}, treating it as discord to globed",
+                self.direction, self.id
+            );
+            SyncDirection::DiscordToGlobed
+        })
+    }
+
+    pub fn is_booster(&self) -> bool {
+        self.discord_id == BOOSTER_ROLE_ID
+    }
+
+    pub fn rule(&self) -> Option<Rule> {
+        let rule = self.rule.as_deref()?;
+
+        match Rule::parse(rule) {
+            Ok(x) => Some(x),
+            Err(e) => {
+                warn!("Ignoring invalid rule of role {}: {e}", self.id);
+                None
+            }
+        }
+    }
+
+    /// Whether a member should have this globed role, by its rule if it has one, otherwise by the mapped discord role.
+    pub fn granted_to(&self, member: &Member) -> bool {
+        match self.rule() {
+            Some(rule) => rule.matches(member),
+            None if self.is_booster() => member.premium_since.is_some(),
+            None => member.roles.contains(&RoleId::new(self.discord_id as u64)),
+        }
+    }
+
+    /// Discord roles whose changes can affect whether a member gets this globed role.
+    pub fn watched_roles(&self) -> Vec<RoleId> {
+        if !self.direction().to_globed() {
+            return Vec::new();
+        }
+
+        match self.rule() {
+            Some(rule) => rule.roles(),
+            None if self.is_booster() => Vec::new(),
+            None => vec![RoleId::new(self.discord_id as u64)],
+        }
+    }
+
+    /// Whether boosting the server can affect whether a member gets this globed role.
+    pub fn watches_booster(&self) -> bool {
+        if !self.direction().to_globed() {
+            return false;
+        }
+
+        match self.rule() {
+            Some(rule) => rule.uses_booster(),
+            None => self.is_booster(),
+        }
+    }
+
+    /// How the discord side of the mapping is shown in messages.
+    pub fn discord_mention(&self) -> String {
+        if self.is_booster() {
+            "Server Booster".to_owned()
+        } else {
+            format!("<@&{}>", self.discord_id)
+        }
+    }
+}
+
+/// Which way a role mapping is synced.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum SyncDirection {
+    #[name = "Discord → Globed"]
+    DiscordToGlobed,
+    #[name = "Globed → Discord"]
+    GlobedToDiscord,
+    #[name = "Both ways"]
+    Both,
+}
+
+impl SyncDirection {
+    pub fn from_db(value: &str) -> Option<Self> {
+        Some(match value {
+            "discord_to_globed" => Self::DiscordToGlobed,
+            "globed_to_discord" => Self::GlobedToDiscord,
+            "both" => Self::Both,
+            _ => return None,
+        })
+    }
+
+    pub fn as_db(self) -> &'static str {
+        match self {
+            Self::DiscordToGlobed => "discord_to_globed",
+            Self::GlobedToDiscord => "globed_to_discord",
+            Self::Both => "both",
+        }
+    }
+
+    /// Whether discord roles are pushed to the globed server.
+    pub fn to_globed(self) -> bool {
+        self != Self::GlobedToDiscord
+    }
+
+    /// Whether globed roles are mirrored back onto discord.
+    pub fn to_discord(self) -> bool {
+        self != Self::DiscordToGlobed
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -12,3 +137,126 @@ pub struct LinkedUser {
     pub id: i64,
     pub gd_account_id: i64,
 }
+
+/// A link, either active or from the history, see [`LinkHistoryEntry::is_active`].
+#[derive(Clone, Debug)]
+pub struct LinkHistoryEntry {
+    pub id: i64,
+    pub gd_account_id: i64,
+    pub linked_at: i64,
+    pub unlinked_at: Option<i64>,
+    pub unlink_reason: Option<String>,
+}
+
+impl LinkHistoryEntry {
+    pub fn is_active(&self) -> bool {
+        self.unlinked_at.is_none()
+    }
+
+    pub fn reason(&self) -> Option<UnlinkReason> {
+        self.unlink_reason
+            .as_deref()
+            .and_then(UnlinkReason::from_db)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnlinkReason {
+    /// The user unlinked themselves with `/unlink`.
+    User,
+    Admin,
+    LeftGuild,
+    Banned,
+    /// Moved to another discord user with `/admin transfer`.
+    Transferred,
+}
+
+impl UnlinkReason {
+    pub fn from_db(value: &str) -> Option<Self> {
+        Some(match value {
+            "self" => Self::User,
+            "admin" => Self::Admin,
+            "left_guild" => Self::LeftGuild,
+            "banned" => Self::Banned,
+            "transferred" => Self::Transferred,
+            _ => return None,
+        })
+    }
+
+    pub fn as_db(self) -> &'static str {
+        match self {
+            Self::User => "self",
+            Self::Admin => "admin",
+            Self::LeftGuild => "left_guild",
+            Self::Banned => "banned",
+            Self::Transferred => "transferred",
+        }
+    }
+}
+
+impl std::fmt::Display for UnlinkReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::User => "unlinked by themselves",
+            Self::Admin => "unlinked by an admin",
+            Self::LeftGuild => "left the server",
+            Self::Banned => "banned",
+            Self::Transferred => "transferred",
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BlockedAccount {
+    pub account_id: i64,
+    pub blocked_at: i64,
+    pub blocked_by: Option<i64>,
+    pub banned_user_id: Option<i64>,
+    pub reason: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct LinkConflict {
+    pub created_at: i64,
+    pub account_id: i64,
+    pub claimant_id: i64,
+    pub owner_id: i64,
+}
+
+/// Conflicts of one GD account, see [`crate::state::BotState::get_contested_accounts`].
+#[derive(Clone, Debug)]
+pub struct ContestedAccount {
+    pub account_id: i64,
+    pub attempts: i64,
+    pub claimants: i64,
+    pub last_attempt_at: i64,
+}
+
+#[derive(Clone, Debug)]
+pub struct QueuedSync {
+    pub account_id: i64,
+    pub keep: String,
+    pub remove: String,
+    pub queued_at: i64,
+    pub attempts: i64,
+    pub version: i64,
+}
+
+#[derive(Clone, Debug)]
+pub struct SyncedRoles {
+    pub account_id: i64,
+    pub roles: String,
+    pub synced_at: i64,
+}
+
+#[derive(Clone, Debug)]
+pub struct AuditLogEntry {
+    pub id: i64,
+    pub created_at: i64,
+    pub action: String,
+    pub actor_id: Option<i64>,
+    pub target_id: Option<i64>,
+    pub account_id: Option<i64>,
+    pub guild_id: Option<i64>,
+    pub details: Option<String>,
+}
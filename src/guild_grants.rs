@@ -0,0 +1,123 @@
+//! Globed roles each guild grants to an account, stored in the `guild_grants` table.
+//! A globed role can be mapped in several guilds, a sync built from one guild must not remove it
+//! while another guild still grants it.
+
+use std::collections::HashSet;
+
+use crate::{
+    serenity::GuildId,
+    state::{BotState, RoleSyncRequest},
+};
+
+impl BotState {
+    /// Records the roles a request built in `guild_id` keeps, and drops roles that another guild
+    /// still grants from its `remove` list.
+    pub(crate) async fn apply_guild_grants(
+        &self,
+        guild_id: GuildId,
+        req: &mut RoleSyncRequest,
+    ) -> Result<(), sqlx::Error> {
+        let guild_id_int = guild_id.get() as i64;
+        let account_id = req.account_id as i64;
+
+        let mut tx = self.database.begin().await?;
+
+        sqlx::query!(
+            "DELETE FROM guild_grants WHERE guild_id = ? AND account_id = ?",
+            guild_id_int,
+            account_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        for role in &req.keep {
+            sqlx::query!(
+                "INSERT OR IGNORE INTO guild_grants (guild_id, account_id, role_id) VALUES (?, ?, ?)",
+                guild_id_int,
+                account_id,
+                role
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        let granted = self
+            .granted_by_other_guilds(guild_id, req.account_id)
+            .await?;
+        req.remove.retain(|role| !granted.contains(role));
+
+        Ok(())
+    }
+
+    /// Roles that guilds other than `guild_id` grant to an account.
+    pub async fn granted_by_other_guilds(
+        &self,
+        guild_id: GuildId,
+        account_id: i32,
+    ) -> Result<HashSet<String>, sqlx::Error> {
+        let guild_id = guild_id.get() as i64;
+        let account_id = account_id as i64;
+
+        let roles = sqlx::query_scalar!(
+            "SELECT role_id FROM guild_grants WHERE account_id = ? AND guild_id != ?",
+            account_id,
+            guild_id
+        )
+        .fetch_all(&self.database)
+        .await?;
+
+        Ok(roles.into_iter().collect())
+    }
+
+    /// Forgets what a guild grants to an account, when the member left it.
+    pub(crate) async fn clear_guild_grants(
+        &self,
+        guild_id: GuildId,
+        account_id: i32,
+    ) -> Result<(), sqlx::Error> {
+        let guild_id = guild_id.get() as i64;
+        let account_id = account_id as i64;
+
+        sqlx::query!(
+            "DELETE FROM guild_grants WHERE guild_id = ? AND account_id = ?",
+            guild_id,
+            account_id
+        )
+        .execute(&self.database)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Forgets what every guild grants to an account, when it gets unlinked or moved to another user.
+    pub(crate) async fn clear_account_grants(&self, account_id: i32) -> Result<(), sqlx::Error> {
+        let account_id = account_id as i64;
+
+        sqlx::query!("DELETE FROM guild_grants WHERE account_id = ?", account_id)
+            .execute(&self.database)
+            .await?;
+
+        Ok(())
+    }
+
+    // a removed mapping grants nothing anymore
+    pub(crate) async fn clear_role_grants(
+        &self,
+        guild_id: GuildId,
+        globed_role_id: &str,
+    ) -> Result<(), sqlx::Error> {
+        let guild_id = guild_id.get() as i64;
+
+        sqlx::query!(
+            "DELETE FROM guild_grants WHERE guild_id = ? AND role_id = ?",
+            guild_id,
+            globed_role_id
+        )
+        .execute(&self.database)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,30 @@
+pub use poise::serenity_prelude as serenity;
+
+pub mod audit;
+pub mod backend;
+pub mod batcher;
+pub mod blocklist;
+pub mod commands;
+pub mod config;
+pub mod db;
+pub mod guild_grants;
+pub mod link_attempts;
+pub mod link_conflicts;
+pub mod log_channel;
+pub mod logger;
+#[cfg(feature = "mock-server")]
+pub mod mock_server;
+pub mod pending_unlinks;
+pub mod pull_roles;
+pub mod reconcile;
+pub mod rule;
+pub mod state;
+pub mod sync_queue;
+pub mod synced_roles;
+
+use std::sync::Arc;
+
+use commands::CommandError;
+use state::BotState;
+
+pub type Context<'a> = poise::Context<'a, Arc<BotState>, CommandError>;
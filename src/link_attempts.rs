@@ -0,0 +1,216 @@
+//! Throttling of `/link`, so that link codes can't be guessed by trying them one after another.
+//! Failed attempts are counted per discord user, which decides how long their next attempt has to wait.
+//! They are also counted per GD username, but only to alert the log channel when many users fail on it,
+//! locking the username would lock out its owner too.
+
+use std::time::Duration;
+
+use time::OffsetDateTime;
+
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    serenity::{Member, UserId},
+    state::BotState,
+};
+
+/// Failed attempts allowed before any cooldown applies, enough for a few typos.
+pub const FREE_ATTEMPTS: i64 = 3;
+
+/// Failed attempts after which linking is locked for [`LOCKOUT`], the log channel is alerted once this is reached.
+pub const LOCKOUT_ATTEMPTS: i64 = 10;
+
+pub const LOCKOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+const INITIAL_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Failures are forgotten once nothing failed for this long.
+const FORGET_AFTER: Duration = LOCKOUT;
+
+/// How long to wait after this many failed attempts, doubling with every failure until the lockout.
+pub fn link_attempt_cooldown(failures: i64) -> Option<Duration> {
+    if failures < FREE_ATTEMPTS {
+        None
+    } else if failures >= LOCKOUT_ATTEMPTS {
+        Some(LOCKOUT)
+    } else {
+        let doublings = (failures - FREE_ATTEMPTS) as u32;
+        Some((INITIAL_COOLDOWN * 2u32.pow(doublings)).min(LOCKOUT))
+    }
+}
+
+fn user_subject(user_id: UserId) -> String {
+    user_id.get().to_string()
+}
+
+fn username_subject(username: &str) -> String {
+    username.trim().to_ascii_lowercase()
+}
+
+impl BotState {
+    /// Checks whether a user may try to link this username now, and if so counts the attempt right away.
+    /// Both happen in one transaction, so that parallel attempts can't all get past the check before any of them is counted.
+    /// Returns how long the user has to wait, or `None` if the attempt was counted and may go ahead.
+    /// A successful attempt is forgotten again with [`Self::clear_user_link_attempts`], one that didn't check a code
+    /// with [`Self::uncount_link_attempt`].
+    pub async fn start_link_attempt(
+        &self,
+        member: &Member,
+        username: &str,
+    ) -> Result<Option<Duration>, sqlx::Error> {
+        let user_id = member.user.id;
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let forget_before = now - FORGET_AFTER.as_secs() as i64;
+
+        let user = user_subject(user_id);
+        let username = username_subject(username);
+
+        // takes the write lock right away, a deferred transaction would only take it after the check
+        let mut tx = self.database.begin_with("BEGIN IMMEDIATE").await?;
+
+        // only the user's own failures lock them out, failures of others on the same username
+        // must not keep its owner from linking it
+        let row = sqlx::query!(
+            "SELECT failures, last_failure_at FROM link_attempts WHERE kind = 'user' AND subject = ?",
+            user
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let wait = row.and_then(|row| {
+            let cooldown = link_attempt_cooldown(row.failures)?;
+            let retry_at = row.last_failure_at + cooldown.as_secs() as i64;
+
+            (retry_at > now).then(|| Duration::from_secs((retry_at - now) as u64))
+        });
+
+        if wait.is_some() {
+            return Ok(wait);
+        }
+
+        let mut alerts = Vec::new();
+
+        for (kind, subject) in [("user", &user), ("username", &username)] {
+            let row = sqlx::query!(
+                "INSERT INTO link_attempts (kind, subject, failures, last_failure_at) VALUES (?, ?, 1, ?)
+                ON CONFLICT (kind, subject) DO UPDATE SET
+                    failures = CASE WHEN last_failure_at < ? THEN 1 ELSE failures + 1 END,
+                    alerted = CASE WHEN last_failure_at < ? THEN 0 ELSE alerted END,
+                    last_failure_at = excluded.last_failure_at
+                RETURNING failures, alerted",
+                kind,
+                subject,
+                now,
+                forget_before,
+                forget_before
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+
+            if row.failures >= LOCKOUT_ATTEMPTS && row.alerted == 0 {
+                sqlx::query!(
+                    "UPDATE link_attempts SET alerted = 1 WHERE kind = ? AND subject = ?",
+                    kind,
+                    subject
+                )
+                .execute(&mut *tx)
+                .await?;
+
+                alerts.push(kind);
+            }
+        }
+
+        tx.commit().await?;
+
+        if !alerts.is_empty() {
+            let hours = LOCKOUT.as_secs() / 3600;
+
+            // the username isn't locked, only the user who made the last attempt is named
+            let details = match alerts.as_slice() {
+                ["user"] => format!(
+                    "{LOCKOUT_ATTEMPTS} failed link attempts, locked the user for {hours} hours"
+                ),
+                ["username"] => format!(
+                    "{LOCKOUT_ATTEMPTS} failed link attempts on username `{username}`, the username is not locked"
+                ),
+                _ => format!(
+                    "{LOCKOUT_ATTEMPTS} failed link attempts on username `{username}`, locked the user for {hours} hours"
+                ),
+            };
+
+            self.audit(
+                AuditEvent::new(AuditAction::LinkLockout)
+                    .actor(user_id)
+                    .guild(member.guild_id)
+                    .details(details),
+            )
+            .await;
+        }
+
+        Ok(None)
+    }
+
+    /// Takes back an attempt counted by [`Self::start_link_attempt`] that never got to check a code, for example because the server was down.
+    pub async fn uncount_link_attempt(
+        &self,
+        user_id: UserId,
+        username: &str,
+    ) -> Result<(), sqlx::Error> {
+        let user = user_subject(user_id);
+        let username = username_subject(username);
+
+        let mut tx = self.database.begin().await?;
+
+        // dropping below the lockout again allows another alert once it is reached
+        sqlx::query!(
+            "UPDATE link_attempts SET
+                failures = failures - 1,
+                alerted = CASE WHEN failures - 1 < ? THEN 0 ELSE alerted END
+            WHERE failures > 0 AND ((kind = 'user' AND subject = ?) OR (kind = 'username' AND subject = ?))",
+            LOCKOUT_ATTEMPTS,
+            user,
+            username
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "DELETE FROM link_attempts WHERE failures <= 0 AND ((kind = 'user' AND subject = ?) OR (kind = 'username' AND subject = ?))",
+            user,
+            username
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Forgets failed attempts of a user, after linking successfully or when an admin clears a lockout.
+    pub async fn clear_user_link_attempts(&self, user_id: UserId) -> Result<bool, sqlx::Error> {
+        let user = user_subject(user_id);
+
+        let result = sqlx::query!(
+            "DELETE FROM link_attempts WHERE kind = 'user' AND subject = ?",
+            user
+        )
+        .execute(&self.database)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Forgets failed attempts for a GD username.
+    pub async fn clear_username_link_attempts(&self, username: &str) -> Result<bool, sqlx::Error> {
+        let username = username_subject(username);
+
+        let result = sqlx::query!(
+            "DELETE FROM link_attempts WHERE kind = 'username' AND subject = ?",
+            username
+        )
+        .execute(&self.database)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
@@ -0,0 +1,107 @@
+//! Attempts to link a GD account that is already linked to someone else, stored in the `link_conflicts` table.
+//! Accounts that keep getting claimed by other people can point to account sharing or impersonation.
+
+use std::time::Duration;
+
+use time::OffsetDateTime;
+
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    db::{ContestedAccount, LinkConflict},
+    serenity::{Member, UserId},
+    state::BotState,
+};
+
+/// The log channel is alerted when an account reaches this many conflicts within [`CONTEST_WINDOW`].
+pub const CONTEST_ALERT_THRESHOLD: i64 = 3;
+
+pub const CONTEST_WINDOW: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+impl BotState {
+    /// Records that `claimant` tried to link an account that is linked to `owner`.
+    pub async fn record_link_conflict(
+        &self,
+        account_id: i32,
+        claimant: &Member,
+        owner: UserId,
+    ) -> Result<(), sqlx::Error> {
+        let guild_id = claimant.guild_id;
+        let claimant = claimant.user.id;
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let since = now - CONTEST_WINDOW.as_secs() as i64;
+        let claimant_id = claimant.get() as i64;
+        let owner_id = owner.get() as i64;
+
+        // the count has to include this insert and no parallel one, or the threshold could be crossed twice or not at all
+        let mut tx = self.database.begin_with("BEGIN IMMEDIATE").await?;
+
+        sqlx::query!(
+            "INSERT INTO link_conflicts (created_at, account_id, claimant_id, owner_id) VALUES (?, ?, ?, ?)",
+            now,
+            account_id,
+            claimant_id,
+            owner_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let recent = sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM link_conflicts WHERE account_id = ? AND created_at >= ?",
+            account_id,
+            since
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        // only when the threshold is crossed, not for every conflict after it
+        if recent == CONTEST_ALERT_THRESHOLD {
+            self.audit(
+                AuditEvent::new(AuditAction::LinkContested)
+                    .actor(claimant)
+                    .target(owner)
+                    .account(account_id)
+                    .guild(guild_id)
+                    .details(format!(
+                        "claimed {recent} times in the last {} days while linked to <@{owner}>",
+                        CONTEST_WINDOW.as_secs() / 86400
+                    )),
+            )
+            .await;
+        }
+
+        Ok(())
+    }
+
+    /// Recent conflicts of one account, newest first.
+    pub async fn get_link_conflicts(
+        &self,
+        account_id: i32,
+        limit: u32,
+    ) -> Result<Vec<LinkConflict>, sqlx::Error> {
+        sqlx::query_as!(
+            LinkConflict,
+            "SELECT created_at, account_id, claimant_id, owner_id FROM link_conflicts WHERE account_id = ? ORDER BY id DESC LIMIT ?",
+            account_id,
+            limit
+        )
+        .fetch_all(&self.database)
+        .await
+    }
+
+    /// Accounts with the most conflicts, most contested first.
+    pub async fn get_contested_accounts(
+        &self,
+        limit: u32,
+    ) -> Result<Vec<ContestedAccount>, sqlx::Error> {
+        sqlx::query_as!(
+            ContestedAccount,
+            r#"SELECT account_id AS "account_id!", COUNT(*) AS "attempts!: i64", COUNT(DISTINCT claimant_id) AS "claimants!: i64", MAX(created_at) AS "last_attempt_at!: i64"
+            FROM link_conflicts GROUP BY account_id ORDER BY 2 DESC, 4 DESC LIMIT ?"#,
+            limit
+        )
+        .fetch_all(&self.database)
+        .await
+    }
+}
@@ -0,0 +1,281 @@
+//! Posts audit log events as embeds to the log channel of their guild, see `log_channel` in the config.
+//! Events are queued without waiting and posted by a background task, so a slow or failing
+//! channel never holds up or fails the command that caused the event.
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use log::warn;
+use parking_lot::Mutex as SyncMutex;
+use poise::ChoiceParameter;
+use tokio::{
+    sync::mpsc::{self, error::TrySendError},
+    task::JoinHandle,
+};
+
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    serenity::{self, ChannelId, CreateEmbed, CreateMessage, GuildId, Timestamp},
+    state::BotState,
+};
+
+/// Events waiting to be posted, more than this are dropped rather than piling up while discord is slow.
+const QUEUE_SIZE: usize = 100;
+
+/// Discord allows at most 10 embeds in one message.
+const MAX_EMBEDS_PER_MESSAGE: usize = 10;
+
+/// Discord rejects messages whose embeds have more than 6000 characters in total.
+const MAX_EMBED_CHARS_PER_MESSAGE: usize = 6000;
+
+/// How many rounds a message that failed to post is tried, before its events are dropped.
+const MAX_POST_ATTEMPTS: u32 = 3;
+
+/// Pause after each round of posts, events arriving meanwhile are bundled into the next message
+/// instead of sending one message per event.
+const POST_INTERVAL: Duration = Duration::from_secs(2);
+
+pub(crate) struct LogChannelQueue {
+    tx: mpsc::Sender<AuditEvent>,
+    rx: SyncMutex<Option<mpsc::Receiver<AuditEvent>>>,
+}
+
+impl LogChannelQueue {
+    pub(crate) fn new() -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+
+        Self {
+            tx,
+            rx: SyncMutex::new(Some(rx)),
+        }
+    }
+}
+
+impl BotState {
+    /// The log channel of a guild, if one is configured.
+    pub fn log_channel(&self, guild_id: GuildId) -> Option<ChannelId> {
+        self.config
+            .guilds
+            .iter()
+            .find(|g| g.id == guild_id.get())
+            .and_then(|g| g.log_channel)
+            .map(ChannelId::new)
+    }
+
+    /// Log channels an event gets posted to. Events that don't belong to a guild go to all of them.
+    pub fn log_channels_for(&self, event: &AuditEvent) -> Vec<ChannelId> {
+        match event.guild_id {
+            Some(guild_id) => self.log_channel(guild_id).into_iter().collect(),
+            None => self
+                .config
+                .guilds
+                .iter()
+                .filter_map(|g| g.log_channel)
+                .map(ChannelId::new)
+                .collect(),
+        }
+    }
+
+    /// Queues an event to be posted to its log channels, without waiting.
+    pub(crate) fn post_to_log_channel(&self, event: &AuditEvent) {
+        if self.log_channels_for(event).is_empty() {
+            return;
+        }
+
+        match self.log_queue.tx.try_send(event.clone()) {
+            Ok(()) => {}
+            Err(TrySendError::Full(event)) => {
+                warn!("Log channel queue is full, not posting {:?}", event.action);
+            }
+            // the poster is gone, nothing to post to
+            Err(TrySendError::Closed(_)) => {}
+        }
+    }
+
+    /// Takes the queue of events waiting to be posted, only the first caller gets it.
+    pub fn take_log_channel_events(&self) -> Option<mpsc::Receiver<AuditEvent>> {
+        self.log_queue.rx.lock().take()
+    }
+}
+
+fn action_colour(action: AuditAction) -> u32 {
+    match action {
+        AuditAction::Link | AuditAction::AdminLink | AuditAction::MemberRejoined => 0x57f287,
+        AuditAction::Unlink | AuditAction::AdminUnlink | AuditAction::MemberLeft => 0xfee75c,
+        AuditAction::Transfer
+        | AuditAction::RoleAdd
+        | AuditAction::RoleRemove
+        | AuditAction::RoleRule => 0x5865f2,
+        AuditAction::SyncFailed
+        | AuditAction::MemberBanned
+        | AuditAction::AccountBlocked
+        | AuditAction::LinkLockout
+        | AuditAction::LinkContested => 0xed4245,
+        AuditAction::MemberUnbanned | AuditAction::AccountUnblocked | AuditAction::LinkUnlock => {
+            0x57f287
+        }
+    }
+}
+
+/// The embed posted for an event.
+pub fn audit_embed(event: &AuditEvent) -> CreateEmbed {
+    let mut embed = CreateEmbed::new()
+        .title(event.action.name())
+        .colour(action_colour(event.action))
+        .timestamp(Timestamp::now());
+
+    if let Some(details) = &event.details {
+        // embed descriptions are limited to 4096 characters
+        let end = details.floor_char_boundary(1000);
+        embed = embed.description(&details[..end]);
+    }
+
+    if let Some(actor) = event.actor {
+        embed = embed.field("By", format!("<@{actor}>"), true);
+    }
+
+    if let Some(target) = event.target.filter(|t| Some(*t) != event.actor) {
+        embed = embed.field("User", format!("<@{target}>"), true);
+    }
+
+    if let Some(account_id) = event.account_id {
+        embed = embed.field("Account", account_id.to_string(), true);
+    }
+
+    embed
+}
+
+/// Characters of an embed that count towards discord's limit: title, description, field names and values, footer and author.
+pub fn embed_length(embed: &CreateEmbed) -> usize {
+    let Ok(value) = serde_json::to_value(embed) else {
+        return 0;
+    };
+
+    let chars = |value: &serde_json::Value| value.as_str().map_or(0, |s| s.chars().count());
+
+    let fields: usize = value["fields"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|field| chars(&field["name"]) + chars(&field["value"]))
+        .sum();
+
+    chars(&value["title"])
+        + chars(&value["description"])
+        + chars(&value["footer"]["text"])
+        + chars(&value["author"]["name"])
+        + fields
+}
+
+/// Splits embeds into messages, keeping each under discord's limits for the number and total length of embeds.
+pub fn log_messages(embeds: Vec<CreateEmbed>) -> Vec<Vec<CreateEmbed>> {
+    let mut messages = Vec::new();
+    let mut current = Vec::new();
+    let mut current_len = 0;
+
+    for embed in embeds {
+        let len = embed_length(&embed);
+
+        if !current.is_empty()
+            && (current.len() == MAX_EMBEDS_PER_MESSAGE
+                || current_len + len > MAX_EMBED_CHARS_PER_MESSAGE)
+        {
+            messages.push(std::mem::take(&mut current));
+            current_len = 0;
+        }
+
+        current.push(embed);
+        current_len += len;
+    }
+
+    if !current.is_empty() {
+        messages.push(current);
+    }
+
+    messages
+}
+
+// a message that failed to post, tried again in the next round
+struct UnsentMessage {
+    channel: ChannelId,
+    embeds: Vec<CreateEmbed>,
+    attempts: u32,
+}
+
+/// Starts the background task posting queued events to the log channels,
+/// returns `None` if no guild has a log channel.
+pub fn spawn_log_channel_poster(
+    state: Arc<BotState>,
+    http: Arc<serenity::Http>,
+) -> Option<JoinHandle<()>> {
+    if state.config.guilds.iter().all(|g| g.log_channel.is_none()) {
+        return None;
+    }
+
+    let mut rx = state.take_log_channel_events()?;
+
+    Some(tokio::spawn(async move {
+        let mut unsent: Vec<UnsentMessage> = Vec::new();
+
+        loop {
+            let mut events = Vec::new();
+
+            // with nothing to retry, wait for the next event
+            if unsent.is_empty() {
+                match rx.recv().await {
+                    Some(event) => events.push(event),
+                    None => break,
+                }
+            }
+
+            while let Ok(event) = rx.try_recv() {
+                events.push(event);
+            }
+
+            let mut embeds: HashMap<ChannelId, Vec<CreateEmbed>> = HashMap::new();
+            for event in &events {
+                for channel in state.log_channels_for(event) {
+                    embeds.entry(channel).or_default().push(audit_embed(event));
+                }
+            }
+
+            // retried messages go first, to keep events in order
+            let mut messages = std::mem::take(&mut unsent);
+            for (channel, embeds) in embeds {
+                messages.extend(
+                    log_messages(embeds)
+                        .into_iter()
+                        .map(|embeds| UnsentMessage {
+                            channel,
+                            embeds,
+                            attempts: 0,
+                        }),
+                );
+            }
+
+            for mut message in messages {
+                // serenity waits out discord's rate limits before sending
+                let create = CreateMessage::new().embeds(message.embeds.clone());
+
+                if let Err(e) = message.channel.send_message(&http, create).await {
+                    message.attempts += 1;
+
+                    if message.attempts < MAX_POST_ATTEMPTS {
+                        warn!(
+                            "Failed to post to log channel {}, trying again: {e}",
+                            message.channel
+                        );
+                        unsent.push(message);
+                    } else {
+                        warn!(
+                            "Failed to post to log channel {}, dropping {} events: {e}",
+                            message.channel,
+                            message.embeds.len()
+                        );
+                    }
+                }
+            }
+
+            tokio::time::sleep(POST_INTERVAL).await;
+        }
+    }))
+}
@@ -1,21 +1,23 @@
-use std::env;
-
-pub use poise::serenity_prelude as serenity;
+use auto_role_bot::{
+    audit::{AuditAction, AuditEvent},
+    batcher::spawn_sync_batcher,
+    blocklist::BanOutcome,
+    commands::{self, CommandError},
+    config::BotConfig,
+    db::UnlinkReason,
+    log_channel::spawn_log_channel_poster,
+    logger::*,
+    pending_unlinks::spawn_unlink_finaliser,
+    reconcile::spawn_reconcile_task,
+    serenity,
+    state::{BotState, RoleSyncError},
+    sync_queue::spawn_sync_queue_worker,
+};
+use std::sync::Arc;
 
 use serenity::prelude::*;
 
-mod commands;
-mod db;
-mod logger;
-mod state;
-
-use commands::CommandError;
-use logger::*;
-use state::{BotState, RoleSyncError};
-
-pub type Context<'a> = poise::Context<'a, BotState, CommandError>;
-
-async fn on_error(error: poise::FrameworkError<'_, BotState, CommandError>) {
+async fn on_error(error: poise::FrameworkError<'_, Arc<BotState>, CommandError>) {
     match error {
         poise::FrameworkError::Setup { error, .. } => panic!("Failed to start bot: {:?}", error),
         poise::FrameworkError::Command { error, ctx, .. } => {
@@ -30,10 +32,10 @@ async fn on_error(error: poise::FrameworkError<'_, BotState, CommandError>) {
 }
 
 async fn event_handler(
-    _ctx: &serenity::Context,
+    ctx: &serenity::Context,
     event: &serenity::FullEvent,
-    _framework: poise::FrameworkContext<'_, BotState, CommandError>,
-    state: &BotState,
+    _framework: poise::FrameworkContext<'_, Arc<BotState>, CommandError>,
+    state: &Arc<BotState>,
 ) -> Result<(), CommandError> {
     match event {
         serenity::FullEvent::GuildMemberUpdate {
@@ -41,28 +43,34 @@ async fn event_handler(
             new: Some(new),
             event: _event,
         } => {
-            // check for the roles
-            let mut should_sync = false;
-            {
-                let watched = state.watched_roles.read();
-
-                if let Some(old_user) = old_if_available {
-                    // iterate over all watched roles, see if anything changed
-                    for role in &*watched {
-                        if new.roles.contains(role) != old_user.roles.contains(role) {
-                            should_sync = true;
-                            break;
-                        }
-                    }
-                } else {
-                    should_sync = true;
-                }
+            if !state.is_configured_guild(new.guild_id) {
+                return Ok(());
+            }
+
+            // the bot changed these roles itself to match globed, don't sync them back
+            if state.is_own_role_edit(new.guild_id, old_if_available.as_ref(), new) {
+                return Ok(());
             }
 
+            // check for the roles
+            let should_sync = match old_if_available {
+                Some(old_user) => state.is_watched_role_changed(new.guild_id, old_user, new),
+                None => true,
+            };
+
             if should_sync {
-                match state.sync_roles(new).await {
-                    Ok(_) | Err(RoleSyncError::NotLinked) => {}
+                match state.request_auto_sync(new).await {
+                    Ok(()) | Err(RoleSyncError::NotLinked) => {}
                     Err(err) => {
+                        state
+                            .audit(
+                                AuditEvent::new(AuditAction::SyncFailed)
+                                    .target(new.user.id)
+                                    .guild(new.guild_id)
+                                    .details(err.to_string()),
+                            )
+                            .await;
+
                         return Err(CommandError::other(format!(
                             "Failed to auto sync user roles: {err}"
                         )));
@@ -72,13 +80,49 @@ async fn event_handler(
         }
 
         serenity::FullEvent::GuildMemberRemoval {
-            guild_id: _guild_id,
+            guild_id,
             user,
             member_data_if_available: _member,
         } => {
-            // if a user left, unlink and remove them
-            match state.unlink_user(user.id).await {
-                Ok(()) | Err(RoleSyncError::NotLinked) => {}
+            if !state.is_configured_guild(*guild_id) {
+                return Ok(());
+            }
+
+            let account_id = state.get_linked_gd_account(user.id).await.ok().flatten();
+
+            // if a user left, but is still in another guild, only remove roles from this guild,
+            // otherwise remove all roles and unlink them once the grace period is over
+            let (result, details) = if state.is_in_other_guild(&ctx.http, user.id, *guild_id).await
+            {
+                (
+                    state.strip_guild_roles(user.id, *guild_id).await,
+                    "still in another server, removed this server's roles",
+                )
+            } else if state.config.sync.unlink_grace_period.is_zero() {
+                (
+                    state.unlink_user(user.id, UnlinkReason::LeftGuild).await,
+                    "unlinked",
+                )
+            } else {
+                (
+                    state.start_unlink_grace(user.id, *guild_id).await,
+                    "removed roles, unlinking after the grace period",
+                )
+            };
+
+            match result {
+                Err(RoleSyncError::NotLinked) => {}
+                Ok(()) => {
+                    state
+                        .audit(
+                            AuditEvent::new(AuditAction::MemberLeft)
+                                .target(user.id)
+                                .account(account_id.map(|x| x.get()))
+                                .guild(*guild_id)
+                                .details(details),
+                        )
+                        .await;
+                }
                 Err(err) => {
                     return Err(CommandError::other(format!(
                         "Failed to unlink user that left the guild: {err}"
@@ -87,6 +131,133 @@ async fn event_handler(
             }
         }
 
+        serenity::FullEvent::GuildMemberAddition { new_member } => {
+            if !state.is_configured_guild(new_member.guild_id) {
+                return Ok(());
+            }
+
+            match state.restore_rejoined_member(&ctx.http, new_member).await {
+                Ok(false) | Err(RoleSyncError::NotLinked) => {}
+                Ok(true) => {
+                    let account_id = state
+                        .get_linked_gd_account(new_member.user.id)
+                        .await
+                        .ok()
+                        .flatten();
+
+                    state
+                        .audit(
+                            AuditEvent::new(AuditAction::MemberRejoined)
+                                .target(new_member.user.id)
+                                .account(account_id.map(|x| x.get()))
+                                .guild(new_member.guild_id)
+                                .details("rejoined during the grace period, kept linked"),
+                        )
+                        .await;
+                }
+                Err(err) => {
+                    return Err(CommandError::other(format!(
+                        "Failed to sync roles of a member that rejoined: {err}"
+                    )));
+                }
+            }
+        }
+
+        serenity::FullEvent::GuildBanAddition {
+            guild_id,
+            banned_user,
+        } => {
+            if !state.is_configured_guild(*guild_id) {
+                return Ok(());
+            }
+
+            let in_other_guild = state
+                .is_in_other_guild(&ctx.http, banned_user.id, *guild_id)
+                .await;
+
+            let reason = Some(format!("banned in {guild_id}"));
+
+            let (account_id, details) = match state
+                .ban_user(banned_user.id, *guild_id, in_other_guild, reason)
+                .await
+            {
+                Ok(BanOutcome::Ignored) => return Ok(()),
+                Ok(BanOutcome::RolesStripped(account_id)) => (
+                    account_id,
+                    "still in another server, removed the roles of this server",
+                ),
+                Ok(BanOutcome::Blocked {
+                    account_id,
+                    unlinked,
+                }) => (
+                    account_id,
+                    if unlinked {
+                        "unlinked and blocked the account"
+                    } else {
+                        "blocked the account they recently unlinked by leaving"
+                    },
+                ),
+                Err(err) => {
+                    return Err(CommandError::other(format!(
+                        "Failed to unlink banned user: {err}"
+                    )));
+                }
+            };
+
+            state
+                .audit(
+                    AuditEvent::new(AuditAction::MemberBanned)
+                        .target(banned_user.id)
+                        .account(account_id)
+                        .guild(*guild_id)
+                        .details(details),
+                )
+                .await;
+        }
+
+        serenity::FullEvent::GuildBanRemoval {
+            guild_id,
+            unbanned_user,
+        } => {
+            if !state.is_configured_guild(*guild_id) {
+                return Ok(());
+            }
+
+            match state.unban_user(unbanned_user.id).await {
+                Ok(accounts) => {
+                    for account_id in accounts {
+                        state
+                            .audit(
+                                AuditEvent::new(AuditAction::MemberUnbanned)
+                                    .target(unbanned_user.id)
+                                    .account(account_id)
+                                    .guild(*guild_id)
+                                    .details("unblocked the account"),
+                            )
+                            .await;
+                    }
+                }
+                Err(err) => {
+                    return Err(CommandError::other(format!(
+                        "Failed to unblock the account of an unbanned user: {err}"
+                    )));
+                }
+            }
+        }
+
+        serenity::FullEvent::InteractionCreate {
+            interaction: serenity::Interaction::Component(press),
+        } => {
+            if !press
+                .guild_id
+                .is_some_and(|guild_id| state.is_configured_guild(guild_id))
+            {
+                return Ok(());
+            }
+
+            commands::handle_panel_interaction(ctx, state, press).await?;
+        }
+
         _ => {}
     }
 
@@ -95,32 +266,36 @@ async fn event_handler(
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Setup logger
+    // Load config
 
-    let write_to_file = std::env::var("BOT_NO_FILE_LOG")
-        .map(|p| p.parse::<i32>().unwrap())
-        .unwrap_or(0)
-        == 0;
+    let config = BotConfig::load();
 
+    let write_to_file = config.as_ref().is_ok_and(|c| c.log.file);
     log::set_logger(Logger::instance("auto_role_bot", write_to_file)).unwrap();
 
-    if let Some(log_level) = get_log_level("BOT_LOG_LEVEL") {
-        log::set_max_level(log_level);
-    } else {
-        log::set_max_level(LogLevelFilter::Warn); // we have to print these logs somehow lol
-        error!("invalid value for the log level environment varaible");
-        warn!("hint: possible values are 'trace', 'debug', 'info', 'warn', 'error', and 'none'.");
-        std::process::exit(1);
-    }
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            log::set_max_level(LogLevelFilter::Warn); // we have to print these logs somehow lol
+            error!("Failed to load the config, found {} problem(s):", e.0.len());
+            for err in &e.0 {
+                error!("  - {err}");
+            }
+
+            warn!(
+                "hint: the config is read from 'config.toml' (or the file in 'BOT_CONFIG'), and every option can be overridden with environment variables."
+            );
+            std::process::exit(1);
+        }
+    };
 
-    let token = env::var("BOT_TOKEN")
-        .expect("No token set; please use the 'BOT_TOKEN' environment variable to pass it");
+    log::set_max_level(config.log.level);
 
     // connect to db
     let db = sqlx::sqlite::SqlitePoolOptions::new().max_connections(5);
 
-    let db = if let Ok(url) = env::var("DATABASE_URL") {
-        db.connect(&url).await
+    let db = if let Some(url) = &config.database_url {
+        db.connect(url).await
     } else {
         db.connect_with(
             sqlx::sqlite::SqliteConnectOptions::new()
@@ -130,7 +305,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .await
     };
 
-    let db = db.expect("Couldn't connect to database, make sure the 'db.sqlite' file exists in the current directory or specify the 'DATABASE_URL' environment variable with the sqlite database URL.");
+    let db = match db {
+        Ok(db) => db,
+        Err(e) => {
+            error!("Couldn't connect to database: {e}");
+            warn!(
+                "hint: by default 'db.sqlite' in the current directory is used, a different sqlite database URL can be set with 'database_url' in the config or the 'DATABASE_URL' environment variable."
+            );
+            std::process::exit(1);
+        }
+    };
 
     // run migrations
     if let Err(e) = sqlx::migrate!().run(&db).await {
@@ -138,7 +322,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     // start the discord bot
-    let state = BotState::new(db).await;
+    let state = Arc::new(BotState::new(&config, db).await?);
 
     let options = poise::FrameworkOptions {
         commands: vec![
@@ -150,9 +334,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         ],
         on_error: |error| Box::pin(on_error(error)),
         command_check: Some(|ctx| {
-            // only allow from a specific guild
+            // only allow from configured guilds
             Box::pin(async move {
-                if !ctx.guild_id().is_some_and(|g| g == ctx.data().guild_id) {
+                if ctx
+                    .guild_id()
+                    .is_none_or(|g| !ctx.data().is_configured_guild(g))
+                {
                     return Ok(false);
                 }
 
@@ -166,37 +353,60 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         ..Default::default()
     };
 
+    let skip_sync = config.skip_sync_all;
+
     let framework = poise::Framework::builder()
         .setup(move |ctx, ready, framework| {
             Box::pin(async move {
                 info!("Logged in as {}", ready.user.name);
 
-                // register commands
-                poise::builtins::register_in_guild(
-                    ctx,
-                    &framework.options().commands,
-                    state.guild_id,
-                )
-                .await?;
+                // register commands in every guild
+                for guild_id in &state.guild_ids {
+                    if let Err(e) = poise::builtins::register_in_guild(
+                        ctx,
+                        &framework.options().commands,
+                        *guild_id,
+                    )
+                    .await
+                    {
+                        warn!("Failed to register commands in guild {guild_id}: {e}");
+                    }
+                }
 
-                let skip_sync = env::var("BOT_SKIP_SYNC_ALL")
-                    .ok()
-                    .map(|x| x != "0")
-                    .unwrap_or(false);
+                // started first so that failures of the startup sync get posted too
+                spawn_log_channel_poster(state.clone(), ctx.http.clone());
 
                 if !skip_sync {
-                    info!("Attempting to sync all members.. (this may take some time)");
-
-                    match state.sync_all_members(&ctx.http).await {
-                        Ok(count) => {
-                            info!("Sync finished! Total {} users synced.", count);
-                        }
-                        Err(e) => {
-                            warn!("Failed to sync roles of members: {e}");
+                    let _guard = state.try_lock_mass_sync();
+
+                    for guild_id in &state.guild_ids {
+                        info!(
+                            "Attempting to sync all members of {guild_id}.. (this may take some time)"
+                        );
+
+                        match state.sync_all_members(&ctx.http, *guild_id, false).await {
+                            Ok(summary) if summary.is_complete() => {
+                                info!(
+                                    "Sync finished! Total {} users synced, {} unchanged.",
+                                    summary.succeeded, summary.unchanged
+                                );
+                            }
+                            Ok(summary) => {
+                                warn!("Sync finished with errors: {summary}");
+                            }
+                            Err(e) => {
+                                warn!("Failed to sync roles of members: {e}");
+                            }
                         }
                     }
                 }
 
+                // retry syncs that failed while the server was unreachable, also the ones from before a restart
+                spawn_sync_queue_worker(state.clone());
+                spawn_sync_batcher(state.clone());
+                spawn_reconcile_task(state.clone(), ctx.http.clone());
+                spawn_unlink_finaliser(state.clone());
+
                 Ok(state)
             })
         })
@@ -204,7 +414,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .build();
 
     let client = serenity::ClientBuilder::new(
-        token,
+        config.token,
         GatewayIntents::non_privileged() | GatewayIntents::GUILD_MEMBERS,
     )
     .framework(framework)
@@ -214,24 +424,3 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
-
-pub fn get_log_level(env_var: &str) -> Option<LogLevelFilter> {
-    std::env::var(env_var).map_or_else(
-        |_| {
-            Some(if cfg!(debug_assertions) {
-                LogLevelFilter::Trace
-            } else {
-                LogLevelFilter::Info
-            })
-        },
-        |level| match &*level.to_lowercase() {
-            "trace" => Some(LogLevelFilter::Trace),
-            "debug" => Some(LogLevelFilter::Debug),
-            "info" => Some(LogLevelFilter::Info),
-            "warn" => Some(LogLevelFilter::Warn),
-            "error" => Some(LogLevelFilter::Error),
-            "off" => Some(LogLevelFilter::Off),
-            _ => None,
-        },
-    )
-}
@@ -0,0 +1,270 @@
+//! Local stand-in for the Globed central server, serving `/gsp/lookup`, `/gsp/sync_roles` and `/gsp/roles`
+//! the same way [`HttpBackend`](crate::backend::HttpBackend) expects them.
+//! Used by the `mock-server` binary for development, and by integration tests. Only built with the `mock-server` feature.
+
+use std::{collections::VecDeque, net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use log::{info, warn};
+use parking_lot::Mutex as SyncMutex;
+use serde::Deserialize;
+use tokio::{net::TcpListener, task::JoinHandle};
+
+use crate::{
+    backend::{GlobedBackend, MemoryBackend, RoleSyncRequestData, UserRolesResponse},
+    state::{LinkError, RoleSyncError},
+};
+
+struct MockState {
+    password: String,
+    backend: MemoryBackend,
+    lookup_failures: SyncMutex<VecDeque<StatusCode>>,
+    forced_lookup_status: SyncMutex<Option<StatusCode>>,
+    forced_sync_status: SyncMutex<Option<StatusCode>>,
+    // every sync payload that passed the password check, accepted or not
+    received_syncs: SyncMutex<Vec<RoleSyncRequestData>>,
+}
+
+/// A running mock server, stops when dropped.
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<MockState>,
+    task: JoinHandle<()>,
+}
+
+#[derive(Deserialize)]
+struct RolesQuery {
+    account_id: i32,
+}
+
+#[derive(Deserialize)]
+struct LookupQuery {
+    username: String,
+    link_code: u32,
+    #[serde(default)]
+    bypass: bool,
+}
+
+impl MockServer {
+    /// Starts the server on the given address, use port 0 to pick a free port.
+    pub async fn start(addr: &str, password: &str) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+
+        let state = Arc::new(MockState {
+            password: password.to_owned(),
+            backend: MemoryBackend::new(),
+            lookup_failures: SyncMutex::new(VecDeque::new()),
+            forced_lookup_status: SyncMutex::new(None),
+            forced_sync_status: SyncMutex::new(None),
+            received_syncs: SyncMutex::new(Vec::new()),
+        });
+
+        let router = Router::new()
+            .route("/gsp/lookup", get(lookup))
+            .route("/gsp/sync_roles", post(sync_roles))
+            .route("/gsp/roles", get(get_roles))
+            .with_state(state.clone());
+
+        let task = tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, router).await {
+                warn!("Mock server stopped: {e}");
+            }
+        });
+
+        info!("Mock server listening on http://{addr}");
+
+        Ok(Self { addr, state, task })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Base URL to put in the bot config.
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Adds a GD account that can be linked with the given link code.
+    pub fn add_user(&self, account_id: i32, name: &str, link_code: u32) {
+        self.state.backend.add_user(account_id, name, link_code);
+    }
+
+    /// Makes the next lookup request fail with the given status.
+    pub fn fail_next_lookup(&self, status: StatusCode) {
+        self.state.lookup_failures.lock().push_back(status);
+    }
+
+    /// Makes the next sync request fail with the given status.
+    pub fn fail_next_sync(&self, status: StatusCode) {
+        self.state
+            .backend
+            .fail_next_sync(status, status.canonical_reason().unwrap_or("error"));
+    }
+
+    /// Makes the next sync request fail with 429, with an optional `Retry-After` header in seconds.
+    pub fn rate_limit_next_sync(&self, retry_after: Option<u64>) {
+        self.state
+            .backend
+            .fail_next_sync_with(RoleSyncError::RateLimited(
+                retry_after.map(Duration::from_secs),
+            ));
+    }
+
+    /// Makes every lookup request fail with the given status, until set back to `None`.
+    pub fn set_lookup_status(&self, status: Option<StatusCode>) {
+        *self.state.forced_lookup_status.lock() = status;
+    }
+
+    /// Makes every sync request fail with the given status, until set back to `None`.
+    pub fn set_sync_status(&self, status: Option<StatusCode>) {
+        *self.state.forced_sync_status.lock() = status;
+    }
+
+    /// Returns all sync requests received so far, including ones that were answered with an error.
+    pub fn sync_requests(&self) -> Vec<RoleSyncRequestData> {
+        self.state.received_syncs.lock().clone()
+    }
+
+    /// Returns the sync requests that were accepted and applied to the account roles.
+    pub fn accepted_sync_requests(&self) -> Vec<RoleSyncRequestData> {
+        self.state.backend.sync_requests()
+    }
+
+    /// Replaces the roles of an account, like staff changing them on the server would.
+    pub fn set_account_roles(&self, account_id: i32, roles: &[&str]) {
+        self.state.backend.set_account_roles(account_id, roles);
+    }
+
+    /// Returns the roles an account currently has, sorted.
+    pub fn account_roles(&self, account_id: i32) -> Vec<String> {
+        self.state.backend.account_roles(account_id)
+    }
+
+    /// Waits until the server stops, which only happens if it fails.
+    pub async fn wait(&mut self) {
+        let _ = (&mut self.task).await;
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn error_response(status: StatusCode) -> Response {
+    (status, status.canonical_reason().unwrap_or("error")).into_response()
+}
+
+fn is_authorized(state: &MockState, headers: &HeaderMap) -> bool {
+    headers
+        .get("Authorization")
+        .is_some_and(|value| value.as_bytes() == state.password.as_bytes())
+}
+
+async fn lookup(
+    State(state): State<Arc<MockState>>,
+    headers: HeaderMap,
+    Query(query): Query<LookupQuery>,
+) -> Response {
+    if !is_authorized(&state, &headers) {
+        return error_response(StatusCode::UNAUTHORIZED);
+    }
+
+    let failure = state.lookup_failures.lock().pop_front();
+    if let Some(status) = failure.or(*state.forced_lookup_status.lock()) {
+        return error_response(status);
+    }
+
+    let link_code = (!query.bypass).then_some(query.link_code);
+
+    match state.backend.lookup_user(&query.username, link_code).await {
+        Ok(user) => {
+            info!(
+                "Lookup of '{}' succeeded, account id {}",
+                query.username, user.account_id
+            );
+
+            Json(user).into_response()
+        }
+
+        Err(LinkError::UserNotFound) => {
+            info!("Lookup of '{}' failed, user not found", query.username);
+            error_response(StatusCode::NOT_FOUND)
+        }
+
+        Err(_) => error_response(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn sync_roles(
+    State(state): State<Arc<MockState>>,
+    headers: HeaderMap,
+    Json(data): Json<RoleSyncRequestData>,
+) -> Response {
+    if !is_authorized(&state, &headers) {
+        return error_response(StatusCode::UNAUTHORIZED);
+    }
+
+    state.received_syncs.lock().push(data.clone());
+
+    if let Some(status) = *state.forced_sync_status.lock() {
+        warn!(
+            "Rejecting role sync of {} users with {status}",
+            data.users.len()
+        );
+        return error_response(status);
+    }
+
+    match state.backend.sync_roles(&data).await {
+        Ok(()) => {
+            for user in &data.users {
+                info!(
+                    "Synced roles of {}, keep: {:?}, remove: {:?}",
+                    user.account_id, user.keep, user.remove
+                );
+            }
+
+            (StatusCode::OK, "ok").into_response()
+        }
+
+        Err(RoleSyncError::ServerUpdate((status, message))) => (status, message).into_response(),
+
+        Err(RoleSyncError::RateLimited(retry_after)) => {
+            let mut response = error_response(StatusCode::TOO_MANY_REQUESTS);
+            if let Some(after) = retry_after {
+                response
+                    .headers_mut()
+                    .insert("Retry-After", after.as_secs().into());
+            }
+
+            response
+        }
+
+        Err(_) => error_response(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn get_roles(
+    State(state): State<Arc<MockState>>,
+    headers: HeaderMap,
+    Query(query): Query<RolesQuery>,
+) -> Response {
+    if !is_authorized(&state, &headers) {
+        return error_response(StatusCode::UNAUTHORIZED);
+    }
+
+    Json(UserRolesResponse {
+        account_id: query.account_id,
+        roles: state.backend.account_roles(query.account_id),
+    })
+    .into_response()
+}
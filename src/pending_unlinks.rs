@@ -0,0 +1,150 @@
+//! Grace period for members who leave every configured guild, see `sync.unlink_grace_period_mins` in the config.
+//! Their Globed roles are removed right away, but the link is only ended once the grace period is over,
+//! so that someone who was kicked by mistake doesn't have to link again after rejoining.
+
+use std::{num::NonZeroI32, sync::Arc, time::Duration};
+
+use log::warn;
+use time::OffsetDateTime;
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    db::UnlinkReason,
+    serenity::{self, GuildId, Member, UserId},
+    state::{BotState, RoleSyncError},
+};
+
+/// How often expired grace periods are checked.
+const FINALISE_INTERVAL: Duration = Duration::from_secs(60);
+
+impl BotState {
+    /// Removes the roles of a member who left, and unlinks them once the grace period is over.
+    pub async fn start_unlink_grace(
+        &self,
+        user_id: UserId,
+        guild_id: GuildId,
+    ) -> Result<(), RoleSyncError> {
+        let Some(account_id) = self.get_linked_gd_account(user_id).await? else {
+            return Err(RoleSyncError::NotLinked);
+        };
+
+        let user_id = user_id.get() as i64;
+        let guild_id = guild_id.get() as i64;
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+
+        // leaving again restarts the grace period
+        sqlx::query!(
+            "INSERT OR REPLACE INTO pending_unlinks (user_id, guild_id, left_at) VALUES (?, ?, ?)",
+            user_id,
+            guild_id,
+            now
+        )
+        .execute(&self.database)
+        .await?;
+
+        // left every guild, nothing grants roles anymore
+        self.clear_account_grants(account_id.get()).await?;
+        self.remove_all_roles(account_id.get()).await
+    }
+
+    /// Cancels the pending unlink of a user, returns whether there was one.
+    pub async fn cancel_pending_unlink(&self, user_id: UserId) -> Result<bool, sqlx::Error> {
+        let user_id = user_id.get() as i64;
+
+        let result = sqlx::query!("DELETE FROM pending_unlinks WHERE user_id = ?", user_id)
+            .execute(&self.database)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Restores a member who rejoined: cancels their pending unlink and syncs their roles again.
+    /// Returns whether an unlink was pending.
+    pub async fn restore_rejoined_member(
+        &self,
+        http: &serenity::Http,
+        member: &Member,
+    ) -> Result<bool, RoleSyncError> {
+        let was_pending = self.cancel_pending_unlink(member.user.id).await?;
+
+        let mut member = member.clone();
+
+        match self.pull_globed_roles(http, &mut member).await {
+            Ok(_) => {}
+            Err(RoleSyncError::NotLinked) => return Err(RoleSyncError::NotLinked),
+            // syncing now would undo the role that could not be mirrored
+            Err(e @ RoleSyncError::DiscordUpdate(_)) => return Err(e),
+            Err(e) => warn!(
+                "Failed to sync roles of rejoined member {} from Globed: {e}",
+                member.user.name
+            ),
+        }
+
+        self.auto_sync_roles(&member).await?;
+
+        Ok(was_pending)
+    }
+
+    /// Unlinks every member whose grace period is over, returns the users that were unlinked.
+    pub async fn finalise_expired_unlinks(&self) -> Result<Vec<UserId>, sqlx::Error> {
+        let grace = self.config.sync.unlink_grace_period.as_secs() as i64;
+        let cutoff = OffsetDateTime::now_utc().unix_timestamp() - grace;
+
+        let expired = sqlx::query!(
+            "SELECT user_id, guild_id FROM pending_unlinks WHERE left_at <= ?",
+            cutoff
+        )
+        .fetch_all(&self.database)
+        .await?;
+
+        let mut unlinked = Vec::new();
+
+        for row in expired {
+            let user_id = UserId::new(row.user_id as u64);
+            let guild_id = GuildId::new(row.guild_id as u64);
+            let account_id = self.get_linked_gd_account(user_id).await?;
+
+            match self.unlink_user(user_id, UnlinkReason::LeftGuild).await {
+                Ok(()) => {
+                    self.audit(
+                        AuditEvent::new(AuditAction::MemberLeft)
+                            .target(user_id)
+                            .account(account_id.map(NonZeroI32::get))
+                            .guild(guild_id)
+                            .details("unlinked after the grace period"),
+                    )
+                    .await;
+
+                    unlinked.push(user_id);
+                }
+
+                // unlinked in the meantime, nothing left to do
+                Err(RoleSyncError::NotLinked) => {
+                    self.cancel_pending_unlink(user_id).await?;
+                }
+
+                // stays pending and is tried again next time
+                Err(e) => warn!("Failed to unlink {user_id} after the grace period: {e}"),
+            }
+        }
+
+        Ok(unlinked)
+    }
+}
+
+/// Starts the background task that unlinks members whose grace period is over.
+pub fn spawn_unlink_finaliser(state: Arc<BotState>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(FINALISE_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = state.finalise_expired_unlinks().await {
+                warn!("Failed to unlink members after the grace period: {e}");
+            }
+        }
+    })
+}
@@ -0,0 +1,270 @@
+//! Mirroring roles from the Globed server back onto Discord, for mappings with the
+//! `globed_to_discord` or `both` direction.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+
+use crate::{
+    db::{LinkedUser, Role},
+    serenity,
+    state::{BotState, RoleSyncError},
+};
+use serenity::all::{GuildId, Member, RoleId, UserId};
+
+// how long member updates caused by our own role edits are ignored
+const OWN_EDIT_WINDOW: Duration = Duration::from_secs(15);
+
+// discord roles the bot changed on a member, and when
+pub(crate) type RecentRoleEdits = HashMap<(GuildId, UserId), (Vec<RoleId>, Instant)>;
+
+/// Discord roles that have to be added or removed so that a member matches their Globed roles.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DiscordRoleChanges {
+    pub added: Vec<RoleId>,
+    pub removed: Vec<RoleId>,
+}
+
+impl DiscordRoleChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Works out which discord roles of a member have to change, given the roles the account has on the server
+/// and the roles it had after the last sync. For `both` mappings, the server only wins if the role changed there.
+pub fn discord_role_changes(
+    member_roles: &[RoleId],
+    globed_roles: &[String],
+    synced_roles: Option<&[String]>,
+    mappings: &[Role],
+) -> DiscordRoleChanges {
+    let mut wanted: Vec<(RoleId, bool)> = Vec::new();
+
+    // boosting can't be granted by the bot
+    for mapping in mappings
+        .iter()
+        .filter(|r| r.direction().to_discord() && !r.is_booster())
+    {
+        let role_id = RoleId::new(mapping.discord_id as u64);
+        let on_discord = member_roles.contains(&role_id);
+        let on_globed = globed_roles.contains(&mapping.id);
+
+        let want = if mapping.direction().to_globed() {
+            match synced_roles {
+                Some(synced) if synced.contains(&mapping.id) != on_globed => on_globed,
+                Some(_) => on_discord,
+                // never synced, so we can't tell which side changed, only add
+                None => on_discord || on_globed,
+            }
+        } else {
+            on_globed
+        };
+
+        // several globed roles can map to one discord role, keep it if any of them wants it
+        match wanted.iter_mut().find(|(id, _)| *id == role_id) {
+            Some((_, w)) => *w |= want,
+            None => wanted.push((role_id, want)),
+        }
+    }
+
+    let mut changes = DiscordRoleChanges::default();
+
+    for (role_id, want) in wanted {
+        let on_discord = member_roles.contains(&role_id);
+
+        if want && !on_discord {
+            changes.added.push(role_id);
+        } else if !want && on_discord {
+            changes.removed.push(role_id);
+        }
+    }
+
+    changes
+}
+
+impl BotState {
+    /// Fetches the Globed roles of a linked member and adds or removes their Discord roles to match.
+    /// `member.roles` is updated with the changes, so that a following push sees the new state.
+    pub async fn pull_globed_roles(
+        &self,
+        http: &serenity::Http,
+        member: &mut Member,
+    ) -> Result<DiscordRoleChanges, RoleSyncError> {
+        let user_id = member.user.id.get() as i64;
+
+        let linked_user = sqlx::query_as!(
+            LinkedUser,
+            "SELECT id, gd_account_id FROM linked_users WHERE id = ? AND unlinked_at IS NULL",
+            user_id
+        )
+        .fetch_one(&self.database)
+        .await?;
+
+        let mappings = self.get_guild_roles(member.guild_id).await?;
+
+        self.pull_globed_roles_with(http, member, &linked_user, &mappings)
+            .await
+    }
+
+    pub async fn pull_globed_roles_with(
+        &self,
+        http: &serenity::Http,
+        member: &mut Member,
+        linked_user: &LinkedUser,
+        mappings: &[Role],
+    ) -> Result<DiscordRoleChanges, RoleSyncError> {
+        if !mappings
+            .iter()
+            .any(|r| r.direction().to_discord() && !r.is_booster())
+        {
+            return Ok(DiscordRoleChanges::default());
+        }
+
+        let account_id = linked_user.gd_account_id as i32;
+
+        // the server is behind on a queued change, its roles can't be trusted yet
+        let queued = sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM sync_queue WHERE account_id = ?",
+            linked_user.gd_account_id
+        )
+        .fetch_one(&self.database)
+        .await?;
+
+        if queued > 0 {
+            return Ok(DiscordRoleChanges::default());
+        }
+
+        let globed_roles = self.backend.get_roles(account_id).await?;
+        let synced_roles = self.get_synced_roles(account_id).await?;
+
+        let changes = discord_role_changes(
+            &member.roles,
+            &globed_roles,
+            synced_roles.as_deref(),
+            mappings,
+        );
+
+        // if a change fails, the old snapshot stays, so that the next pull tries it again
+        // instead of seeing the missing role as removed on discord
+        if !changes.is_empty() {
+            self.apply_discord_role_changes(http, member, &changes)
+                .await?;
+        }
+
+        self.replace_synced_roles(account_id, &globed_roles).await?;
+
+        Ok(changes)
+    }
+
+    /// Mirrors Globed roles onto the member, then syncs their roles to the server. Returns the synced roles and the pulled changes.
+    /// If a pulled role could not be applied on discord, nothing is synced, as that would undo the role on the server.
+    pub async fn pull_and_sync_roles(
+        &self,
+        http: &serenity::Http,
+        member: &mut Member,
+    ) -> Result<(Vec<String>, DiscordRoleChanges), RoleSyncError> {
+        let pulled = match self.pull_globed_roles(http, member).await {
+            Ok(changes) => changes,
+            Err(RoleSyncError::NotLinked) => return Err(RoleSyncError::NotLinked),
+            Err(e @ RoleSyncError::DiscordUpdate(_)) => return Err(e),
+            Err(e) => {
+                warn!(
+                    "Failed to sync roles of {} from Globed: {e}",
+                    member.user.name
+                );
+                DiscordRoleChanges::default()
+            }
+        };
+
+        let roles = self.sync_roles(member).await?;
+
+        Ok((roles, pulled))
+    }
+
+    async fn apply_discord_role_changes(
+        &self,
+        http: &serenity::Http,
+        member: &mut Member,
+        changes: &DiscordRoleChanges,
+    ) -> Result<(), RoleSyncError> {
+        let guild_id = member.guild_id;
+        let mut error = None;
+        let user_id = member.user.id;
+
+        // remember the edit before making it, the member update events can arrive before the requests return
+        let edited: Vec<RoleId> = changes
+            .added
+            .iter()
+            .chain(changes.removed.iter())
+            .copied()
+            .collect();
+
+        self.recent_role_edits
+            .lock()
+            .insert((guild_id, user_id), (edited, Instant::now()));
+
+        for role_id in &changes.added {
+            if let Err(e) = http
+                .add_member_role(guild_id, user_id, *role_id, Some("Synced from Globed"))
+                .await
+            {
+                warn!("Failed to add role {role_id} to {user_id}: {e}");
+                error.get_or_insert(e);
+                continue;
+            }
+
+            member.roles.push(*role_id);
+        }
+
+        for role_id in &changes.removed {
+            if let Err(e) = http
+                .remove_member_role(guild_id, user_id, *role_id, Some("Synced from Globed"))
+                .await
+            {
+                warn!("Failed to remove role {role_id} from {user_id}: {e}");
+                error.get_or_insert(e);
+                continue;
+            }
+
+            member.roles.retain(|x| x != role_id);
+        }
+
+        info!(
+            "Synced roles of {} from Globed, added: {:?}, removed: {:?}",
+            member.user.name, changes.added, changes.removed
+        );
+
+        match error {
+            Some(e) => Err(RoleSyncError::DiscordUpdate(e)),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether a member update was caused by the bot mirroring Globed roles, in which case it must not be synced back.
+    /// If `old` is unknown or no roles changed, it is not treated as our own edit, syncing it again is harmless.
+    pub fn is_own_role_edit(&self, guild_id: GuildId, old: Option<&Member>, new: &Member) -> bool {
+        let mut edits = self.recent_role_edits.lock();
+        edits.retain(|_, (_, at)| at.elapsed() < OWN_EDIT_WINDOW);
+
+        let Some((edited, _)) = edits.get(&(guild_id, new.user.id)) else {
+            return false;
+        };
+
+        let Some(old) = old else {
+            return false;
+        };
+
+        let changed: Vec<&RoleId> = new
+            .roles
+            .iter()
+            .filter(|r| !old.roles.contains(r))
+            .chain(old.roles.iter().filter(|r| !new.roles.contains(r)))
+            .collect();
+
+        !changed.is_empty() && changed.iter().all(|role| edited.contains(role))
+    }
+}
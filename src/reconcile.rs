@@ -0,0 +1,62 @@
+use std::sync::Arc;
+
+use log::{info, warn};
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+
+use crate::{serenity, state::BotState};
+
+/// Starts the background task that periodically syncs all members of every configured guild,
+/// returns `None` if it is disabled in the config.
+/// Every member is sent, not just the ones that changed on discord, so that roles changed on the server get fixed too.
+pub fn spawn_reconcile_task(
+    state: Arc<BotState>,
+    http: Arc<serenity::Http>,
+) -> Option<JoinHandle<()>> {
+    let period = state.config.sync.reconcile_interval;
+    if period.is_zero() {
+        return None;
+    }
+
+    Some(tokio::spawn(async move {
+        // everything was just synced on startup, so the first run can wait
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            let Some(_guard) = state.try_lock_mass_sync() else {
+                info!(
+                    "Skipping scheduled reconciliation, a sync of all members is already running"
+                );
+                continue;
+            };
+
+            for guild_id in &state.guild_ids {
+                match state.sync_all_members(&http, *guild_id, true).await {
+                    Ok(summary) => {
+                        if let Some(error) = &summary.scan_error {
+                            warn!("Reconciliation of {guild_id} did not see every member: {error}");
+                        }
+
+                        let total = summary.succeeded + summary.failed;
+
+                        if summary.failed == 0 {
+                            info!(
+                                "Reconciled {guild_id}: synced all {total} linked members, {} had drifted",
+                                summary.drifted
+                            );
+                        } else {
+                            info!(
+                                "Reconciled {guild_id}: {} of {total} linked members synced, {} failed, {} had drifted",
+                                summary.succeeded, summary.failed, summary.drifted
+                            );
+                        }
+                    }
+
+                    Err(e) => warn!("Failed to reconcile roles of {guild_id}: {e}"),
+                }
+            }
+        }
+    }))
+}
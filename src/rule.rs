@@ -0,0 +1,281 @@
+//! Rules deciding whether a member gets a Globed role, for example `11 OR 12` or `(11 OR 12) AND NOT 13`.
+//! Roles are written as ids or mentions, combined with `AND`, `OR`, `NOT` and parentheses.
+//! `booster` matches members that are boosting the server.
+
+use std::fmt::Display;
+
+use crate::serenity::{Member, RoleId};
+
+// how deep parentheses and `NOT`s can be nested, so that a rule can't overflow the stack
+const MAX_DEPTH: usize = 32;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Rule {
+    Role(RoleId),
+    Booster,
+    Not(Box<Rule>),
+    And(Vec<Rule>),
+    Or(Vec<Rule>),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RuleParseError {
+    Empty,
+    UnexpectedEnd,
+    UnexpectedToken(String),
+    InvalidRole(String),
+    TooDeep,
+}
+
+impl Display for RuleParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Empty => f.write_str("Rule is empty"),
+            Self::UnexpectedEnd => f.write_str("Rule ends unexpectedly"),
+            Self::UnexpectedToken(t) => write!(f, "Unexpected `{t}` in rule"),
+            Self::InvalidRole(t) => write!(f, "`{t}` is not a role id or mention"),
+            Self::TooDeep => write!(f, "Rule is nested more than {MAX_DEPTH} levels deep"),
+        }
+    }
+}
+
+impl std::error::Error for RuleParseError {}
+
+fn tokenize(input: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+
+    for c in input.chars() {
+        if c.is_whitespace() || c == '(' || c == ')' {
+            if !current.is_empty() {
+                tokens.push(std::mem::take(&mut current));
+            }
+
+            if !c.is_whitespace() {
+                tokens.push(c.to_string());
+            }
+        } else {
+            current.push(c);
+        }
+    }
+
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+
+    tokens
+}
+
+fn parse_role(token: &str) -> Result<RoleId, RuleParseError> {
+    let id = token
+        .strip_prefix("<@&")
+        .and_then(|x| x.strip_suffix('>'))
+        .unwrap_or(token);
+
+    match id.parse::<u64>() {
+        Ok(id) if id != 0 => Ok(RoleId::new(id)),
+        _ => Err(RuleParseError::InvalidRole(token.to_owned())),
+    }
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(String::as_str)
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        self.peek().is_some_and(|t| t.eq_ignore_ascii_case(keyword))
+    }
+
+    fn next(&mut self) -> Option<String> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn parse_or(&mut self) -> Result<Rule, RuleParseError> {
+        let mut rules = vec![self.parse_and()?];
+
+        while self.peek_keyword("or") {
+            self.pos += 1;
+            rules.push(self.parse_and()?);
+        }
+
+        Ok(if rules.len() == 1 {
+            rules.pop().unwrap()
+        } else {
+            Rule::Or(rules)
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<Rule, RuleParseError> {
+        let mut rules = vec![self.parse_unary()?];
+
+        while self.peek_keyword("and") {
+            self.pos += 1;
+            rules.push(self.parse_unary()?);
+        }
+
+        Ok(if rules.len() == 1 {
+            rules.pop().unwrap()
+        } else {
+            Rule::And(rules)
+        })
+    }
+
+    fn parse_unary(&mut self) -> Result<Rule, RuleParseError> {
+        let token = self.next().ok_or(RuleParseError::UnexpectedEnd)?;
+
+        if token.eq_ignore_ascii_case("not") || token == "(" {
+            if self.depth == MAX_DEPTH {
+                return Err(RuleParseError::TooDeep);
+            }
+
+            self.depth += 1;
+            let rule = self.parse_nested(&token);
+            self.depth -= 1;
+
+            return rule;
+        }
+
+        if token == ")" || token.eq_ignore_ascii_case("and") || token.eq_ignore_ascii_case("or") {
+            return Err(RuleParseError::UnexpectedToken(token));
+        }
+
+        if token.eq_ignore_ascii_case("booster") {
+            return Ok(Rule::Booster);
+        }
+
+        parse_role(&token).map(Rule::Role)
+    }
+
+    fn parse_nested(&mut self, token: &str) -> Result<Rule, RuleParseError> {
+        if token != "(" {
+            return Ok(Rule::Not(Box::new(self.parse_unary()?)));
+        }
+
+        let rule = self.parse_or()?;
+
+        match self.next() {
+            Some(t) if t == ")" => Ok(rule),
+            Some(t) => Err(RuleParseError::UnexpectedToken(t)),
+            None => Err(RuleParseError::UnexpectedEnd),
+        }
+    }
+}
+
+impl Rule {
+    pub fn parse(input: &str) -> Result<Self, RuleParseError> {
+        let tokens = tokenize(input);
+        if tokens.is_empty() {
+            return Err(RuleParseError::Empty);
+        }
+
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            depth: 0,
+        };
+        let rule = parser.parse_or()?;
+
+        match parser.next() {
+            Some(t) => Err(RuleParseError::UnexpectedToken(t)),
+            None => Ok(rule),
+        }
+    }
+
+    /// Whether a member with these roles matches the rule.
+    pub fn matches(&self, member: &Member) -> bool {
+        match self {
+            Self::Role(id) => member.roles.contains(id),
+            Self::Booster => member.premium_since.is_some(),
+            Self::Not(rule) => !rule.matches(member),
+            Self::And(rules) => rules.iter().all(|r| r.matches(member)),
+            Self::Or(rules) => rules.iter().any(|r| r.matches(member)),
+        }
+    }
+
+    /// All discord roles the rule depends on.
+    pub fn roles(&self) -> Vec<RoleId> {
+        let mut out = Vec::new();
+        self.collect_roles(&mut out);
+        out.sort();
+        out.dedup();
+        out
+    }
+
+    fn collect_roles(&self, out: &mut Vec<RoleId>) {
+        match self {
+            Self::Role(id) => out.push(*id),
+            Self::Booster => {}
+            Self::Not(rule) => rule.collect_roles(out),
+            Self::And(rules) | Self::Or(rules) => {
+                for rule in rules {
+                    rule.collect_roles(out);
+                }
+            }
+        }
+    }
+
+    /// Roles the rule depends on that are not in `existing`, for example deleted roles or ids from another server.
+    pub fn unknown_roles(&self, existing: &[RoleId]) -> Vec<RoleId> {
+        self.roles()
+            .into_iter()
+            .filter(|id| !existing.contains(id))
+            .collect()
+    }
+
+    /// Whether the rule depends on a member boosting the server.
+    pub fn uses_booster(&self) -> bool {
+        match self {
+            Self::Role(_) => false,
+            Self::Booster => true,
+            Self::Not(rule) => rule.uses_booster(),
+            Self::And(rules) | Self::Or(rules) => rules.iter().any(Rule::uses_booster),
+        }
+    }
+
+    /// Like [`Display`], but with role mentions instead of ids, for showing in discord.
+    pub fn to_mentions(&self) -> String {
+        self.format(true)
+    }
+
+    fn format(&self, mentions: bool) -> String {
+        let nested = |rule: &Rule| match rule {
+            Self::And(_) | Self::Or(_) => format!("({})", rule.format(mentions)),
+            _ => rule.format(mentions),
+        };
+
+        match self {
+            Self::Role(id) if mentions => format!("<@&{id}>"),
+            Self::Role(id) => id.to_string(),
+            Self::Booster => "booster".to_owned(),
+            Self::Not(rule) => format!("NOT {}", nested(rule)),
+            Self::And(rules) => rules
+                .iter()
+                .map(|r| match r {
+                    Self::Or(_) => nested(r),
+                    _ => r.format(mentions),
+                })
+                .collect::<Vec<_>>()
+                .join(" AND "),
+            Self::Or(rules) => rules
+                .iter()
+                .map(|r| r.format(mentions))
+                .collect::<Vec<_>>()
+                .join(" OR "),
+        }
+    }
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.format(false))
+    }
+}
@@ -1,34 +1,60 @@
-use std::{env, fmt::Display, num::NonZeroI32};
-
-use crate::{db::*, serenity, Context};
-use log::{debug, error, warn};
-use parking_lot::RwLock as SyncRwLock;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    num::NonZeroI32,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Context as _;
+
+pub use crate::backend::{RoleSyncRequest, RoleSyncRequestData, UserLookupResponse};
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    backend::{GlobedBackend, HttpBackend},
+    config::BotConfig,
+    db::*,
+    log_channel::LogChannelQueue,
+    pull_roles::RecentRoleEdits,
+    rule::Rule,
+    serenity,
+};
+use log::{debug, info, warn};
+use parking_lot::{Mutex as SyncMutex, RwLock as SyncRwLock};
+use poise::futures_util::{stream, StreamExt};
 use reqwest::StatusCode;
-use serde::{Deserialize, Serialize};
 use serenity::all::{GuildId, Member, RoleId, UserId};
+use time::OffsetDateTime;
+use tokio::sync::{watch, Mutex, MutexGuard, Notify};
+
+// how many members have their roles fetched from the server at once during a mass sync
+const PULL_CONCURRENCY: usize = 8;
 
 pub struct BotState {
-    pub http_client: reqwest::Client,
-    pub base_url: String,
-    pub server_password: String,
+    pub backend: Arc<dyn GlobedBackend>,
     pub database: sqlx::SqlitePool,
-    pub guild_id: GuildId,
+    pub guild_ids: Vec<GuildId>,
+    pub config: BotConfig,
 
-    pub watched_roles: SyncRwLock<Vec<RoleId>>,
-}
+    pub watched_roles: SyncRwLock<HashMap<GuildId, Vec<RoleId>>>,
+    /// Guilds with mappings that depend on whether a member is boosting.
+    pub watched_boosts: SyncRwLock<HashSet<GuildId>>,
 
-#[derive(Serialize)]
-pub struct RoleSyncRequest {
-    pub account_id: i32,
-    pub keep: Vec<String>,
-    pub remove: Vec<String>,
-}
+    // members waiting to be synced in the next batch, see `batcher.rs`
+    pub(crate) pending_syncs: SyncMutex<HashMap<(GuildId, UserId), Member>>,
+    pub(crate) pending_sync_notify: Notify,
+
+    // discord roles the bot itself just changed, see `pull_roles.rs`
+    pub(crate) recent_role_edits: SyncMutex<RecentRoleEdits>,
+
+    // events waiting to be posted to the log channels, see `log_channel.rs`
+    pub(crate) log_queue: LogChannelQueue,
 
-#[derive(Serialize)]
-pub struct RoleSyncRequestData {
-    pub users: Vec<RoleSyncRequest>,
+    // held while all members of a guild are synced, so that manual and scheduled syncs don't overlap
+    mass_sync_lock: Mutex<()>,
 }
 
+#[derive(Debug)]
 pub enum RoleSyncError {
     NotLinked,
     Database(sqlx::Error),
@@ -36,6 +62,23 @@ pub enum RoleSyncError {
     #[allow(unused)]
     InternalError(&'static str),
     ServerUpdate((StatusCode, String)),
+    RateLimited(Option<Duration>),
+    RetriesExhausted(u32, Box<RoleSyncError>),
+    ServerMalformedResponse(serde_json::Error, String),
+    DiscordUpdate(serenity::Error),
+}
+
+impl RoleSyncError {
+    /// Whether retrying the same request later could succeed.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::ServerRequest(e) => e.is_connect() || e.is_timeout(),
+            Self::ServerUpdate((code, _)) => code.is_server_error(),
+            Self::RateLimited(_) => true,
+            Self::RetriesExhausted(_, e) => e.is_transient(),
+            _ => false,
+        }
+    }
 }
 
 impl From<sqlx::Error> for RoleSyncError {
@@ -57,10 +100,179 @@ impl Display for RoleSyncError {
             Self::ServerUpdate((code, message)) => {
                 write!(f, "Server returned error (code {code}): {message}")
             }
+            Self::RateLimited(Some(after)) => {
+                write!(f, "Rate limited by the server, retry after {after:?}")
+            }
+            Self::RateLimited(None) => f.write_str("Rate limited by the server"),
+            Self::RetriesExhausted(attempts, e) => {
+                write!(f, "{e} (gave up after {attempts} attempts)")
+            }
+            Self::ServerMalformedResponse(e, _) => {
+                write!(f, "Server returned unparsable data: {e}")
+            }
+            Self::DiscordUpdate(e) => write!(f, "Failed to update discord roles: {e}"),
+        }
+    }
+}
+
+/// Result of syncing all members of a guild in chunks.
+#[derive(Debug, Default)]
+pub struct SyncAllSummary {
+    pub succeeded: usize,
+    /// Users that were skipped, because their roles did not change since the last sync.
+    pub unchanged: usize,
+    /// Users whose roles differ from the last sync, counted even when the sync is forced and nobody is skipped.
+    pub drifted: usize,
+    pub failed: usize,
+    pub failed_chunks: Vec<ChunkFailure>,
+    /// Set if fetching members from discord failed, members after that point were not synced.
+    pub scan_error: Option<serenity::Error>,
+}
+
+impl SyncAllSummary {
+    /// Whether every linked member was found and synced.
+    pub fn is_complete(&self) -> bool {
+        self.failed == 0 && self.scan_error.is_none()
+    }
+}
+
+/// Progress of a running mass sync, see [`BotState::sync_all_members_with_progress`].
+#[derive(Clone, Debug, Default)]
+pub struct SyncAllProgress {
+    pub members_scanned: usize,
+    pub linked_found: usize,
+    pub chunks_sent: usize,
+    pub errors: usize,
+}
+
+/// A chunk of a mass sync that the server did not accept.
+#[derive(Debug)]
+pub struct ChunkFailure {
+    /// Index of the chunk, starting at 0.
+    pub index: usize,
+    pub users: usize,
+    pub error: RoleSyncError,
+}
+
+impl Display for SyncAllSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} users synced, {} unchanged, {} failed",
+            self.succeeded, self.unchanged, self.failed
+        )?;
+
+        for chunk in &self.failed_chunks {
+            write!(
+                f,
+                "\n* chunk {} ({} users): {}",
+                chunk.index + 1,
+                chunk.users,
+                chunk.error
+            )?;
+        }
+
+        if let Some(error) = &self.scan_error {
+            write!(f, "\n* member scan stopped early: {error}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Sends the requests of a mass sync in chunks of `sync.chunk_size` users, as soon as a chunk fills up.
+/// A failed chunk does not stop the rest, see [`SyncAllSummary`].
+pub struct ChunkedSync<'a> {
+    state: &'a BotState,
+    progress: &'a watch::Sender<SyncAllProgress>,
+    pending: Vec<RoleSyncRequest>,
+    chunks: usize,
+    force: bool,
+    summary: SyncAllSummary,
+}
+
+impl ChunkedSync<'_> {
+    /// Sends every user, even if their roles did not change since the last sync.
+    /// The last sync is only what the bot sent, so this is needed to fix roles that were changed on the server.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Adds users to the sync, sending every chunk that is full.
+    /// Unless forced, users whose roles did not change since the last sync are skipped.
+    pub async fn push(&mut self, data: RoleSyncRequestData) {
+        let (changed, unchanged) = self.state.without_unchanged_syncs(&data).await;
+        self.summary.drifted += changed.users.len();
+
+        let data = if self.force {
+            data
+        } else {
+            self.summary.unchanged += unchanged;
+            changed
+        };
+
+        self.pending.extend(data.users);
+
+        let chunk_size = self.state.config.sync.chunk_size;
+        while self.pending.len() >= chunk_size {
+            let chunk: Vec<_> = self.pending.drain(..chunk_size).collect();
+            self.send(chunk).await;
+        }
+    }
+
+    /// Sends the last chunk, returns the summary of the whole sync.
+    pub async fn finish(mut self) -> SyncAllSummary {
+        if !self.pending.is_empty() {
+            let chunk = std::mem::take(&mut self.pending);
+            self.send(chunk).await;
+        }
+
+        self.summary
+    }
+
+    async fn send(&mut self, chunk: Vec<RoleSyncRequest>) {
+        let index = self.chunks;
+        let users = chunk.len();
+        self.chunks += 1;
+
+        let result = self
+            .state
+            .send_sync_roles_req(&RoleSyncRequestData { users: chunk })
+            .await;
+
+        self.progress.send_modify(|p| {
+            p.chunks_sent += 1;
+            p.errors += result.is_err() as usize;
+        });
+
+        match result {
+            Ok(()) => self.summary.succeeded += users,
+            Err(error) => {
+                warn!(
+                    "Failed to sync chunk {} ({users} users): {error}",
+                    index + 1
+                );
+
+                self.state
+                    .audit(AuditEvent::new(AuditAction::SyncFailed).details(format!(
+                        "chunk {} of a mass sync ({users} users): {error}",
+                        index + 1
+                    )))
+                    .await;
+
+                self.summary.failed += users;
+                self.summary.failed_chunks.push(ChunkFailure {
+                    index,
+                    users,
+                    error,
+                });
+            }
         }
     }
 }
 
+#[derive(Debug)]
 pub enum RoleRemoveError {
     Database(sqlx::Error),
     NotFound,
@@ -75,6 +287,24 @@ impl From<sqlx::Error> for RoleRemoveError {
     }
 }
 
+#[derive(Debug)]
+pub enum TransferError {
+    /// The user to transfer from is not linked.
+    NotLinked,
+    /// The user to transfer to already has a link.
+    AlreadyLinked,
+    AccountBlocked,
+    SameUser,
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for TransferError {
+    fn from(value: sqlx::Error) -> Self {
+        Self::Database(value)
+    }
+}
+
+#[derive(Debug)]
 pub enum LinkError {
     AlreadyLinked,
     InvalidUsername,
@@ -84,7 +314,10 @@ pub enum LinkError {
     ServerMalformedResponse(serde_json::Error, String),
     Database(sqlx::Error),
     RoleSync(RoleSyncError, UserLookupResponse),
-    LinkedToOther(String),
+    LinkedToOther(UserId),
+    AccountBlocked,
+    /// Too many wrong link codes, the user has to wait this long before trying again.
+    TooManyAttempts(Duration),
 }
 
 impl From<sqlx::Error> for LinkError {
@@ -93,64 +326,96 @@ impl From<sqlx::Error> for LinkError {
     }
 }
 
-#[derive(Deserialize)]
-pub struct UserLookupResponse {
-    pub account_id: i32,
-    pub name: String,
-}
-
 impl BotState {
-    pub async fn new(database: sqlx::SqlitePool) -> Self {
-        let mut base_url =
-            env::var("BOT_BASE_URL").expect("'BOT_BASE_URL' env variable not passed");
-        if base_url.ends_with('/') {
-            base_url.pop();
-        }
+    pub async fn new(config: &BotConfig, database: sqlx::SqlitePool) -> anyhow::Result<Self> {
+        let backend =
+            HttpBackend::new(&config.server).context("failed to create the HTTP client")?;
 
-        let server_password =
-            env::var("BOT_SERVER_PASSWORD").expect("'BOT_SERVER_PASSWORD' env variable not passed");
-
-        let guild_id = GuildId::new(
-            env::var("BOT_SERVER_ID")
-                .expect("Expected BOT_SERVER_ID in environment")
-                .parse()
-                .expect("BOT_SERVER_ID must be an integer"),
-        );
-
-        // fetch roles
+        Self::with_backend(config, database, Arc::new(backend)).await
+    }
 
+    pub async fn with_backend(
+        config: &BotConfig,
+        database: sqlx::SqlitePool,
+        backend: Arc<dyn GlobedBackend>,
+    ) -> anyhow::Result<Self> {
         let ret = Self {
-            http_client: reqwest::Client::builder()
-                .user_agent(format!(
-                    "globed-game-server/discord-bot-{}",
-                    env!("CARGO_PKG_VERSION")
-                ))
-                .build()
-                .expect("Failed to create the HTTP client"),
-            base_url,
-            server_password,
+            backend,
             database,
-            guild_id,
-            watched_roles: SyncRwLock::new(Vec::new()),
+            guild_ids: config.guilds.iter().map(|g| GuildId::new(g.id)).collect(),
+            config: config.clone(),
+            watched_roles: SyncRwLock::new(HashMap::new()),
+            watched_boosts: SyncRwLock::new(HashSet::new()),
+            pending_syncs: SyncMutex::new(HashMap::new()),
+            pending_sync_notify: Notify::new(),
+            recent_role_edits: SyncMutex::new(HashMap::new()),
+            log_queue: LogChannelQueue::new(),
+            mass_sync_lock: Mutex::new(()),
         };
 
-        // get all roles from the database and push them to a vec
+        ret.assign_legacy_roles()
+            .await
+            .context("failed to assign role mappings to a guild")?;
+
+        // fetch roles from the database and push them to the watched roles of their guild
         let roles = ret
             .get_all_roles()
             .await
-            .expect("Failed to fetch roles from the database");
+            .context("failed to fetch roles from the database")?;
 
-        let mut watched = ret.watched_roles.write();
-        for role in roles {
-            watched.push(RoleId::new(role.discord_id as u64));
+        for role in &roles {
+            let guild_id = GuildId::new(role.guild_id as u64);
+
+            if !ret.guild_ids.contains(&guild_id) {
+                warn!(
+                    "Role mapping {} is for guild {guild_id}, which is not in the config, ignoring it",
+                    role.id
+                );
+            }
         }
 
-        #[cfg(debug_assertions)]
-        debug!("new watched roles: {:?}", *watched);
+        for guild_id in &ret.guild_ids {
+            let guild_roles: Vec<Role> = roles
+                .iter()
+                .filter(|r| r.guild_id == guild_id.get() as i64)
+                .cloned()
+                .collect();
 
-        drop(watched);
+            ret.set_watched_roles(*guild_id, &guild_roles);
+        }
 
-        ret
+        Ok(ret)
+    }
+
+    // role mappings created before multi guild support have guild id 0, give them to the only configured guild
+    async fn assign_legacy_roles(&self) -> Result<(), sqlx::Error> {
+        let legacy = sqlx::query_scalar!("SELECT COUNT(*) FROM roles WHERE guild_id = 0")
+            .fetch_one(&self.database)
+            .await?;
+
+        if legacy == 0 {
+            return Ok(());
+        }
+
+        if let [guild_id] = self.guild_ids[..] {
+            let guild_id = guild_id.get() as i64;
+
+            sqlx::query!("UPDATE roles SET guild_id = ? WHERE guild_id = 0", guild_id)
+                .execute(&self.database)
+                .await?;
+
+            info!("Assigned {legacy} existing role mappings to guild {guild_id}");
+        } else {
+            warn!(
+                "There are {legacy} role mappings from before multi guild support, but more than one guild is configured. Add them again with `/role add` in the right guild."
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn is_configured_guild(&self, guild_id: GuildId) -> bool {
+        self.guild_ids.contains(&guild_id)
     }
 
     /* Methods for linking/unlinking users etc. */
@@ -167,7 +432,7 @@ impl BotState {
 
         let res = sqlx::query_as!(
             LinkedUser,
-            "SELECT * FROM linked_users WHERE id = ?",
+            "SELECT id, gd_account_id FROM linked_users WHERE id = ? AND unlinked_at IS NULL",
             user_id
         )
         .fetch_one(&self.database)
@@ -188,7 +453,7 @@ impl BotState {
 
         let res = sqlx::query_as!(
             LinkedUser,
-            "SELECT * FROM linked_users WHERE gd_account_id = ?",
+            "SELECT id, gd_account_id FROM linked_users WHERE gd_account_id = ? AND unlinked_at IS NULL",
             account_id
         )
         .fetch_one(&self.database)
@@ -203,7 +468,6 @@ impl BotState {
 
     pub async fn link_user(
         &self,
-        ctx: &Context<'_>,
         member: &Member,
         gd_username: &str,
         link_code: Option<u32>, // if None, bypasses verification
@@ -216,57 +480,51 @@ impl BotState {
             return Err(LinkError::AlreadyLinked);
         }
 
-        let bypass_verification = link_code.is_none();
+        // verified links are throttled, so that link codes can't be guessed
+        let verified = link_code.is_some();
 
-        let mut url = format!(
-            "{}/gsp/lookup?username={}&link_code={}",
-            self.base_url,
-            gd_username,
-            link_code.unwrap_or(0)
-        );
-
-        if bypass_verification {
-            url += "&bypass=true";
+        if verified && let Some(wait) = self.start_link_attempt(member, gd_username).await? {
+            return Err(LinkError::TooManyAttempts(wait));
         }
 
-        let response = match self
-            .http_client
-            .get(url)
-            .header("Authorization", &self.server_password)
-            .send()
-            .await
-        {
-            Ok(resp) => resp,
+        let response = match self.backend.lookup_user(gd_username, link_code).await {
+            Ok(x) => x,
+            // a wrong code, the attempt stays counted
+            Err(LinkError::UserNotFound) => return Err(LinkError::UserNotFound),
             Err(e) => {
-                return Err(LinkError::ServerRequest(e));
-            }
-        };
+                if verified {
+                    self.uncount_link_attempt(member.user.id, gd_username)
+                        .await?;
+                }
 
-        let status = response.status();
-        if !status.is_success() {
-            if status == StatusCode::NOT_FOUND {
-                return Err(LinkError::UserNotFound);
+                return Err(e);
             }
+        };
 
-            let message = response
-                .text()
-                .await
-                .unwrap_or_else(|_| "<no message>".to_owned());
-
-            return Err(LinkError::ServerInternalError(status, message));
+        if verified {
+            self.clear_user_link_attempts(member.user.id).await?;
+            self.clear_username_link_attempts(gd_username).await?;
         }
 
-        let json = response.text().await.unwrap_or_default();
-        let response: UserLookupResponse = match serde_json::from_str(&json) {
-            Ok(x) => x,
-            Err(err) => {
-                return Err(LinkError::ServerMalformedResponse(err, json));
-            }
-        };
-
         // insert into the db
-        self.add_linked_user(ctx, member.user.id, response.account_id)
-            .await?;
+        match self
+            .add_linked_user(member.user.id, response.account_id)
+            .await
+        {
+            Ok(()) => {}
+            // only members claiming an account count as a conflict, admins linking one don't
+            Err(LinkError::LinkedToOther(owner)) => {
+                if let Err(e) = self
+                    .record_link_conflict(response.account_id, member, owner)
+                    .await
+                {
+                    warn!("Failed to record link conflict: {e}");
+                }
+
+                return Err(LinkError::LinkedToOther(owner));
+            }
+            Err(e) => return Err(e),
+        }
 
         // sync roles
         match self.sync_roles(member).await {
@@ -275,58 +533,204 @@ impl BotState {
         }
     }
 
-    pub async fn unlink_user(&self, user_id: UserId) -> Result<(), RoleSyncError> {
+    /// Unlinks a user, keeping the link in their history with the reason it ended.
+    pub async fn unlink_user(
+        &self,
+        user_id: UserId,
+        reason: UnlinkReason,
+    ) -> Result<(), RoleSyncError> {
         let user_id = user_id.get() as i64;
 
         // check if the user is linked
         let linked_user = sqlx::query_as!(
             LinkedUser,
-            "SELECT * FROM linked_users WHERE id = ?",
+            "SELECT id, gd_account_id FROM linked_users WHERE id = ? AND unlinked_at IS NULL",
             user_id
         )
         .fetch_one(&self.database)
         .await?;
 
-        // fetch roles from the database
-        let db_roles = sqlx::query_as!(Role, "SELECT * FROM roles")
-            .fetch_all(&self.database)
-            .await?;
+        // end the link, the row stays as history
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let reason = reason.as_db();
 
-        // remove user from the database
-        sqlx::query!("DELETE FROM linked_users WHERE id = ?", user_id)
+        sqlx::query!(
+            "UPDATE linked_users SET unlinked_at = ?, unlink_reason = ? WHERE id = ? AND unlinked_at IS NULL",
+            now,
+            reason,
+            user_id
+        )
+        .execute(&self.database)
+        .await?;
+
+        // a later link must not be ended by a grace period that started before it
+        sqlx::query!("DELETE FROM pending_unlinks WHERE user_id = ?", user_id)
             .execute(&self.database)
             .await?;
 
+        self.clear_account_grants(linked_user.gd_account_id as i32)
+            .await?;
+
         // sync roles with the server
+        self.remove_all_roles(linked_user.gd_account_id as i32)
+            .await
+    }
 
-        let mut removed = Vec::new();
+    /// Moves the link of `from` to `to` without unlinking the GD account in between, returns the account.
+    /// `from` doesn't have to be in the guild anymore, roles are not synced here.
+    pub async fn transfer_link(&self, from: UserId, to: UserId) -> Result<i32, TransferError> {
+        if from == to {
+            return Err(TransferError::SameUser);
+        }
+
+        let Some(account_id) = self.get_linked_gd_account(from).await? else {
+            return Err(TransferError::NotLinked);
+        };
 
-        for role in db_roles {
-            removed.push(role.id);
+        if self.is_linked(to).await? {
+            return Err(TransferError::AlreadyLinked);
+        }
+
+        if self.is_account_blocked(account_id.get()).await? {
+            return Err(TransferError::AccountBlocked);
+        }
+
+        let account_id = account_id.get();
+        let from = from.get() as i64;
+        let to = to.get() as i64;
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let reason = UnlinkReason::Transferred.as_db();
+
+        let mut tx = self.database.begin().await?;
+
+        let ended = sqlx::query!(
+            "UPDATE linked_users SET unlinked_at = ?, unlink_reason = ? WHERE id = ? AND gd_account_id = ? AND unlinked_at IS NULL",
+            now,
+            reason,
+            from,
+            account_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        // unlinked since the check above
+        if ended.rows_affected() == 0 {
+            return Err(TransferError::NotLinked);
+        }
+
+        match sqlx::query!(
+            "INSERT INTO linked_users (id, gd_account_id, linked_at) VALUES (?, ?, ?)",
+            to,
+            account_id,
+            now
+        )
+        .execute(&mut *tx)
+        .await
+        {
+            Ok(_) => {}
+            Err(sqlx::Error::Database(err))
+                if err.message().contains("UNIQUE constraint failed") =>
+            {
+                return Err(TransferError::AlreadyLinked);
+            }
+            Err(e) => return Err(e.into()),
         }
 
+        // the old user might have left and be waiting to be unlinked
+        sqlx::query!("DELETE FROM pending_unlinks WHERE user_id = ?", from)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        // roles granted to the old user are worked out again for the new one
+        self.clear_account_grants(account_id).await?;
+
+        Ok(account_id)
+    }
+
+    // removes every mapped role of all guilds from an account
+    pub(crate) async fn remove_all_roles(&self, account_id: i32) -> Result<(), RoleSyncError> {
+        let db_roles = self.get_all_roles().await?;
+
+        let mut removed: Vec<String> = db_roles.into_iter().map(|role| role.id).collect();
+        removed.sort();
+        removed.dedup();
+
         let req = RoleSyncRequest {
-            account_id: linked_user.gd_account_id as i32,
+            account_id,
             keep: Vec::new(),
             remove: removed,
         };
 
-        self.send_sync_roles_req(&RoleSyncRequestData { users: vec![req] })
+        self.send_or_queue_sync_roles_req(&RoleSyncRequestData { users: vec![req] })
             .await
     }
 
-    pub async fn add_linked_user(
+    // removes the roles linked in one guild without unlinking, used when a user leaves one of several guilds
+    pub async fn strip_guild_roles(
         &self,
-        ctx: &Context<'_>,
         user_id: UserId,
-        account_id: i32,
-    ) -> Result<(), LinkError> {
+        guild_id: GuildId,
+    ) -> Result<(), RoleSyncError> {
+        let Some(account_id) = self.get_linked_gd_account(user_id).await? else {
+            return Err(RoleSyncError::NotLinked);
+        };
+
+        self.clear_guild_grants(guild_id, account_id.get()).await?;
+
+        // roles that another guild still grants stay
+        let granted = self
+            .granted_by_other_guilds(guild_id, account_id.get())
+            .await?;
+
+        let removed = self
+            .get_guild_roles(guild_id)
+            .await?
+            .into_iter()
+            .map(|role| role.id)
+            .filter(|role| !granted.contains(role))
+            .collect();
+
+        let req = RoleSyncRequest {
+            account_id: account_id.get(),
+            keep: Vec::new(),
+            remove: removed,
+        };
+
+        self.send_or_queue_sync_roles_req(&RoleSyncRequestData { users: vec![req] })
+            .await
+    }
+
+    // returns whether the user is still in any configured guild other than `except`
+    pub async fn is_in_other_guild(
+        &self,
+        http: &serenity::Http,
+        user_id: UserId,
+        except: GuildId,
+    ) -> bool {
+        for guild_id in self.guild_ids.iter().filter(|id| **id != except) {
+            if http.get_member(*guild_id, user_id).await.is_ok() {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    pub async fn add_linked_user(&self, user_id: UserId, account_id: i32) -> Result<(), LinkError> {
+        if self.is_account_blocked(account_id).await? {
+            return Err(LinkError::AccountBlocked);
+        }
+
         let user_id_int = user_id.get() as i64;
+        let now = OffsetDateTime::now_utc().unix_timestamp();
 
         match sqlx::query!(
-            "INSERT INTO linked_users (id, gd_account_id) VALUES (?, ?)",
+            "INSERT INTO linked_users (id, gd_account_id, linked_at) VALUES (?, ?, ?)",
             user_id_int,
-            account_id
+            account_id,
+            now
         )
         .execute(&self.database)
         .await
@@ -342,30 +746,8 @@ impl BotState {
                 let linked_disc = self.get_linked_discord_account(account_id).await?;
 
                 // if linked to someone else than us, tell the user
-                if linked_disc.as_ref().is_some_and(|id| *id != user_id) {
-                    let linked_id = linked_disc.unwrap();
-
-                    // try to fetch the member and display their username, else fall back to their user id
-                    let mut ident = String::new();
-
-                    // god i fucking hate async rust
-                    {
-                        if let Some(cached) = ctx.cache().user(linked_id) {
-                            ident.push('@');
-                            ident.push_str(&cached.name);
-                        }
-                    }
-
-                    if ident.is_empty() {
-                        if let Ok(user) = ctx.http().get_user(linked_id).await {
-                            ident.push('@');
-                            ident.push_str(&user.name);
-                        } else {
-                            ident = linked_id.to_string();
-                        }
-                    };
-
-                    return Err(LinkError::LinkedToOther(ident));
+                if let Some(linked_id) = linked_disc.filter(|id| *id != user_id) {
+                    return Err(LinkError::LinkedToOther(linked_id));
                 } else {
                     // otherwise most likely we are already linked
                     return Err(LinkError::AlreadyLinked);
@@ -380,82 +762,209 @@ impl BotState {
     }
 
     pub async fn get_all_linked_users(&self) -> Result<Vec<LinkedUser>, sqlx::Error> {
-        sqlx::query_as!(LinkedUser, "SELECT * FROM linked_users")
-            .fetch_all(&self.database)
-            .await
+        sqlx::query_as!(
+            LinkedUser,
+            "SELECT id, gd_account_id FROM linked_users WHERE unlinked_at IS NULL"
+        )
+        .fetch_all(&self.database)
+        .await
+    }
+
+    /// All links a discord user ever had, newest first.
+    pub async fn get_user_link_history(
+        &self,
+        user_id: UserId,
+    ) -> Result<Vec<LinkHistoryEntry>, sqlx::Error> {
+        let user_id = user_id.get() as i64;
+
+        sqlx::query_as!(
+            LinkHistoryEntry,
+            "SELECT id, gd_account_id, linked_at, unlinked_at, unlink_reason FROM linked_users WHERE id = ? ORDER BY row_id DESC",
+            user_id
+        )
+        .fetch_all(&self.database)
+        .await
+    }
+
+    /// All discord users a GD account was ever linked to, newest first.
+    pub async fn get_account_link_history(
+        &self,
+        account_id: i32,
+    ) -> Result<Vec<LinkHistoryEntry>, sqlx::Error> {
+        sqlx::query_as!(
+            LinkHistoryEntry,
+            "SELECT id, gd_account_id, linked_at, unlinked_at, unlink_reason FROM linked_users WHERE gd_account_id = ? ORDER BY row_id DESC",
+            account_id
+        )
+        .fetch_all(&self.database)
+        .await
     }
 
     /* Methods for adding/removing/getting linked roles */
 
-    pub async fn add_role(&self, role_id: i64, globed_role_id: &str) -> Result<(), sqlx::Error> {
+    pub async fn add_role(
+        &self,
+        guild_id: GuildId,
+        role_id: i64,
+        globed_role_id: &str,
+        direction: SyncDirection,
+    ) -> Result<(), sqlx::Error> {
+        let guild_id_int = guild_id.get() as i64;
+        let direction_str = direction.as_db();
+
         sqlx::query!(
-            "INSERT INTO roles (id, discord_id) VALUES (?, ?)",
+            "INSERT INTO roles (guild_id, id, discord_id, direction) VALUES (?, ?, ?, ?)",
+            guild_id_int,
             globed_role_id,
-            role_id
+            role_id,
+            direction_str
         )
         .execute(&self.database)
         .await?;
 
-        let id = RoleId::new(role_id as u64);
+        self.refresh_watched_roles(guild_id).await
+    }
 
-        let mut watched = self.watched_roles.write();
-        if !watched.contains(&id) {
-            watched.push(id);
-        }
+    /// Sets the rule deciding who gets a globed role, `None` goes back to just checking the mapped discord role.
+    pub async fn set_role_rule(
+        &self,
+        guild_id: GuildId,
+        globed_role_id: &str,
+        rule: Option<&Rule>,
+    ) -> Result<(), RoleRemoveError> {
+        let guild_id_int = guild_id.get() as i64;
+        let rule = rule.map(|r| r.to_string());
+
+        let affected = sqlx::query!(
+            "UPDATE roles SET rule = ? WHERE guild_id = ? AND id = ?",
+            rule,
+            guild_id_int,
+            globed_role_id
+        )
+        .execute(&self.database)
+        .await?
+        .rows_affected();
 
-        watched.sort();
+        if affected == 0 {
+            return Err(RoleRemoveError::NotFound);
+        }
 
-        #[cfg(debug_assertions)]
-        debug!("new watched roles: {:?}", *watched);
+        self.refresh_watched_roles(guild_id).await?;
 
         Ok(())
     }
 
-    pub async fn remove_role(&self, role_id: i64) -> Result<(), RoleRemoveError> {
-        let affected = sqlx::query!("DELETE FROM roles WHERE discord_id = ?", role_id)
-            .execute(&self.database)
-            .await?
-            .rows_affected();
+    pub async fn remove_role(
+        &self,
+        guild_id: GuildId,
+        role_id: i64,
+    ) -> Result<(), RoleRemoveError> {
+        let guild_id_int = guild_id.get() as i64;
+
+        let removed = sqlx::query!(
+            "DELETE FROM roles WHERE guild_id = ? AND discord_id = ? RETURNING id",
+            guild_id_int,
+            role_id
+        )
+        .fetch_all(&self.database)
+        .await?;
 
-        if affected == 0 {
+        if removed.is_empty() {
             return Err(RoleRemoveError::NotFound);
         }
 
-        let id = RoleId::new(role_id as u64);
-
-        let mut watched = self.watched_roles.write();
-        if let Some(pos) = watched.iter().position(|x| *x == id) {
-            watched.remove(pos);
+        for role in removed {
+            self.clear_role_grants(guild_id, &role.id).await?;
         }
 
-        #[cfg(debug_assertions)]
-        debug!("new watched roles: {:?}", *watched);
+        self.refresh_watched_roles(guild_id).await?;
 
         Ok(())
     }
 
-    pub async fn remove_role_by_globed_id(&self, role: &str) -> Result<(), RoleRemoveError> {
-        let deleted = sqlx::query_as!(Role, "DELETE FROM roles WHERE id = ? RETURNING *", role)
-            .fetch_one(&self.database)
-            .await?;
+    pub async fn remove_role_by_globed_id(
+        &self,
+        guild_id: GuildId,
+        role: &str,
+    ) -> Result<(), RoleRemoveError> {
+        let guild_id_int = guild_id.get() as i64;
 
-        let id = RoleId::new(deleted.discord_id as u64);
+        sqlx::query!(
+            "DELETE FROM roles WHERE guild_id = ? AND id = ? RETURNING id",
+            guild_id_int,
+            role
+        )
+        .fetch_one(&self.database)
+        .await?;
 
-        let mut watched = self.watched_roles.write();
-        if let Some(pos) = watched.iter().position(|x| *x == id) {
-            watched.remove(pos);
-        }
+        self.clear_role_grants(guild_id, role).await?;
+        self.refresh_watched_roles(guild_id).await?;
 
-        #[cfg(debug_assertions)]
-        debug!("new watched roles: {:?}", *watched);
+        Ok(())
+    }
+
+    // recomputes which discord roles of the guild are used by mappings pushed to globed
+    async fn refresh_watched_roles(&self, guild_id: GuildId) -> Result<(), sqlx::Error> {
+        let roles = self.get_guild_roles(guild_id).await?;
+        self.set_watched_roles(guild_id, &roles);
 
         Ok(())
     }
 
+    fn set_watched_roles(&self, guild_id: GuildId, roles: &[Role]) {
+        let mut ids: Vec<RoleId> = roles.iter().flat_map(Role::watched_roles).collect();
+        ids.sort();
+        ids.dedup();
+
+        #[cfg(debug_assertions)]
+        debug!("new watched roles in {guild_id}: {ids:?}");
+
+        self.watched_roles.write().insert(guild_id, ids);
+
+        let mut boosts = self.watched_boosts.write();
+        if roles.iter().any(Role::watches_booster) {
+            boosts.insert(guild_id);
+        } else {
+            boosts.remove(&guild_id);
+        }
+    }
+
     pub async fn get_all_roles(&self) -> Result<Vec<Role>, sqlx::Error> {
-        sqlx::query_as!(Role, "SELECT * FROM roles")
-            .fetch_all(&self.database)
-            .await
+        sqlx::query_as!(
+            Role,
+            "SELECT guild_id, id, discord_id, direction, rule FROM roles ORDER BY guild_id, id"
+        )
+        .fetch_all(&self.database)
+        .await
+    }
+
+    pub async fn get_guild_roles(&self, guild_id: GuildId) -> Result<Vec<Role>, sqlx::Error> {
+        let guild_id = guild_id.get() as i64;
+
+        sqlx::query_as!(
+            Role,
+            "SELECT guild_id, id, discord_id, direction, rule FROM roles WHERE guild_id = ? ORDER BY id",
+            guild_id
+        )
+        .fetch_all(&self.database)
+        .await
+    }
+
+    pub fn is_watched_role_changed(&self, guild_id: GuildId, old: &Member, new: &Member) -> bool {
+        let watched = self.watched_roles.read();
+
+        // iterate over all watched roles of the guild, see if anything changed
+        let roles_changed = watched.get(&guild_id).is_some_and(|roles| {
+            roles
+                .iter()
+                .any(|role| new.roles.contains(role) != old.roles.contains(role))
+        });
+
+        // boosting doesn't necessarily change any watched role
+        let boost_changed = old.premium_since.is_some() != new.premium_since.is_some()
+            && self.watched_boosts.read().contains(&guild_id);
+
+        roles_changed || boost_changed
     }
 
     /* Methods for syncing */
@@ -472,22 +981,47 @@ impl BotState {
         Ok(retval)
     }
 
-    pub async fn sync_all_members(&self, http: &serenity::Http) -> Result<usize, RoleSyncError> {
-        // get all linked users
-        let linked_users = self
-            .get_all_linked_users()
+    // syncs roles after a change on discord, queueing the sync if the server can't be reached
+    pub async fn auto_sync_roles(&self, user: &Member) -> Result<(), RoleSyncError> {
+        let req = self.make_role_sync_request(user).await?;
+
+        self.send_or_queue_sync_roles_req(&RoleSyncRequestData { users: vec![req] })
             .await
-            .expect("Failed to read the database");
+    }
 
-        // get all linked roles
-        let linked_roles = self
-            .get_all_roles()
+    /// Returns a guard that must be held while syncing all members, or `None` if such a sync is already running.
+    pub fn try_lock_mass_sync(&self) -> Option<MutexGuard<'_, ()>> {
+        self.mass_sync_lock.try_lock().ok()
+    }
+
+    /// Syncs roles of every linked member of the guild, sending them to the server in chunks of `sync.chunk_size` users.
+    /// A failed chunk does not stop the rest, the returned summary lists which chunks failed and why.
+    /// Unless `force` is set, members whose roles did not change since the last sync are skipped, see [`ChunkedSync::force`].
+    pub async fn sync_all_members(
+        &self,
+        http: &serenity::Http,
+        guild_id: GuildId,
+        force: bool,
+    ) -> Result<SyncAllSummary, RoleSyncError> {
+        self.sync_all_members_with_progress(http, guild_id, force, &watch::Sender::default())
             .await
-            .expect("Failed to read the database");
+    }
 
-        let mut sync_data = RoleSyncRequestData {
-            users: Vec::with_capacity(linked_users.len()),
-        };
+    /// Same as [`Self::sync_all_members`], but reports how far along the sync is through `progress`.
+    pub async fn sync_all_members_with_progress(
+        &self,
+        http: &serenity::Http,
+        guild_id: GuildId,
+        force: bool,
+        progress: &watch::Sender<SyncAllProgress>,
+    ) -> Result<SyncAllSummary, RoleSyncError> {
+        // get all linked users
+        let linked_users = self.get_all_linked_users().await?;
+
+        // get all roles linked in this guild
+        let linked_roles = self.get_guild_roles(guild_id).await?;
+
+        let mut sync = self.chunked_sync(progress).force(force);
 
         // for fastest lookup, put all ids of linked users into a vec and sort it, so binary search can be applied later
         let mut linked_ids: Vec<u64> = linked_users.iter().map(|x| x.id as u64).collect();
@@ -496,12 +1030,15 @@ impl BotState {
         // Perform quite a massive scan
 
         let mut after = None;
+        let mut scan_error = None;
 
         loop {
-            let members = match http.get_guild_members(self.guild_id, None, after).await {
+            let members = match http.get_guild_members(guild_id, None, after).await {
                 Ok(x) => x,
                 Err(err) => {
                     warn!("Failed to fetch guild members: {err}");
+                    progress.send_modify(|p| p.errors += 1);
+                    scan_error = Some(err);
                     break;
                 }
             };
@@ -511,29 +1048,86 @@ impl BotState {
             }
 
             after = Some(members.last().unwrap().user.id.get());
+            let scanned = members.len();
+            let mut page = RoleSyncRequestData::default();
 
             // iterate over this member chunk, if any of them are linked, add them to sync list
-            for member in members {
+            let linked_members = members.into_iter().filter_map(|member| {
                 let member_id = member.user.id.get();
-                if linked_ids.binary_search(&member_id).is_ok() {
-                    let req = self.make_role_sync_request_with(
-                        &member,
-                        linked_users
-                            .iter()
-                            .find(|x| x.id == member_id as i64)
-                            .unwrap(), // unwrap should be safe
-                        &linked_roles,
+                linked_ids.binary_search(&member_id).ok()?;
+
+                let linked_user = linked_users
+                    .iter()
+                    .find(|x| x.id == member_id as i64)
+                    .unwrap(); // unwrap should be safe
+
+                Some((member, linked_user.clone()))
+            });
+
+            // mirror roles from globed first, so that they aren't pushed back with the old state.
+            // this makes a request per member, so run a few at once
+            let mut pulled = stream::iter(linked_members)
+                .map(|(mut member, linked_user)| {
+                    let linked_roles = &linked_roles;
+
+                    async move {
+                        let result = self
+                            .pull_globed_roles_with(http, &mut member, &linked_user, linked_roles)
+                            .await;
+
+                        (member, linked_user, result)
+                    }
+                })
+                .buffered(PULL_CONCURRENCY);
+
+            while let Some((member, linked_user, result)) = pulled.next().await {
+                if let Err(e) = result {
+                    warn!(
+                        "Failed to sync roles of {} from Globed: {e}",
+                        member.user.id
                     );
+                    progress.send_modify(|p| p.errors += 1);
 
-                    sync_data.users.push(req);
+                    // pushing would undo the role that could not be mirrored
+                    if matches!(e, RoleSyncError::DiscordUpdate(_)) {
+                        continue;
+                    }
                 }
+
+                let mut req =
+                    self.make_role_sync_request_with(&member, &linked_user, &linked_roles);
+                self.apply_guild_grants(guild_id, &mut req).await?;
+
+                page.users.push(req);
             }
+
+            progress.send_modify(|p| {
+                p.members_scanned += scanned;
+                p.linked_found += page.users.len();
+            });
+
+            sync.push(page).await;
         }
 
-        // send a mass sync request!
-        self.send_sync_roles_req(&sync_data)
-            .await
-            .map(|()| sync_data.users.len())
+        let mut summary = sync.finish().await;
+        summary.scan_error = scan_error;
+
+        Ok(summary)
+    }
+
+    /// Starts sending a mass sync in chunks, see [`ChunkedSync`].
+    pub fn chunked_sync<'a>(
+        &'a self,
+        progress: &'a watch::Sender<SyncAllProgress>,
+    ) -> ChunkedSync<'a> {
+        ChunkedSync {
+            state: self,
+            progress,
+            pending: Vec::new(),
+            chunks: 0,
+            force: false,
+            summary: SyncAllSummary::default(),
+        }
     }
 
     pub async fn make_role_sync_request(
@@ -545,16 +1139,19 @@ impl BotState {
         // check if the user is linked
         let linked_user = sqlx::query_as!(
             LinkedUser,
-            "SELECT * FROM linked_users WHERE id = ?",
+            "SELECT id, gd_account_id FROM linked_users WHERE id = ? AND unlinked_at IS NULL",
             user_id
         )
         .fetch_one(&self.database)
         .await?;
 
-        // fetch roles from the database
-        let db_roles = self.get_all_roles().await?;
+        // fetch roles of the member's guild from the database
+        let db_roles = self.get_guild_roles(user.guild_id).await?;
 
-        Ok(self.make_role_sync_request_with(user, &linked_user, &db_roles))
+        let mut req = self.make_role_sync_request_with(user, &linked_user, &db_roles);
+        self.apply_guild_grants(user.guild_id, &mut req).await?;
+
+        Ok(req)
     }
 
     pub fn make_role_sync_request_with(
@@ -567,13 +1164,9 @@ impl BotState {
         let mut kept = Vec::new();
         let mut removed = Vec::new();
 
-        for role in all_roles {
-            // check if user has that role on discord
-            if user
-                .roles
-                .iter()
-                .any(|id| id.get() as i64 == role.discord_id)
-            {
+        for role in all_roles.iter().filter(|r| r.direction().to_globed()) {
+            // check if user has that role on discord, or matches its rule
+            if role.granted_to(user) {
                 // add to list of roles to be kept
                 kept.push(role.id.clone());
             } else {
@@ -601,52 +1194,95 @@ impl BotState {
         &self,
         data: &RoleSyncRequestData,
     ) -> Result<(), RoleSyncError> {
-        let body: String = match serde_json::to_string(data) {
-            Ok(x) => x,
-            Err(err) => {
-                error!("This should never fail: {err}");
-
-                #[cfg(debug_assertions)]
-                unreachable!();
-                #[cfg(not(debug_assertions))]
-                return Err(RoleSyncError::InternalError(
-                    "internal error in serializing data",
-                ));
+        self.send_sync_roles_req_with_retry(data).await?;
+
+        // anything still queued for these users is now outdated
+        if let Err(e) = self.settle_queued_syncs(data).await {
+            warn!("Failed to update the sync queue: {e}");
+        }
+
+        Ok(())
+    }
+
+    /// Like [`Self::send_sync_roles_req`], but if the server can't be reached, the request is written to the sync queue
+    /// and retried later in the background. Only errors that retrying won't fix are returned.
+    /// Users whose roles did not change since the last sync are left out, if nobody is left no request is made.
+    pub async fn send_or_queue_sync_roles_req(
+        &self,
+        data: &RoleSyncRequestData,
+    ) -> Result<(), RoleSyncError> {
+        let (data, unchanged) = self.without_unchanged_syncs(data).await;
+
+        if unchanged > 0 {
+            debug!("Skipping sync of {unchanged} users, their roles did not change");
+        }
+
+        if data.users.is_empty() {
+            return Ok(());
+        }
+
+        match self.send_sync_roles_req(&data).await {
+            Err(err) if err.is_transient() => {
+                self.enqueue_sync(&data).await?;
+
+                warn!(
+                    "Queued role sync of {} users to retry later: {err}",
+                    data.users.len()
+                );
+
+                Ok(())
             }
-        };
 
-        let response = match self
-            .http_client
-            .post(format!("{}/gsp/sync_roles", self.base_url))
-            .header("Authorization", &self.server_password)
-            .header("Content-Type", "application/json")
-            .body(body)
-            .send()
-            .await
-        {
-            Ok(resp) => resp,
-            Err(e) => {
-                return Err(RoleSyncError::ServerRequest(e));
+            res => res,
+        }
+    }
+
+    // sends a sync request, retrying transient failures
+    pub(crate) async fn send_sync_roles_req_with_retry(
+        &self,
+        data: &RoleSyncRequestData,
+    ) -> Result<(), RoleSyncError> {
+        let policy = &self.config.sync.retry;
+        let mut attempt = 1;
+
+        loop {
+            let err = match self.backend.sync_roles(data).await {
+                Ok(()) => {
+                    if let Err(e) = self.record_synced_roles(data).await {
+                        warn!("Failed to store synced roles: {e}");
+                    }
+
+                    return Ok(());
+                }
+                Err(e) => e,
+            };
+
+            if !err.is_transient() || attempt >= policy.max_attempts {
+                warn!(
+                    "Role sync attempt {attempt}/{} failed, giving up: {err}",
+                    policy.max_attempts
+                );
+
+                return Err(if attempt > 1 {
+                    RoleSyncError::RetriesExhausted(attempt, Box::new(err))
+                } else {
+                    err
+                });
             }
-        };
 
-        let status = response.status();
-        if !status.is_success() {
-            let message = response
-                .text()
-                .await
-                .unwrap_or_else(|_| "<no message>".to_owned());
+            // honor Retry-After, if the server sent one, but don't let it stall us for longer than any backoff would
+            let delay = match &err {
+                RoleSyncError::RateLimited(Some(after)) => (*after).min(policy.max_delay),
+                _ => policy.backoff_delay(attempt),
+            };
 
             warn!(
-                "Role update failed: code {}, message: {}",
-                status.as_u16(),
-                message
+                "Role sync attempt {attempt}/{} failed, retrying in {delay:?}: {err}",
+                policy.max_attempts
             );
 
-            return Err(RoleSyncError::ServerUpdate((status, message)));
+            tokio::time::sleep(delay).await;
+            attempt += 1;
         }
-
-        // success!
-        Ok(())
     }
 }
@@ -0,0 +1,294 @@
+use std::sync::Arc;
+
+use log::{error, info, warn};
+use time::OffsetDateTime;
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    db::QueuedSync,
+    state::{BotState, RoleSyncError, RoleSyncRequest, RoleSyncRequestData},
+};
+
+impl QueuedSync {
+    pub fn to_request(&self) -> Result<RoleSyncRequest, serde_json::Error> {
+        Ok(RoleSyncRequest {
+            account_id: self.account_id as i32,
+            keep: serde_json::from_str(&self.keep)?,
+            remove: serde_json::from_str(&self.remove)?,
+        })
+    }
+}
+
+// applies a newer request on top of a queued one, for every role the newer state wins
+fn merge_request(queued: &mut RoleSyncRequest, newer: &RoleSyncRequest) {
+    queued
+        .keep
+        .retain(|role| !newer.remove.contains(role) && !newer.keep.contains(role));
+    queued
+        .remove
+        .retain(|role| !newer.keep.contains(role) && !newer.remove.contains(role));
+
+    queued.keep.extend(newer.keep.iter().cloned());
+    queued.remove.extend(newer.remove.iter().cloned());
+
+    queued.keep.sort();
+    queued.remove.sort();
+}
+
+impl BotState {
+    /// Writes the users of a failed sync request to the sync queue, merging them with entries already queued for the same account.
+    pub async fn enqueue_sync(&self, data: &RoleSyncRequestData) -> Result<(), sqlx::Error> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let mut tx = self.database.begin().await?;
+
+        for user in &data.users {
+            let account_id = user.account_id as i64;
+
+            let existing = sqlx::query_as!(
+                QueuedSync,
+                "SELECT * FROM sync_queue WHERE account_id = ?",
+                account_id
+            )
+            .fetch_optional(&mut *tx)
+            .await?;
+
+            let mut merged = match existing.as_ref().map(QueuedSync::to_request) {
+                Some(Ok(queued)) => queued,
+                Some(Err(e)) => {
+                    warn!("Replacing malformed sync queue entry for {account_id}: {e}");
+                    RoleSyncRequest {
+                        account_id: user.account_id,
+                        keep: Vec::new(),
+                        remove: Vec::new(),
+                    }
+                }
+                None => RoleSyncRequest {
+                    account_id: user.account_id,
+                    keep: Vec::new(),
+                    remove: Vec::new(),
+                },
+            };
+
+            merge_request(&mut merged, user);
+
+            // serializing a vec of strings can't fail
+            let keep = serde_json::to_string(&merged.keep).unwrap();
+            let remove = serde_json::to_string(&merged.remove).unwrap();
+
+            sqlx::query!(
+                "INSERT INTO sync_queue (account_id, keep, remove, queued_at) VALUES (?, ?, ?, ?)
+                ON CONFLICT(account_id) DO UPDATE SET keep = excluded.keep, remove = excluded.remove, queued_at = excluded.queued_at, version = version + 1",
+                account_id,
+                keep,
+                remove,
+                now
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await
+    }
+
+    /// Removes roles that were just synced directly from queued entries, so that the worker does not overwrite them with an older state later.
+    pub(crate) async fn settle_queued_syncs(
+        &self,
+        data: &RoleSyncRequestData,
+    ) -> Result<(), sqlx::Error> {
+        let queued = sqlx::query_scalar!("SELECT COUNT(*) FROM sync_queue")
+            .fetch_one(&self.database)
+            .await?;
+
+        if queued == 0 {
+            return Ok(());
+        }
+
+        let mut tx = self.database.begin().await?;
+
+        for user in &data.users {
+            let account_id = user.account_id as i64;
+
+            let Some(existing) = sqlx::query_as!(
+                QueuedSync,
+                "SELECT * FROM sync_queue WHERE account_id = ?",
+                account_id
+            )
+            .fetch_optional(&mut *tx)
+            .await?
+            else {
+                continue;
+            };
+
+            let mut remaining = match existing.to_request() {
+                Ok(x) => x,
+                Err(_) => continue, // the worker takes care of malformed entries
+            };
+
+            remaining
+                .keep
+                .retain(|role| !user.keep.contains(role) && !user.remove.contains(role));
+            remaining
+                .remove
+                .retain(|role| !user.keep.contains(role) && !user.remove.contains(role));
+
+            if remaining.keep.is_empty() && remaining.remove.is_empty() {
+                sqlx::query!("DELETE FROM sync_queue WHERE account_id = ?", account_id)
+                    .execute(&mut *tx)
+                    .await?;
+            } else {
+                let keep = serde_json::to_string(&remaining.keep).unwrap();
+                let remove = serde_json::to_string(&remaining.remove).unwrap();
+
+                sqlx::query!(
+                    "UPDATE sync_queue SET keep = ?, remove = ?, version = version + 1 WHERE account_id = ?",
+                    keep,
+                    remove,
+                    account_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+
+        tx.commit().await
+    }
+
+    pub async fn get_queued_syncs(&self) -> Result<Vec<QueuedSync>, sqlx::Error> {
+        sqlx::query_as!(
+            QueuedSync,
+            "SELECT * FROM sync_queue ORDER BY queued_at, account_id"
+        )
+        .fetch_all(&self.database)
+        .await
+    }
+
+    // deletes a queue entry, unless it was changed after it was read
+    async fn remove_queued_sync(&self, entry: &QueuedSync) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "DELETE FROM sync_queue WHERE account_id = ? AND version = ?",
+            entry.account_id,
+            entry.version
+        )
+        .execute(&self.database)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn bump_queued_attempts(&self, entries: &[QueuedSync]) -> Result<(), sqlx::Error> {
+        for entry in entries {
+            sqlx::query!(
+                "UPDATE sync_queue SET attempts = attempts + 1 WHERE account_id = ?",
+                entry.account_id
+            )
+            .execute(&self.database)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends everything in the sync queue to the server, returns how many users were synced.
+    /// If the server is still unreachable, entries stay queued for the next try.
+    pub async fn process_sync_queue(&self) -> Result<usize, RoleSyncError> {
+        let mut entries = Vec::new();
+        let mut requests = Vec::new();
+
+        for entry in self.get_queued_syncs().await? {
+            match entry.to_request() {
+                Ok(req) => {
+                    entries.push(entry);
+                    requests.push(req);
+                }
+                Err(e) => {
+                    error!(
+                        "Dropping malformed sync queue entry for {}: {e}",
+                        entry.account_id
+                    );
+                    self.remove_queued_sync(&entry).await?;
+                }
+            }
+        }
+
+        if entries.is_empty() {
+            return Ok(0);
+        }
+
+        let data = RoleSyncRequestData { users: requests };
+
+        match self.send_sync_roles_req_with_retry(&data).await {
+            Ok(()) => {
+                for entry in &entries {
+                    self.remove_queued_sync(entry).await?;
+                }
+
+                return Ok(entries.len());
+            }
+
+            Err(e) if e.is_transient() => {
+                self.bump_queued_attempts(&entries).await?;
+                return Err(e);
+            }
+
+            Err(e) => {
+                warn!("Queued sync batch was rejected, retrying users one by one: {e}");
+            }
+        }
+
+        // the server rejected the batch, find the entries it doesn't like
+        let mut synced = 0;
+
+        for (entry, req) in entries.iter().zip(data.users) {
+            let single = RoleSyncRequestData { users: vec![req] };
+
+            match self.send_sync_roles_req_with_retry(&single).await {
+                Ok(()) => {
+                    self.remove_queued_sync(entry).await?;
+                    synced += 1;
+                }
+
+                Err(e) if e.is_transient() => {
+                    self.bump_queued_attempts(std::slice::from_ref(entry))
+                        .await?;
+                    return Err(e);
+                }
+
+                Err(e) => {
+                    error!(
+                        "Dropping queued sync for {}, the server rejected it: {e}",
+                        entry.account_id
+                    );
+                    self.remove_queued_sync(entry).await?;
+
+                    self.audit(
+                        AuditEvent::new(AuditAction::SyncFailed)
+                            .account(entry.account_id as i32)
+                            .details(format!("queued sync rejected: {e}")),
+                    )
+                    .await;
+                }
+            }
+        }
+
+        Ok(synced)
+    }
+}
+
+/// Starts the background task that periodically drains the sync queue.
+pub fn spawn_sync_queue_worker(state: Arc<BotState>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(state.config.sync.queue_interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            match state.process_sync_queue().await {
+                Ok(0) => {}
+                Ok(count) => info!("Synced {count} users from the sync queue"),
+                Err(e) => warn!("Failed to process the sync queue, trying again later: {e}"),
+            }
+        }
+    })
+}
@@ -0,0 +1,171 @@
+use log::warn;
+use time::OffsetDateTime;
+
+use crate::{
+    db::SyncedRoles,
+    state::{BotState, RoleSyncRequest, RoleSyncRequestData},
+};
+
+impl SyncedRoles {
+    pub fn to_roles(&self) -> Result<Vec<String>, serde_json::Error> {
+        serde_json::from_str(&self.roles)
+    }
+}
+
+// whether sending this request would change anything, given the roles the account had after the last sync
+fn changes_roles(req: &RoleSyncRequest, synced: &[String]) -> bool {
+    req.keep.iter().any(|role| !synced.contains(role))
+        || req.remove.iter().any(|role| synced.contains(role))
+}
+
+impl BotState {
+    /// Returns the Globed roles of an account as of the last successful sync, `None` if it was never synced.
+    pub async fn get_synced_roles(
+        &self,
+        account_id: i32,
+    ) -> Result<Option<Vec<String>>, sqlx::Error> {
+        let account_id = account_id as i64;
+
+        let entry = sqlx::query_as!(
+            SyncedRoles,
+            "SELECT * FROM synced_roles WHERE account_id = ?",
+            account_id
+        )
+        .fetch_optional(&self.database)
+        .await?;
+
+        Ok(match entry.as_ref().map(SyncedRoles::to_roles) {
+            Some(Ok(roles)) => Some(roles),
+            Some(Err(e)) => {
+                warn!("Ignoring malformed synced roles of {account_id}: {e}");
+                None
+            }
+            None => None,
+        })
+    }
+
+    /// Applies a request that the server accepted to the stored snapshots of its users.
+    pub(crate) async fn record_synced_roles(
+        &self,
+        data: &RoleSyncRequestData,
+    ) -> Result<(), sqlx::Error> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let mut tx = self.database.begin().await?;
+
+        for user in &data.users {
+            let account_id = user.account_id as i64;
+
+            let existing = sqlx::query_as!(
+                SyncedRoles,
+                "SELECT * FROM synced_roles WHERE account_id = ?",
+                account_id
+            )
+            .fetch_optional(&mut *tx)
+            .await?;
+
+            let mut roles = existing
+                .as_ref()
+                .and_then(|x| x.to_roles().ok())
+                .unwrap_or_default();
+
+            roles.retain(|role| !user.remove.contains(role));
+            for role in &user.keep {
+                if !roles.contains(role) {
+                    roles.push(role.clone());
+                }
+            }
+            roles.sort();
+
+            let roles = serde_json::to_string(&roles).unwrap();
+
+            sqlx::query!(
+                "INSERT INTO synced_roles (account_id, roles, synced_at) VALUES (?, ?, ?)
+                ON CONFLICT(account_id) DO UPDATE SET roles = excluded.roles, synced_at = excluded.synced_at",
+                account_id,
+                roles,
+                now
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await
+    }
+
+    /// Replaces the snapshot of an account with roles fetched from the server.
+    pub(crate) async fn replace_synced_roles(
+        &self,
+        account_id: i32,
+        roles: &[String],
+    ) -> Result<(), sqlx::Error> {
+        let account_id = account_id as i64;
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+
+        let mut roles = roles.to_vec();
+        roles.sort();
+        let roles = serde_json::to_string(&roles).unwrap();
+
+        sqlx::query!(
+            "INSERT INTO synced_roles (account_id, roles, synced_at) VALUES (?, ?, ?)
+            ON CONFLICT(account_id) DO UPDATE SET roles = excluded.roles, synced_at = excluded.synced_at",
+            account_id,
+            roles,
+            now
+        )
+        .execute(&self.database)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Removes users from the request whose roles already match the last successful sync, returns how many were removed.
+    /// Users that were never synced, or still have a sync queued, are always kept.
+    pub async fn drop_unchanged_syncs(
+        &self,
+        data: &mut RoleSyncRequestData,
+    ) -> Result<usize, sqlx::Error> {
+        let before = data.users.len();
+        let mut users = Vec::with_capacity(before);
+
+        for user in std::mem::take(&mut data.users) {
+            let account_id = user.account_id as i64;
+
+            let queued = sqlx::query_scalar!(
+                "SELECT COUNT(*) FROM sync_queue WHERE account_id = ?",
+                account_id
+            )
+            .fetch_one(&self.database)
+            .await?;
+
+            let unchanged = queued == 0
+                && self
+                    .get_synced_roles(user.account_id)
+                    .await?
+                    .is_some_and(|synced| !changes_roles(&user, &synced));
+
+            if !unchanged {
+                users.push(user);
+            }
+        }
+
+        data.users = users;
+
+        Ok(before - data.users.len())
+    }
+
+    // copy of the request without unchanged users, if the snapshots can't be read everyone is kept
+    pub(crate) async fn without_unchanged_syncs(
+        &self,
+        data: &RoleSyncRequestData,
+    ) -> (RoleSyncRequestData, usize) {
+        let mut filtered = data.clone();
+
+        match self.drop_unchanged_syncs(&mut filtered).await {
+            Ok(dropped) => (filtered, dropped),
+            Err(e) => {
+                warn!("Failed to read synced roles, syncing everyone: {e}");
+                (data.clone(), 0)
+            }
+        }
+    }
+}
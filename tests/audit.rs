@@ -0,0 +1,113 @@
+mod common;
+
+use auto_role_bot::{
+    audit::{AuditAction, AuditEvent, AuditFilter},
+    serenity::{GuildId, UserId},
+};
+use common::*;
+
+#[tokio::test]
+async fn audit_log_filters_and_pages() {
+    let (state, _backend) = setup().await;
+    let admin = UserId::new(9);
+
+    state
+        .audit(
+            AuditEvent::new(AuditAction::Link)
+                .actor(UserId::new(1))
+                .target(UserId::new(1))
+                .account(500)
+                .guild(GuildId::new(GUILD)),
+        )
+        .await;
+    state
+        .audit(
+            AuditEvent::new(AuditAction::AdminLink)
+                .actor(admin)
+                .target(UserId::new(2))
+                .account(600),
+        )
+        .await;
+    state
+        .audit(
+            AuditEvent::new(AuditAction::MemberLeft)
+                .target(UserId::new(1))
+                .account(500)
+                .details("unlinked"),
+        )
+        .await;
+
+    let (all, total) = state
+        .query_audit_log(&AuditFilter::default(), 0, 10)
+        .await
+        .unwrap();
+    assert_eq!(total, 3);
+
+    // newest first
+    let actions: Vec<&str> = all.iter().map(|e| e.action.as_str()).collect();
+    assert_eq!(actions, vec!["member_left", "admin_link", "link"]);
+    assert_eq!(all[0].actor_id, None);
+    assert_eq!(all[0].details.as_deref(), Some("unlinked"));
+
+    // a user matches as both actor and target
+    let by_user = AuditFilter {
+        user: Some(UserId::new(1)),
+        ..Default::default()
+    };
+    assert_eq!(state.query_audit_log(&by_user, 0, 10).await.unwrap().1, 2);
+
+    let by_admin = AuditFilter {
+        user: Some(admin),
+        ..Default::default()
+    };
+    assert_eq!(state.query_audit_log(&by_admin, 0, 10).await.unwrap().1, 1);
+
+    let by_account = AuditFilter {
+        account_id: Some(500),
+        action: Some(AuditAction::Link),
+        ..Default::default()
+    };
+    let (entries, total) = state.query_audit_log(&by_account, 0, 10).await.unwrap();
+    assert_eq!(total, 1);
+    assert_eq!(entries[0].guild_id, Some(GUILD as i64));
+
+    // pagination
+    let (page, total) = state
+        .query_audit_log(&AuditFilter::default(), 1, 2)
+        .await
+        .unwrap();
+    assert_eq!(total, 3);
+    assert_eq!(page.len(), 1);
+    assert_eq!(page[0].action, "link");
+}
+
+#[tokio::test]
+async fn rejected_queued_sync_is_audited() {
+    let (state, backend) = setup().await;
+
+    backend.reject_role("bad");
+    for _ in 0..3 {
+        backend.fail_next_sync(reqwest::StatusCode::SERVICE_UNAVAILABLE, "down");
+    }
+
+    state
+        .send_or_queue_sync_roles_req(&auto_role_bot::backend::RoleSyncRequestData {
+            users: vec![auto_role_bot::backend::RoleSyncRequest {
+                account_id: 500,
+                keep: strings(&["bad"]),
+                remove: Vec::new(),
+            }],
+        })
+        .await
+        .unwrap();
+
+    state.process_sync_queue().await.unwrap();
+
+    let filter = AuditFilter {
+        action: Some(AuditAction::SyncFailed),
+        ..Default::default()
+    };
+    let (entries, _) = state.query_audit_log(&filter, 0, 10).await.unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].account_id, Some(500));
+}
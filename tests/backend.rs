@@ -0,0 +1,156 @@
+use std::time::Duration;
+
+use auto_role_bot::{
+    backend::{GlobedBackend, HttpBackend, RoleSyncRequest, RoleSyncRequestData},
+    config::ServerConfig,
+    mock_server::MockServer,
+    state::{LinkError, RoleSyncError},
+};
+use reqwest::StatusCode;
+
+async fn start() -> (MockServer, HttpBackend) {
+    let server = MockServer::start("127.0.0.1:0", "secret").await.unwrap();
+    server.add_user(500, "Player", 1234);
+
+    let backend = HttpBackend::new(&ServerConfig {
+        base_url: server.base_url(),
+        password: "secret".to_owned(),
+        request_timeout: Duration::from_secs(5),
+    })
+    .unwrap();
+
+    (server, backend)
+}
+
+fn request() -> RoleSyncRequestData {
+    RoleSyncRequestData {
+        users: vec![RoleSyncRequest {
+            account_id: 500,
+            keep: vec!["mod".to_owned()],
+            remove: vec!["vip".to_owned()],
+        }],
+    }
+}
+
+#[tokio::test]
+async fn http_lookup() {
+    let (server, backend) = start().await;
+
+    let user = backend.lookup_user("player", Some(1234)).await.unwrap();
+    assert_eq!((user.account_id, user.name.as_str()), (500, "Player"));
+
+    assert!(matches!(
+        backend.lookup_user("player", Some(1)).await,
+        Err(LinkError::UserNotFound)
+    ));
+
+    // bypassing verification ignores the code
+    assert!(backend.lookup_user("player", None).await.is_ok());
+
+    server.fail_next_lookup(StatusCode::SERVICE_UNAVAILABLE);
+    assert!(matches!(
+        backend.lookup_user("player", Some(1234)).await,
+        Err(LinkError::ServerInternalError(
+            StatusCode::SERVICE_UNAVAILABLE,
+            _
+        ))
+    ));
+}
+
+#[tokio::test]
+async fn http_sync_roles() {
+    let (server, backend) = start().await;
+
+    backend.sync_roles(&request()).await.unwrap();
+    assert_eq!(server.sync_requests(), vec![request()]);
+    assert_eq!(server.account_roles(500), vec!["mod".to_owned()]);
+
+    server.fail_next_sync(StatusCode::BAD_GATEWAY);
+    assert!(matches!(
+        backend.sync_roles(&request()).await,
+        Err(RoleSyncError::ServerUpdate((StatusCode::BAD_GATEWAY, _)))
+    ));
+
+    // rejected payloads are recorded too, but not applied
+    assert_eq!(server.sync_requests(), vec![request(), request()]);
+    assert_eq!(server.accepted_sync_requests(), vec![request()]);
+}
+
+#[tokio::test]
+async fn http_forced_sync_status() {
+    let (server, backend) = start().await;
+
+    server.set_sync_status(Some(StatusCode::INTERNAL_SERVER_ERROR));
+    assert!(matches!(
+        backend.sync_roles(&request()).await,
+        Err(RoleSyncError::ServerUpdate((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            _
+        )))
+    ));
+    assert_eq!(server.sync_requests(), vec![request()]);
+    assert!(server.accepted_sync_requests().is_empty());
+    assert!(server.account_roles(500).is_empty());
+
+    server.set_sync_status(None);
+    backend.sync_roles(&request()).await.unwrap();
+    assert_eq!(server.accepted_sync_requests(), vec![request()]);
+}
+
+#[tokio::test]
+async fn http_rate_limited() {
+    let (server, backend) = start().await;
+
+    server.rate_limit_next_sync(Some(7));
+    assert!(matches!(
+        backend.sync_roles(&request()).await,
+        Err(RoleSyncError::RateLimited(Some(after))) if after == Duration::from_secs(7)
+    ));
+
+    server.rate_limit_next_sync(None);
+    assert!(matches!(
+        backend.sync_roles(&request()).await,
+        Err(RoleSyncError::RateLimited(None))
+    ));
+}
+
+#[tokio::test]
+async fn http_wrong_password() {
+    let (server, _) = start().await;
+
+    let backend = HttpBackend::new(&ServerConfig {
+        base_url: server.base_url(),
+        password: "wrong".to_owned(),
+        request_timeout: Duration::from_secs(5),
+    })
+    .unwrap();
+
+    assert!(matches!(
+        backend.sync_roles(&request()).await,
+        Err(RoleSyncError::ServerUpdate((StatusCode::UNAUTHORIZED, _)))
+    ));
+    assert!(matches!(
+        backend.lookup_user("player", Some(1234)).await,
+        Err(LinkError::ServerInternalError(StatusCode::UNAUTHORIZED, _))
+    ));
+    assert!(server.sync_requests().is_empty());
+}
+
+#[tokio::test]
+async fn http_get_roles() {
+    let (server, backend) = start().await;
+
+    assert!(backend.get_roles(500).await.unwrap().is_empty());
+
+    backend.sync_roles(&request()).await.unwrap();
+    assert_eq!(
+        backend.get_roles(500).await.unwrap(),
+        vec!["mod".to_owned()]
+    );
+
+    server.set_account_roles(500, &["booster", "mod"]);
+    assert_eq!(
+        backend.get_roles(500).await.unwrap(),
+        vec!["booster".to_owned(), "mod".to_owned()]
+    );
+}
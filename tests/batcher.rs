@@ -0,0 +1,184 @@
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use auto_role_bot::{
+    backend::{RoleSyncRequest, RoleSyncRequestData},
+    batcher::spawn_sync_batcher,
+    db::SyncDirection,
+    serenity::{GuildId, UserId},
+    state::BotState,
+};
+use common::*;
+
+async fn add_roles(state: &BotState) {
+    let guild = GuildId::new(GUILD);
+    state
+        .add_role(guild, 11, "mod", SyncDirection::DiscordToGlobed)
+        .await
+        .unwrap();
+    state
+        .add_role(guild, 12, "vip", SyncDirection::DiscordToGlobed)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn repeated_updates_collapse_into_one_batch() {
+    let (state, backend) = setup().await;
+    add_roles(&state).await;
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+    state.add_linked_user(UserId::new(2), 600).await.unwrap();
+
+    state
+        .request_auto_sync(&member(GUILD, 1, &[11]))
+        .await
+        .unwrap();
+    state
+        .request_auto_sync(&member(GUILD, 2, &[11]))
+        .await
+        .unwrap();
+    state
+        .request_auto_sync(&member(GUILD, 1, &[12]))
+        .await
+        .unwrap();
+    // not linked, dropped when flushing
+    state
+        .request_auto_sync(&member(GUILD, 3, &[11]))
+        .await
+        .unwrap();
+
+    assert_eq!(state.pending_sync_count(), 3);
+    assert!(backend.sync_requests().is_empty());
+
+    assert_eq!(state.flush_pending_syncs().await.unwrap(), 2);
+    assert_eq!(state.pending_sync_count(), 0);
+    assert_eq!(
+        backend.sync_requests(),
+        vec![RoleSyncRequestData {
+            users: vec![
+                RoleSyncRequest {
+                    account_id: 500,
+                    keep: strings(&["vip"]),
+                    remove: strings(&["mod"]),
+                },
+                RoleSyncRequest {
+                    account_id: 600,
+                    keep: strings(&["mod"]),
+                    remove: strings(&["vip"]),
+                },
+            ],
+        }]
+    );
+
+    assert_eq!(state.flush_pending_syncs().await.unwrap(), 0);
+    assert_eq!(backend.sync_requests().len(), 1);
+}
+
+#[tokio::test]
+async fn zero_window_syncs_right_away() {
+    let mut config = test_config();
+    config.sync.batch_window = Duration::ZERO;
+
+    let (state, backend) = setup_with_config(config).await;
+    add_roles(&state).await;
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+
+    state
+        .request_auto_sync(&member(GUILD, 1, &[11]))
+        .await
+        .unwrap();
+
+    assert_eq!(state.pending_sync_count(), 0);
+    assert_eq!(backend.sync_requests().len(), 1);
+    assert!(spawn_sync_batcher(Arc::new(state)).is_none());
+}
+
+#[tokio::test]
+async fn batcher_task_flushes_after_window() {
+    let (state, backend) = setup().await;
+    add_roles(&state).await;
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+
+    let state = Arc::new(state);
+    let task = spawn_sync_batcher(state.clone()).unwrap();
+
+    state
+        .request_auto_sync(&member(GUILD, 1, &[11]))
+        .await
+        .unwrap();
+    state
+        .request_auto_sync(&member(GUILD, 1, &[11, 12]))
+        .await
+        .unwrap();
+
+    for _ in 0..100 {
+        if !backend.sync_requests().is_empty() {
+            break;
+        }
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    task.abort();
+
+    let requests = backend.sync_requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].users[0].keep, strings(&["mod", "vip"]));
+}
+
+#[tokio::test]
+async fn rejected_batch_is_retried_per_user() {
+    let (state, backend) = setup().await;
+    add_roles(&state).await;
+    state
+        .add_role(
+            GuildId::new(OTHER_GUILD),
+            21,
+            "helper",
+            SyncDirection::DiscordToGlobed,
+        )
+        .await
+        .unwrap();
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+    state.add_linked_user(UserId::new(2), 600).await.unwrap();
+
+    // only requests of the first guild mention this role
+    backend.reject_role("vip");
+
+    state
+        .request_auto_sync(&member(GUILD, 1, &[11]))
+        .await
+        .unwrap();
+    state
+        .request_auto_sync(&member(OTHER_GUILD, 2, &[21]))
+        .await
+        .unwrap();
+
+    assert_eq!(state.flush_pending_syncs().await.unwrap(), 1);
+    assert_eq!(backend.account_roles(600), strings(&["helper"]));
+    assert!(backend.account_roles(500).is_empty());
+    assert_eq!(state.pending_sync_count(), 0);
+}
+
+#[tokio::test]
+async fn failed_flush_keeps_members_pending() {
+    let (state, backend) = setup().await;
+    add_roles(&state).await;
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+
+    state
+        .request_auto_sync(&member(GUILD, 1, &[11]))
+        .await
+        .unwrap();
+    state
+        .request_auto_sync(&member(GUILD, 2, &[11]))
+        .await
+        .unwrap();
+
+    state.database.close().await;
+
+    assert!(state.flush_pending_syncs().await.is_err());
+    assert_eq!(state.pending_sync_count(), 2);
+    assert!(backend.sync_requests().is_empty());
+}
@@ -0,0 +1,192 @@
+mod common;
+
+use auto_role_bot::{
+    blocklist::{BanOutcome, BlockSource},
+    db::{SyncDirection, UnlinkReason},
+    serenity::{GuildId, UserId},
+    state::{BotState, LinkError},
+};
+use common::*;
+
+#[tokio::test]
+async fn blocked_accounts_cannot_be_linked() {
+    let (state, backend) = setup().await;
+    backend.add_user(500, "Player", 1234);
+
+    let source = BlockSource {
+        blocked_by: Some(UserId::new(9)),
+        reason: Some("alt".to_owned()),
+        ..Default::default()
+    };
+    assert!(state.block_account(500, &source).await.unwrap());
+    assert!(!state.block_account(500, &source).await.unwrap());
+
+    assert!(matches!(
+        state.add_linked_user(UserId::new(1), 500).await,
+        Err(LinkError::AccountBlocked)
+    ));
+    assert!(matches!(
+        state
+            .link_user(&member(GUILD, 1, &[]), "player", Some(1234))
+            .await,
+        Err(LinkError::AccountBlocked)
+    ));
+
+    let blocked = state.get_blocked_accounts().await.unwrap();
+    assert_eq!(blocked.len(), 1);
+    assert_eq!(blocked[0].blocked_by, Some(9));
+    assert_eq!(blocked[0].reason.as_deref(), Some("alt"));
+
+    assert!(state.unblock_account(500).await.unwrap());
+    assert!(!state.unblock_account(500).await.unwrap());
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+}
+
+async fn ban(state: &BotState, user: UserId, in_other_guild: bool) -> BanOutcome {
+    state
+        .ban_user(user, GuildId::new(GUILD), in_other_guild, None)
+        .await
+        .unwrap()
+}
+
+// moves the end of a user's last link back in time
+async fn age_unlink(state: &BotState, user: UserId, days: i64) {
+    sqlx::query("UPDATE linked_users SET unlinked_at = unlinked_at - ? WHERE id = ?")
+        .bind(days * 24 * 60 * 60)
+        .bind(user.get() as i64)
+        .execute(&state.database)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn ban_unlinks_and_blocks_until_unban() {
+    let (state, _backend) = setup().await;
+    let user = UserId::new(1);
+
+    // never linked, nothing to block
+    assert_eq!(ban(&state, user, false).await, BanOutcome::Ignored);
+
+    state.add_linked_user(user, 500).await.unwrap();
+    assert_eq!(
+        ban(&state, user, false).await,
+        BanOutcome::Blocked {
+            account_id: 500,
+            unlinked: true
+        }
+    );
+
+    assert!(!state.is_linked(user).await.unwrap());
+    assert!(state.is_account_blocked(500).await.unwrap());
+    assert_eq!(
+        state.get_user_link_history(user).await.unwrap()[0].reason(),
+        Some(UnlinkReason::Banned)
+    );
+
+    // an alt can't take over the account
+    assert!(matches!(
+        state.add_linked_user(UserId::new(2), 500).await,
+        Err(LinkError::AccountBlocked)
+    ));
+
+    assert_eq!(state.unban_user(user).await.unwrap(), vec![500]);
+    assert!(!state.is_account_blocked(500).await.unwrap());
+}
+
+#[tokio::test]
+async fn unban_keeps_manual_blocks() {
+    let (state, _backend) = setup().await;
+    let user = UserId::new(1);
+
+    state.add_linked_user(user, 500).await.unwrap();
+    state
+        .block_account(500, &BlockSource::default())
+        .await
+        .unwrap();
+    ban(&state, user, false).await;
+
+    assert!(state.unban_user(user).await.unwrap().is_empty());
+    assert!(state.is_account_blocked(500).await.unwrap());
+}
+
+#[tokio::test]
+async fn ban_blocks_account_only_after_a_recent_leave() {
+    let (state, _backend) = setup().await;
+    let user = UserId::new(1);
+
+    // left and got banned soon after
+    state.add_linked_user(user, 500).await.unwrap();
+    state
+        .unlink_user(user, UnlinkReason::LeftGuild)
+        .await
+        .unwrap();
+    assert_eq!(
+        ban(&state, user, false).await,
+        BanOutcome::Blocked {
+            account_id: 500,
+            unlinked: false
+        }
+    );
+    state.unban_user(user).await.unwrap();
+
+    // left too long ago
+    age_unlink(&state, user, 8).await;
+    assert_eq!(ban(&state, user, false).await, BanOutcome::Ignored);
+
+    // unlinked themselves
+    state.add_linked_user(user, 500).await.unwrap();
+    state.unlink_user(user, UnlinkReason::User).await.unwrap();
+    assert_eq!(ban(&state, user, false).await, BanOutcome::Ignored);
+    assert!(!state.is_account_blocked(500).await.unwrap());
+}
+
+#[tokio::test]
+async fn ban_never_blocks_someone_elses_account() {
+    let (state, _backend) = setup().await;
+    let user = UserId::new(1);
+    let other = UserId::new(2);
+
+    // moved to another user
+    state.add_linked_user(user, 500).await.unwrap();
+    state.transfer_link(user, other).await.unwrap();
+    assert_eq!(ban(&state, user, false).await, BanOutcome::Ignored);
+
+    // left, then someone else linked the account
+    state.add_linked_user(user, 600).await.unwrap();
+    state
+        .unlink_user(user, UnlinkReason::LeftGuild)
+        .await
+        .unwrap();
+    state.add_linked_user(UserId::new(3), 600).await.unwrap();
+    assert_eq!(ban(&state, user, false).await, BanOutcome::Ignored);
+
+    assert!(state.get_blocked_accounts().await.unwrap().is_empty());
+    assert!(state.is_linked(other).await.unwrap());
+}
+
+#[tokio::test]
+async fn ban_in_one_guild_keeps_link_of_member_of_another() {
+    let (state, backend) = setup().await;
+    let user = UserId::new(1);
+
+    state
+        .add_role(
+            GuildId::new(GUILD),
+            11,
+            "mod",
+            SyncDirection::DiscordToGlobed,
+        )
+        .await
+        .unwrap();
+    state.add_linked_user(user, 500).await.unwrap();
+    backend.set_account_roles(500, &["mod"]);
+
+    // like leaving one of several guilds: only the roles of the banning guild go
+    assert_eq!(
+        ban(&state, user, true).await,
+        BanOutcome::RolesStripped(500)
+    );
+    assert!(state.is_linked(user).await.unwrap());
+    assert!(!state.is_account_blocked(500).await.unwrap());
+    assert!(backend.account_roles(500).is_empty());
+}
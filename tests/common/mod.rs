@@ -0,0 +1,99 @@
+#![allow(dead_code)]
+
+use std::{sync::Arc, time::Duration};
+
+use auto_role_bot::{
+    backend::MemoryBackend,
+    config::{BotConfig, GuildConfig, LogConfig, RetryConfig, ServerConfig, SyncConfig},
+    logger::LogLevelFilter,
+    serenity::{GuildId, Member, RoleId, UserId},
+    state::BotState,
+};
+use sqlx::sqlite::SqlitePoolOptions;
+
+pub const GUILD: u64 = 1000;
+pub const OTHER_GUILD: u64 = 2000;
+
+pub fn test_config() -> BotConfig {
+    BotConfig {
+        token: "token".to_owned(),
+        database_url: None,
+        guilds: vec![
+            GuildConfig {
+                id: GUILD,
+                log_channel: None,
+            },
+            GuildConfig {
+                id: OTHER_GUILD,
+                log_channel: None,
+            },
+        ],
+        skip_sync_all: true,
+        server: ServerConfig {
+            base_url: "http://127.0.0.1:1".to_owned(),
+            password: "password".to_owned(),
+            request_timeout: Duration::from_secs(5),
+        },
+        sync: SyncConfig {
+            retry: RetryConfig {
+                max_attempts: 3,
+                initial_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(5),
+            },
+            queue_interval: Duration::from_secs(30),
+            batch_window: Duration::from_millis(20),
+            chunk_size: 200,
+            reconcile_interval: Duration::ZERO,
+            unlink_grace_period: Duration::ZERO,
+        },
+        log: LogConfig {
+            level: LogLevelFilter::Off,
+            file: false,
+        },
+    }
+}
+
+/// In-memory sqlite database with all migrations applied.
+pub async fn test_database() -> sqlx::SqlitePool {
+    // a single connection that never closes, every new connection would get its own empty database
+    let db = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await
+        .expect("failed to open in-memory database");
+
+    sqlx::migrate!()
+        .run(&db)
+        .await
+        .expect("failed to run migrations");
+
+    db
+}
+
+pub async fn setup_with_config(config: BotConfig) -> (BotState, Arc<MemoryBackend>) {
+    let backend = Arc::new(MemoryBackend::new());
+    let state = BotState::with_backend(&config, test_database().await, backend.clone())
+        .await
+        .expect("failed to create bot state");
+
+    (state, backend)
+}
+
+pub async fn setup() -> (BotState, Arc<MemoryBackend>) {
+    setup_with_config(test_config()).await
+}
+
+pub fn member(guild_id: u64, user_id: u64, roles: &[u64]) -> Member {
+    let mut member = Member::default();
+    member.guild_id = GuildId::new(guild_id);
+    member.user.id = UserId::new(user_id);
+    member.user.name = format!("user{user_id}");
+    member.roles = roles.iter().map(|id| RoleId::new(*id)).collect();
+    member
+}
+
+pub fn strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|x| (*x).to_owned()).collect()
+}
@@ -0,0 +1,193 @@
+use std::{collections::HashMap, time::Duration};
+
+use auto_role_bot::config::{BotConfig, ConfigError, RetryConfig};
+
+const CONFIG: &str = r#"
+token = "file-token"
+
+[[guilds]]
+id = 1000
+log_channel = 1001
+
+[[guilds]]
+id = 2000
+
+[server]
+base_url = "https://globed.example/"
+password = "file-password"
+"#;
+
+fn load(contents: &str, env: &[(&str, &str)]) -> Result<BotConfig, ConfigError> {
+    let env: HashMap<String, String> = env
+        .iter()
+        .map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+        .collect();
+
+    BotConfig::from_toml(contents, |name| env.get(name).cloned())
+}
+
+#[test]
+fn config_from_file() {
+    let config = load(CONFIG, &[]).unwrap();
+
+    assert_eq!(config.token, "file-token");
+    assert_eq!(config.server.base_url, "https://globed.example");
+    assert_eq!(config.guilds.len(), 2);
+    assert_eq!(config.guilds[0].log_channel, Some(1001));
+    assert_eq!(
+        config.sync.reconcile_interval,
+        Duration::from_secs(360 * 60)
+    );
+    assert_eq!(
+        config.sync.unlink_grace_period,
+        Duration::from_secs(1440 * 60)
+    );
+    assert!(!config.skip_sync_all);
+}
+
+#[test]
+fn env_overrides_file() {
+    let config = load(
+        CONFIG,
+        &[
+            ("BOT_TOKEN", "env-token"),
+            ("BOT_SERVER_PASSWORD", "env-password"),
+            ("BOT_SERVER_ID", "2000, 1000,3000"),
+            ("BOT_NO_FILE_LOG", "1"),
+        ],
+    )
+    .unwrap();
+
+    assert_eq!(config.token, "env-token");
+    assert_eq!(config.server.password, "env-password");
+    assert!(!config.log.file);
+
+    // guilds from the file keep their log channel
+    let guilds: Vec<_> = config
+        .guilds
+        .iter()
+        .map(|g| (g.id, g.log_channel))
+        .collect();
+    assert_eq!(guilds, [(2000, None), (1000, Some(1001)), (3000, None)]);
+}
+
+#[test]
+fn env_only_config() {
+    let config = load(
+        "",
+        &[
+            ("BOT_TOKEN", "token"),
+            ("BOT_SERVER_ID", "1000"),
+            ("BOT_BASE_URL", "http://localhost:4201"),
+            ("BOT_SERVER_PASSWORD", "password"),
+        ],
+    )
+    .unwrap();
+
+    assert_eq!(config.guilds.len(), 1);
+    assert_eq!(config.server.base_url, "http://localhost:4201");
+}
+
+#[test]
+fn skip_sync_all_is_set_by_anything_but_zero() {
+    for (value, skip) in [
+        ("0", false),
+        ("1", true),
+        ("true", true),
+        ("false", true),
+        ("", true),
+    ] {
+        let config = load(CONFIG, &[("BOT_SKIP_SYNC_ALL", value)]).unwrap();
+        assert_eq!(config.skip_sync_all, skip, "BOT_SKIP_SYNC_ALL={value:?}");
+    }
+}
+
+#[test]
+fn validation_errors_are_collected() {
+    let contents = r#"
+[[guilds]]
+id = 0
+
+[[guilds]]
+id = 5
+log_channel = 0
+
+[server]
+base_url = "globed.example"
+
+[sync]
+chunk_size = 0
+reconcile_interval_mins = 525601
+unlink_grace_period_mins = 9223372036854775807
+
+[sync.retry]
+max_attempts = 0
+"#;
+
+    let errors = load(
+        contents,
+        &[("BOT_SERVER_ID", "5,abc"), ("BOT_NO_FILE_LOG", "maybe")],
+    )
+    .unwrap_err()
+    .0;
+
+    let expected = [
+        "BOT_SERVER_ID: invalid guild id 'abc'",
+        "BOT_NO_FILE_LOG: expected a boolean",
+        "token: not set",
+        "guilds: log channel of guild 5 must not be 0",
+        "server.base_url: expected an http:// or https:// URL",
+        "server.password: not set",
+        "sync.retry.max_attempts: must be at least 1",
+        "sync.chunk_size: must be greater than 0",
+        "sync.reconcile_interval_mins: 525601 is too large, at most 525600 (a year)",
+        "sync.unlink_grace_period_mins: 9223372036854775807 is too large, at most 525600 (a year)",
+    ];
+
+    assert_eq!(errors.len(), expected.len(), "{errors:#?}");
+    for (error, expected) in errors.iter().zip(expected) {
+        assert!(
+            error.starts_with(expected),
+            "{error:?} should start with {expected:?}"
+        );
+    }
+}
+
+#[test]
+fn unknown_keys_are_rejected() {
+    assert!(load("tokn = \"typo\"", &[]).is_err());
+}
+
+#[test]
+fn backoff_delay_doubles_with_jitter() {
+    let policy = RetryConfig {
+        max_attempts: 10,
+        initial_delay: Duration::from_millis(100),
+        max_delay: Duration::from_millis(1000),
+    };
+
+    for _ in 0..100 {
+        let expected = [100, 200, 400, 800, 1000, 1000];
+
+        for (retry, full) in expected.into_iter().enumerate() {
+            let delay = policy.backoff_delay(retry as u32 + 1).as_millis() as u64;
+            assert!(
+                (full / 2..=full).contains(&delay),
+                "retry {}: {delay}ms not in {}..={full}ms",
+                retry + 1,
+                full / 2
+            );
+        }
+    }
+}
+
+#[test]
+fn backoff_delay_does_not_overflow() {
+    let policy = RetryConfig {
+        max_attempts: u32::MAX,
+        initial_delay: Duration::from_secs(1),
+        max_delay: Duration::from_secs(60),
+    };
+
+    assert!(policy.backoff_delay(u32::MAX) <= Duration::from_secs(60));
+}
@@ -0,0 +1,169 @@
+mod common;
+
+use std::time::Duration;
+
+use auto_role_bot::{
+    audit::{AuditAction, AuditFilter},
+    link_attempts::{link_attempt_cooldown, FREE_ATTEMPTS, LOCKOUT, LOCKOUT_ATTEMPTS},
+    serenity::UserId,
+    state::{BotState, LinkError},
+};
+use common::*;
+use poise::futures_util::future::join_all;
+use reqwest::StatusCode;
+
+#[test]
+fn cooldown_escalates_to_lockout() {
+    assert_eq!(link_attempt_cooldown(0), None);
+    assert_eq!(link_attempt_cooldown(2), None);
+    assert_eq!(link_attempt_cooldown(3), Some(Duration::from_secs(30)));
+    assert_eq!(link_attempt_cooldown(4), Some(Duration::from_secs(60)));
+    assert_eq!(link_attempt_cooldown(5), Some(Duration::from_secs(120)));
+    assert_eq!(link_attempt_cooldown(LOCKOUT_ATTEMPTS), Some(LOCKOUT));
+    assert_eq!(link_attempt_cooldown(1000), Some(LOCKOUT));
+
+    for failures in 3..LOCKOUT_ATTEMPTS {
+        assert!(link_attempt_cooldown(failures) < link_attempt_cooldown(failures + 1));
+    }
+}
+
+#[tokio::test]
+async fn wrong_codes_are_throttled() {
+    let (state, backend) = setup().await;
+    backend.add_user(500, "Player", 1234);
+
+    let attacker = member(GUILD, 1, &[]);
+
+    for _ in 0..3 {
+        assert!(matches!(
+            state.link_user(&attacker, "Player", Some(1)).await,
+            Err(LinkError::UserNotFound)
+        ));
+    }
+
+    // even the right code is refused during the cooldown, without asking the server
+    assert!(matches!(
+        state.link_user(&attacker, "Player", Some(1234)).await,
+        Err(LinkError::TooManyAttempts(wait)) if wait <= Duration::from_secs(30)
+    ));
+
+    // the owner of the username can still link it
+    assert!(state
+        .link_user(&member(GUILD, 2, &[]), "player", Some(1234))
+        .await
+        .is_ok());
+}
+
+#[tokio::test]
+async fn admin_links_are_not_throttled() {
+    let (state, backend) = setup().await;
+    backend.add_user(500, "Player", 1234);
+
+    let user = member(GUILD, 1, &[]);
+
+    for _ in 0..3 {
+        let _ = state.link_user(&user, "Player", Some(1)).await;
+    }
+
+    // admin links bypass verification and aren't throttled
+    assert!(state.link_user(&user, "Player", None).await.is_ok());
+}
+
+#[tokio::test]
+async fn parallel_attempts_are_all_counted() {
+    let (state, backend) = setup().await;
+    backend.add_user(500, "Player", 1234);
+
+    let user = member(GUILD, 1, &[]);
+
+    let attempts: Vec<_> = (0..6)
+        .map(|_| state.link_user(&user, "Player", Some(1)))
+        .collect();
+
+    let results = join_all(attempts).await;
+    let throttled = results
+        .iter()
+        .filter(|r| matches!(r, Err(LinkError::TooManyAttempts(_))))
+        .count();
+
+    // only the free attempts get through, no matter how many run at once
+    assert_eq!(throttled, 6 - FREE_ATTEMPTS as usize);
+}
+
+#[tokio::test]
+async fn lockout_is_alerted_once() {
+    let (state, backend) = setup().await;
+    backend.add_user(500, "Player", 1234);
+
+    // several users guessing the same username
+    for user_id in 0..(LOCKOUT_ATTEMPTS + 5) as u64 {
+        let _ = state
+            .link_user(&member(GUILD, 100 + user_id, &[]), "Player", Some(1))
+            .await;
+    }
+
+    let lockouts = lockout_alerts(&state).await;
+    assert_eq!(lockouts.len(), 1);
+    assert!(lockouts[0].contains("the username is not locked"));
+}
+
+async fn lockout_alerts(state: &BotState) -> Vec<String> {
+    let filter = AuditFilter {
+        action: Some(AuditAction::LinkLockout),
+        ..Default::default()
+    };
+
+    let (entries, _) = state.query_audit_log(&filter, 0, 100).await.unwrap();
+    entries.into_iter().filter_map(|x| x.details).collect()
+}
+
+#[tokio::test]
+async fn failed_lookups_are_not_counted() {
+    let (state, backend) = setup().await;
+    backend.add_user(500, "Player", 1234);
+
+    let user = member(GUILD, 1, &[]);
+
+    // the server being down says nothing about the code
+    for _ in 0..5 {
+        backend.fail_next_lookup(LinkError::ServerInternalError(
+            StatusCode::BAD_GATEWAY,
+            String::new(),
+        ));
+
+        assert!(matches!(
+            state.link_user(&user, "Player", Some(1)).await,
+            Err(LinkError::ServerInternalError(..))
+        ));
+    }
+
+    // taken back attempts leave nothing behind
+    assert!(!state
+        .clear_user_link_attempts(UserId::new(1))
+        .await
+        .unwrap());
+    assert!(!state.clear_username_link_attempts("Player").await.unwrap());
+
+    assert!(state.link_user(&user, "Player", Some(1234)).await.is_ok());
+}
+
+#[tokio::test]
+async fn clearing_attempts_lifts_the_cooldown() {
+    let (state, backend) = setup().await;
+    backend.add_user(500, "Player", 1234);
+
+    let user = member(GUILD, 1, &[]);
+
+    for _ in 0..3 {
+        let _ = state.link_user(&user, "Player", Some(1)).await;
+    }
+
+    assert!(state
+        .clear_user_link_attempts(UserId::new(1))
+        .await
+        .unwrap());
+    assert!(state.clear_username_link_attempts("PLAYER").await.unwrap());
+    assert!(!state.clear_username_link_attempts("player").await.unwrap());
+
+    assert!(state.link_user(&user, "Player", Some(1234)).await.is_ok());
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use auto_role_bot::{
+    audit::{AuditAction, AuditFilter},
+    link_conflicts::CONTEST_ALERT_THRESHOLD,
+    serenity::UserId,
+    state::LinkError,
+};
+use common::*;
+use poise::futures_util::future::join_all;
+
+#[tokio::test]
+async fn conflicting_links_are_recorded() {
+    let (state, backend) = setup().await;
+    backend.add_user(500, "Player", 1234);
+    let owner = UserId::new(1);
+
+    state.add_linked_user(owner, 500).await.unwrap();
+
+    for claimant in [2, 3, 2] {
+        assert!(matches!(
+            state
+                .link_user(&member(GUILD, claimant, &[]), "Player", Some(1234))
+                .await,
+            Err(LinkError::LinkedToOther(id)) if id == owner
+        ));
+    }
+
+    // relinking your own account is not a conflict
+    assert!(matches!(
+        state.add_linked_user(owner, 500).await,
+        Err(LinkError::AlreadyLinked)
+    ));
+
+    let conflicts = state.get_link_conflicts(500, 10).await.unwrap();
+    let claimants: Vec<i64> = conflicts.iter().map(|c| c.claimant_id).collect();
+    assert_eq!(claimants, vec![2, 3, 2]);
+    assert!(conflicts.iter().all(|c| c.owner_id == 1));
+
+    let contested = state.get_contested_accounts(10).await.unwrap();
+    assert_eq!(contested.len(), 1);
+    assert_eq!((contested[0].attempts, contested[0].claimants), (3, 2));
+}
+
+#[tokio::test]
+async fn admin_links_are_not_conflicts() {
+    let (state, _backend) = setup().await;
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+
+    assert!(matches!(
+        state.add_linked_user(UserId::new(2), 500).await,
+        Err(LinkError::LinkedToOther(_))
+    ));
+
+    assert!(state.get_link_conflicts(500, 10).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn conflicts_raise_one_alert_in_their_guild() {
+    let (state, backend) = setup().await;
+    backend.add_user(500, "Player", 1234);
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+
+    let filter = AuditFilter {
+        action: Some(AuditAction::LinkContested),
+        ..Default::default()
+    };
+
+    for attempt in 1..=CONTEST_ALERT_THRESHOLD * 3 {
+        let _ = state
+            .link_user(&member(OTHER_GUILD, 2, &[]), "Player", Some(1234))
+            .await;
+
+        let (_, alerts) = state.query_audit_log(&filter, 0, 10).await.unwrap();
+        let expected = if attempt >= CONTEST_ALERT_THRESHOLD {
+            1
+        } else {
+            0
+        };
+        assert_eq!(alerts, expected, "after {attempt} conflicts");
+    }
+
+    let (entries, _) = state.query_audit_log(&filter, 0, 10).await.unwrap();
+    assert_eq!(entries[0].guild_id, Some(OTHER_GUILD as i64));
+}
+
+#[tokio::test]
+async fn parallel_conflicts_raise_one_alert() {
+    let (state, _backend) = setup().await;
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+
+    let claimants: Vec<_> = (0..CONTEST_ALERT_THRESHOLD as u64 * 2)
+        .map(|x| member(GUILD, 10 + x, &[]))
+        .collect();
+
+    let results = join_all(
+        claimants
+            .iter()
+            .map(|claimant| state.record_link_conflict(500, claimant, UserId::new(1))),
+    )
+    .await;
+    assert!(results.iter().all(|r| r.is_ok()));
+
+    let filter = AuditFilter {
+        action: Some(AuditAction::LinkContested),
+        ..Default::default()
+    };
+
+    let (_, alerts) = state.query_audit_log(&filter, 0, 10).await.unwrap();
+    assert_eq!(alerts, 1);
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use auto_role_bot::{
+    blocklist::BlockSource,
+    commands::{submit_link_form, LinkModal},
+    serenity::{Http, UserId},
+};
+use common::*;
+
+fn form(username: &str, link_code: &str) -> LinkModal {
+    LinkModal {
+        username: username.to_owned(),
+        link_code: link_code.to_owned(),
+    }
+}
+
+#[test]
+fn link_form_validation() {
+    assert_eq!(form("Player", "1234").validate(), Ok(1234));
+    assert_eq!(form("  Player ", " 1234 ").validate(), Ok(1234));
+
+    assert!(form("", "1234").validate().is_err());
+    assert!(form("   ", "1234").validate().is_err());
+    assert!(form("Plåyer", "1234").validate().is_err());
+    assert!(form("AVeryLongUsername1", "1234").validate().is_err());
+
+    assert!(form("Player", "").validate().is_err());
+    assert!(form("Player", "12a4").validate().is_err());
+    assert!(form("Player", "-1").validate().is_err());
+    assert!(form("Player", "99999999999").validate().is_err());
+}
+
+#[tokio::test]
+async fn link_form_links_through_the_server() {
+    let (state, backend) = setup().await;
+    backend.add_user(500, "Player", 1234);
+
+    let http = Http::new("");
+    let user = member(GUILD, 1, &[]);
+
+    // a bad form never reaches the server
+    assert!(
+        submit_link_form(&state, &http, &user, &form("Player", "abc"))
+            .await
+            .is_err()
+    );
+
+    let reply = submit_link_form(&state, &http, &user, &form("Player", "1"))
+        .await
+        .unwrap();
+    assert!(reply.retry);
+    assert!(reply.error.is_none());
+
+    let reply = submit_link_form(&state, &http, &user, &form(" player ", "1234"))
+        .await
+        .unwrap();
+    assert!(!reply.retry);
+    assert!(reply
+        .message
+        .contains("Linked <@1> to GD account Player (500)"));
+    assert_eq!(
+        state
+            .get_linked_gd_account(UserId::new(1))
+            .await
+            .unwrap()
+            .map(|x| x.get()),
+        Some(500)
+    );
+}
+
+#[tokio::test]
+async fn link_form_reports_throttled_and_blocked_links() {
+    let (state, backend) = setup().await;
+    backend.add_user(500, "Player", 1234);
+    backend.add_user(600, "Blocked", 4321);
+
+    let http = Http::new("");
+    let user = member(GUILD, 1, &[]);
+
+    state
+        .block_account(600, &BlockSource::default())
+        .await
+        .unwrap();
+
+    let reply = submit_link_form(&state, &http, &user, &form("Blocked", "4321"))
+        .await
+        .unwrap();
+    assert!(!reply.retry);
+    assert!(reply.message.contains("blocked from being linked"));
+
+    for _ in 0..3 {
+        let _ = submit_link_form(&state, &http, &user, &form("Player", "1")).await;
+    }
+
+    // even the right code has to wait, and trying again right away won't help
+    let reply = submit_link_form(&state, &http, &user, &form("Player", "1234"))
+        .await
+        .unwrap();
+    assert!(!reply.retry);
+    assert!(reply.message.contains("Too many failed attempts"));
+    assert!(!state.is_linked(UserId::new(1)).await.unwrap());
+}
@@ -0,0 +1,115 @@
+mod common;
+
+use auto_role_bot::{
+    audit::{AuditAction, AuditEvent},
+    serenity::{ChannelId, GuildId, UserId},
+};
+use common::*;
+
+const LOG_CHANNEL: u64 = 3000;
+
+#[tokio::test]
+async fn audit_events_are_queued_for_log_channel() {
+    let mut config = test_config();
+    config.guilds[0].log_channel = Some(LOG_CHANNEL);
+    let (state, _backend) = setup_with_config(config).await;
+
+    let mut events = state.take_log_channel_events().unwrap();
+    assert!(state.take_log_channel_events().is_none());
+
+    state
+        .audit(
+            AuditEvent::new(AuditAction::Link)
+                .actor(UserId::new(1))
+                .account(500)
+                .guild(GuildId::new(GUILD)),
+        )
+        .await;
+
+    // no log channel in the other guild
+    state
+        .audit(AuditEvent::new(AuditAction::RoleAdd).guild(GuildId::new(OTHER_GUILD)))
+        .await;
+
+    // events without a guild go to every log channel
+    state
+        .audit(AuditEvent::new(AuditAction::SyncFailed).details("server down"))
+        .await;
+
+    let event = events.try_recv().unwrap();
+    assert_eq!(event.action, AuditAction::Link);
+    assert_eq!(
+        state.log_channels_for(&event),
+        vec![ChannelId::new(LOG_CHANNEL)]
+    );
+
+    let event = events.try_recv().unwrap();
+    assert_eq!(event.action, AuditAction::SyncFailed);
+    assert_eq!(
+        state.log_channels_for(&event),
+        vec![ChannelId::new(LOG_CHANNEL)]
+    );
+
+    assert!(events.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn full_log_channel_queue_never_blocks() {
+    let mut config = test_config();
+    config.guilds[0].log_channel = Some(LOG_CHANNEL);
+    let (state, _backend) = setup_with_config(config).await;
+
+    // nobody is posting, so the queue fills up and further events are dropped
+    for _ in 0..500 {
+        state
+            .audit(AuditEvent::new(AuditAction::RoleRule).guild(GuildId::new(GUILD)))
+            .await;
+    }
+
+    let mut events = state.take_log_channel_events().unwrap();
+    let mut queued = 0;
+    while events.try_recv().is_ok() {
+        queued += 1;
+    }
+
+    assert!(queued > 0 && queued < 500);
+}
+
+#[tokio::test]
+async fn nothing_is_queued_without_log_channels() {
+    let (state, _backend) = setup().await;
+
+    state
+        .audit(AuditEvent::new(AuditAction::SyncFailed).details("server down"))
+        .await;
+
+    assert!(state.take_log_channel_events().unwrap().try_recv().is_err());
+}
+
+#[test]
+fn log_messages_stay_under_discord_limits() {
+    use auto_role_bot::log_channel::{audit_embed, embed_length, log_messages};
+
+    let event = |details: &str| AuditEvent::new(AuditAction::Link).details(details);
+
+    // short events are only limited by the embed count
+    let short: Vec<_> = (0..25).map(|_| audit_embed(&event("linked"))).collect();
+    let sizes: Vec<usize> = log_messages(short).iter().map(Vec::len).collect();
+    assert_eq!(sizes, vec![10, 10, 5]);
+
+    // long details are cut to 1000 characters, so only a few fit in one message
+    let long: Vec<_> = (0..10)
+        .map(|_| audit_embed(&event(&"a".repeat(5000))))
+        .collect();
+    assert_eq!(embed_length(&long[0]), "Link".len() + 1000);
+
+    let messages = log_messages(long);
+    assert_eq!(messages.len(), 2);
+
+    for message in &messages {
+        let total: usize = message.iter().map(embed_length).sum();
+        assert!(total <= 6000, "message has {total} characters");
+    }
+
+    assert!(log_messages(Vec::new()).is_empty());
+}
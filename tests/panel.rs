@@ -0,0 +1,158 @@
+mod common;
+
+use auto_role_bot::{
+    commands::{panel_reply, PanelButton, PanelResponse},
+    db::{SyncDirection, UnlinkReason},
+    serenity::{GuildId, Http, Member, UserId},
+    state::BotState,
+};
+use common::*;
+
+#[test]
+fn panel_custom_ids_are_stable() {
+    // panels that were already posted keep these ids, changing them breaks the buttons
+    assert_eq!(PanelButton::Link.custom_id(), "panel:link");
+    assert_eq!(PanelButton::Unlink.custom_id(), "panel:unlink");
+    assert_eq!(
+        PanelButton::ConfirmUnlink.custom_id(),
+        "panel:unlink_confirm"
+    );
+    assert_eq!(PanelButton::Sync.custom_id(), "panel:sync");
+    assert_eq!(PanelButton::Status.custom_id(), "panel:status");
+
+    for button in [
+        PanelButton::Link,
+        PanelButton::Unlink,
+        PanelButton::ConfirmUnlink,
+        PanelButton::Sync,
+        PanelButton::Status,
+    ] {
+        assert_eq!(
+            PanelButton::from_custom_id(button.custom_id()),
+            Some(button)
+        );
+    }
+
+    assert_eq!(PanelButton::from_custom_id("panel:"), None);
+    assert_eq!(PanelButton::from_custom_id("1234link"), None);
+}
+
+async fn press(state: &BotState, button: PanelButton, user: &Member) -> PanelResponse {
+    let reply = panel_reply(state, &Http::new(""), button, user).await;
+    assert!(reply.error.is_none(), "{:?}", reply.error);
+    reply.response
+}
+
+fn message(response: PanelResponse) -> String {
+    match response {
+        PanelResponse::Message(content, _) | PanelResponse::Update(content) => content,
+        PanelResponse::LinkForm => panic!("expected a message, got the link form"),
+    }
+}
+
+#[tokio::test]
+async fn link_button_only_opens_the_form_when_unlinked() {
+    let (state, _backend) = setup().await;
+    let user = member(GUILD, 1, &[]);
+
+    assert_eq!(
+        press(&state, PanelButton::Link, &user).await,
+        PanelResponse::LinkForm
+    );
+
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+
+    let response = message(press(&state, PanelButton::Link, &user).await);
+    assert!(response.contains("Already linked"));
+}
+
+#[tokio::test]
+async fn unlink_button_asks_for_confirmation() {
+    let (state, _backend) = setup().await;
+    let user = member(GUILD, 1, &[]);
+
+    let response = message(press(&state, PanelButton::Unlink, &user).await);
+    assert!(response.contains("Not currently linked"));
+
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+
+    match press(&state, PanelButton::Unlink, &user).await {
+        PanelResponse::Message(content, next) => {
+            assert!(content.contains("GD account 500"));
+            assert_eq!(next, Some(PanelButton::ConfirmUnlink));
+        }
+        other => panic!("unexpected response {other:?}"),
+    }
+    assert!(state.is_linked(UserId::new(1)).await.unwrap());
+
+    // the confirmation is replaced, so it can't be pressed twice
+    let response = press(&state, PanelButton::ConfirmUnlink, &user).await;
+    assert!(matches!(&response, PanelResponse::Update(x) if x.contains("Successfully unlinked")));
+    assert!(!state.is_linked(UserId::new(1)).await.unwrap());
+
+    let response = press(&state, PanelButton::ConfirmUnlink, &user).await;
+    assert!(matches!(&response, PanelResponse::Update(x) if x.contains("Not currently linked")));
+}
+
+#[tokio::test]
+async fn sync_button_syncs_roles() {
+    let (state, backend) = setup().await;
+    state
+        .add_role(
+            GuildId::new(GUILD),
+            11,
+            "mod",
+            SyncDirection::DiscordToGlobed,
+        )
+        .await
+        .unwrap();
+
+    let user = member(GUILD, 1, &[11]);
+
+    let response = message(press(&state, PanelButton::Sync, &user).await);
+    assert!(response.contains("Not currently linked"));
+
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+
+    let response = message(press(&state, PanelButton::Sync, &user).await);
+    assert!(response.contains("Synced roles: mod"));
+    assert_eq!(backend.account_roles(500), strings(&["mod"]));
+}
+
+#[tokio::test]
+async fn status_button_shows_roles_on_the_server() {
+    let (state, backend) = setup().await;
+    state
+        .add_role(
+            GuildId::new(GUILD),
+            11,
+            "mod",
+            SyncDirection::DiscordToGlobed,
+        )
+        .await
+        .unwrap();
+
+    let user = member(GUILD, 1, &[11]);
+
+    let response = message(press(&state, PanelButton::Status, &user).await);
+    assert!(response.contains("Not linked to any account yet"));
+
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+
+    // not synced yet, the server doesn't have the discord role
+    backend.set_account_roles(500, &["vip"]);
+    let response = message(press(&state, PanelButton::Status, &user).await);
+    assert!(response.contains("Linked to GD account 500"));
+    assert!(response.contains("Roles on Globed: vip"));
+
+    backend.set_account_roles(500, &[]);
+    let response = message(press(&state, PanelButton::Status, &user).await);
+    assert!(response.contains("No roles on Globed"));
+
+    state
+        .unlink_user(UserId::new(1), UnlinkReason::User)
+        .await
+        .unwrap();
+    let response = message(press(&state, PanelButton::Status, &user).await);
+    assert!(response.contains("Not currently linked, last linked to GD account 500"));
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use std::time::Duration;
+
+use auto_role_bot::{
+    backend::{RoleSyncRequest, RoleSyncRequestData},
+    db::{SyncDirection, UnlinkReason},
+    serenity::{GuildId, UserId},
+    state::BotState,
+};
+use common::*;
+
+async fn add_mod_role(state: &BotState) {
+    state
+        .add_role(
+            GuildId::new(GUILD),
+            11,
+            "mod",
+            SyncDirection::DiscordToGlobed,
+        )
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn leaving_strips_roles_but_keeps_link() {
+    let mut config = test_config();
+    config.sync.unlink_grace_period = Duration::from_secs(3600);
+    let (state, backend) = setup_with_config(config).await;
+    add_mod_role(&state).await;
+
+    let user = UserId::new(1);
+    state.add_linked_user(user, 500).await.unwrap();
+    state
+        .start_unlink_grace(user, GuildId::new(GUILD))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        backend.sync_requests(),
+        vec![RoleSyncRequestData {
+            users: vec![RoleSyncRequest {
+                account_id: 500,
+                keep: vec![],
+                remove: strings(&["mod"]),
+            }],
+        }]
+    );
+
+    // still inside the grace period
+    assert!(state.finalise_expired_unlinks().await.unwrap().is_empty());
+    assert!(state.is_linked(user).await.unwrap());
+
+    // rejoining keeps the link
+    assert!(state.cancel_pending_unlink(user).await.unwrap());
+    assert!(!state.cancel_pending_unlink(user).await.unwrap());
+    assert!(state.is_linked(user).await.unwrap());
+}
+
+#[tokio::test]
+async fn expired_grace_period_unlinks() {
+    let (state, _backend) = setup().await;
+    add_mod_role(&state).await;
+
+    let user = UserId::new(1);
+    state.add_linked_user(user, 500).await.unwrap();
+    state
+        .start_unlink_grace(user, GuildId::new(GUILD))
+        .await
+        .unwrap();
+
+    // the test config has no grace period, so it is over right away
+    assert_eq!(state.finalise_expired_unlinks().await.unwrap(), vec![user]);
+    assert!(!state.is_linked(user).await.unwrap());
+
+    let history = state.get_user_link_history(user).await.unwrap();
+    assert_eq!(history[0].reason(), Some(UnlinkReason::LeftGuild));
+
+    assert!(state.finalise_expired_unlinks().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn unlinking_cancels_grace_period() {
+    let (state, _backend) = setup().await;
+    add_mod_role(&state).await;
+
+    let user = UserId::new(1);
+    state.add_linked_user(user, 500).await.unwrap();
+    state
+        .start_unlink_grace(user, GuildId::new(GUILD))
+        .await
+        .unwrap();
+    state.unlink_user(user, UnlinkReason::Admin).await.unwrap();
+
+    // a new link must survive the old grace period
+    state.add_linked_user(user, 600).await.unwrap();
+    assert!(state.finalise_expired_unlinks().await.unwrap().is_empty());
+    assert!(state.is_linked(user).await.unwrap());
+}
@@ -0,0 +1,189 @@
+mod common;
+
+use auto_role_bot::{
+    db::{Role, SyncDirection},
+    pull_roles::{discord_role_changes, DiscordRoleChanges},
+    serenity::{GuildId, RoleId, UserId},
+};
+use common::*;
+
+fn mapping(id: &str, discord_id: i64, direction: SyncDirection) -> Role {
+    Role {
+        guild_id: GUILD as i64,
+        id: id.to_owned(),
+        discord_id,
+        direction: direction.as_db().to_owned(),
+        rule: None,
+    }
+}
+
+fn roles(ids: &[u64]) -> Vec<RoleId> {
+    ids.iter().map(|x| RoleId::new(*x)).collect()
+}
+
+#[test]
+fn globed_to_discord_follows_the_server() {
+    let mappings = [
+        mapping("mod", 11, SyncDirection::GlobedToDiscord),
+        mapping("vip", 12, SyncDirection::GlobedToDiscord),
+        mapping("helper", 13, SyncDirection::DiscordToGlobed),
+    ];
+
+    let changes = discord_role_changes(
+        &roles(&[12, 13]),
+        &strings(&["mod", "helper"]),
+        None,
+        &mappings,
+    );
+
+    assert_eq!(
+        changes,
+        DiscordRoleChanges {
+            added: roles(&[11]),
+            removed: roles(&[12]),
+        }
+    );
+}
+
+#[test]
+fn both_ways_only_pulls_changes_made_on_globed() {
+    let mappings = [mapping("mod", 11, SyncDirection::Both)];
+    let synced = strings(&["mod"]);
+
+    // removed on globed since the last sync
+    let changes = discord_role_changes(&roles(&[11]), &[], Some(&synced), &mappings);
+    assert_eq!(changes.removed, roles(&[11]));
+
+    // removed on discord, globed still has the old state, discord wins
+    let changes = discord_role_changes(&[], &synced, Some(&synced), &mappings);
+    assert!(changes.is_empty());
+
+    // never synced, only adds
+    let changes = discord_role_changes(&roles(&[11]), &[], None, &mappings);
+    assert!(changes.is_empty());
+    let changes = discord_role_changes(&[], &synced, None, &mappings);
+    assert_eq!(changes.added, roles(&[11]));
+}
+
+#[test]
+fn unknown_direction_is_rejected() {
+    for direction in [
+        SyncDirection::DiscordToGlobed,
+        SyncDirection::GlobedToDiscord,
+        SyncDirection::Both,
+    ] {
+        assert_eq!(SyncDirection::from_db(direction.as_db()), Some(direction));
+    }
+
+    assert_eq!(SyncDirection::from_db("globed-to-discord"), None);
+
+    let mut role = mapping("mod", 11, SyncDirection::Both);
+    role.direction = "sideways".to_owned();
+    assert_eq!(role.direction(), SyncDirection::DiscordToGlobed);
+}
+
+#[tokio::test]
+async fn globed_to_discord_roles_are_not_pushed() {
+    let (state, backend) = setup().await;
+    let guild = GuildId::new(GUILD);
+
+    state
+        .add_role(guild, 11, "mod", SyncDirection::DiscordToGlobed)
+        .await
+        .unwrap();
+    state
+        .add_role(guild, 12, "vip", SyncDirection::GlobedToDiscord)
+        .await
+        .unwrap();
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+
+    assert_eq!(state.watched_roles.read()[&guild], roles(&[11]));
+
+    state
+        .sync_roles(&member(GUILD, 1, &[11, 12]))
+        .await
+        .unwrap();
+
+    let requests = backend.sync_requests();
+    assert_eq!(requests[0].users[0].keep, strings(&["mod"]));
+    assert!(requests[0].users[0].remove.is_empty());
+
+    // removing the last pushed mapping of a role stops watching it
+    state.remove_role(guild, 11).await.unwrap();
+    assert!(state.watched_roles.read()[&guild].is_empty());
+}
+
+#[tokio::test]
+async fn unlinked_member_pull_is_rejected() {
+    let (state, _backend) = setup().await;
+    let http = auto_role_bot::serenity::Http::new("");
+
+    let mut member = member(GUILD, 1, &[]);
+    assert!(matches!(
+        state.pull_globed_roles(&http, &mut member).await,
+        Err(auto_role_bot::state::RoleSyncError::NotLinked)
+    ));
+}
+
+// an http client whose requests to discord always fail
+fn failing_http() -> auto_role_bot::serenity::Http {
+    auto_role_bot::serenity::HttpBuilder::new("token")
+        .proxy("http://127.0.0.1:1")
+        .ratelimiter_disabled(true)
+        .build()
+}
+
+#[tokio::test]
+async fn failed_discord_edit_is_not_pushed_back() {
+    let (state, backend) = setup().await;
+    state
+        .add_role(GuildId::new(GUILD), 11, "mod", SyncDirection::Both)
+        .await
+        .unwrap();
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+    backend.set_account_roles(500, &["mod"]);
+
+    let http = failing_http();
+    let mut member = member(GUILD, 1, &[]);
+
+    assert!(matches!(
+        state.pull_and_sync_roles(&http, &mut member).await,
+        Err(auto_role_bot::state::RoleSyncError::DiscordUpdate(_))
+    ));
+
+    // the role stays on the server, and the next pull tries adding it again
+    assert!(member.roles.is_empty());
+    assert!(backend.sync_requests().is_empty());
+    assert_eq!(backend.account_roles(500), strings(&["mod"]));
+    assert_eq!(state.get_synced_roles(500).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn own_role_edit_needs_a_matching_change() {
+    let (state, backend) = setup().await;
+    state
+        .add_role(
+            GuildId::new(GUILD),
+            11,
+            "mod",
+            SyncDirection::GlobedToDiscord,
+        )
+        .await
+        .unwrap();
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+    backend.set_account_roles(500, &["mod"]);
+
+    // the edit fails, but it is still remembered as ours
+    let mut old = member(GUILD, 1, &[]);
+    let _ = state.pull_globed_roles(&failing_http(), &mut old).await;
+
+    let guild = GuildId::new(GUILD);
+    let added = member(GUILD, 1, &[11]);
+    let other = member(GUILD, 1, &[12]);
+
+    assert!(state.is_own_role_edit(guild, Some(&old), &added));
+    assert!(!state.is_own_role_edit(guild, Some(&old), &other));
+    // nothing changed, or the old member is unknown
+    assert!(!state.is_own_role_edit(guild, Some(&old), &old));
+    assert!(!state.is_own_role_edit(guild, None, &added));
+}
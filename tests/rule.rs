@@ -0,0 +1,195 @@
+mod common;
+
+use auto_role_bot::{
+    db::SyncDirection,
+    rule::{Rule, RuleParseError},
+    serenity::{GuildId, RoleId, UserId},
+};
+use common::*;
+
+#[test]
+fn parse_and_display() {
+    let rule = Rule::parse("(<@&11> or 12) AND not 13").unwrap();
+
+    assert_eq!(
+        rule,
+        Rule::And(vec![
+            Rule::Or(vec![
+                Rule::Role(RoleId::new(11)),
+                Rule::Role(RoleId::new(12))
+            ]),
+            Rule::Not(Box::new(Rule::Role(RoleId::new(13)))),
+        ])
+    );
+
+    assert_eq!(rule.to_string(), "(11 OR 12) AND NOT 13");
+    assert_eq!(rule.to_mentions(), "(<@&11> OR <@&12>) AND NOT <@&13>");
+    assert_eq!(Rule::parse(&rule.to_string()).unwrap(), rule);
+    assert_eq!(
+        rule.roles(),
+        vec![RoleId::new(11), RoleId::new(12), RoleId::new(13)]
+    );
+
+    // AND binds stronger than OR
+    assert_eq!(
+        Rule::parse("11 OR 12 AND 13").unwrap().to_string(),
+        "11 OR 12 AND 13"
+    );
+}
+
+#[test]
+fn parse_errors() {
+    assert_eq!(Rule::parse("  "), Err(RuleParseError::Empty));
+    assert_eq!(Rule::parse("11 AND"), Err(RuleParseError::UnexpectedEnd));
+    assert_eq!(Rule::parse("(11 OR 12"), Err(RuleParseError::UnexpectedEnd));
+    assert_eq!(
+        Rule::parse("11 12"),
+        Err(RuleParseError::UnexpectedToken("12".to_owned()))
+    );
+    assert_eq!(
+        Rule::parse("11 AND muted"),
+        Err(RuleParseError::InvalidRole("muted".to_owned()))
+    );
+}
+
+#[test]
+fn deeply_nested_rules_are_rejected() {
+    let nested = |depth: usize| format!("{}11{}", "(".repeat(depth), ")".repeat(depth));
+
+    assert!(Rule::parse(&nested(32)).is_ok());
+    assert_eq!(Rule::parse(&nested(33)), Err(RuleParseError::TooDeep));
+    assert_eq!(
+        Rule::parse(&format!("{}11", "NOT ".repeat(100_000))),
+        Err(RuleParseError::TooDeep)
+    );
+    assert_eq!(
+        Rule::parse(&"(".repeat(100_000)),
+        Err(RuleParseError::TooDeep)
+    );
+}
+
+#[test]
+fn unknown_roles() {
+    let rule = Rule::parse("(11 OR 12) AND NOT 13 OR booster").unwrap();
+
+    assert_eq!(
+        rule.unknown_roles(&[RoleId::new(11), RoleId::new(13)]),
+        vec![RoleId::new(12)]
+    );
+    assert!(rule
+        .unknown_roles(&[RoleId::new(11), RoleId::new(12), RoleId::new(13)])
+        .is_empty());
+}
+
+#[test]
+fn matches_member() {
+    let rule = Rule::parse("(11 OR 12) AND NOT 13").unwrap();
+
+    assert!(rule.matches(&member(GUILD, 1, &[11])));
+    assert!(rule.matches(&member(GUILD, 1, &[12, 14])));
+    assert!(!rule.matches(&member(GUILD, 1, &[11, 13])));
+    assert!(!rule.matches(&member(GUILD, 1, &[])));
+}
+
+#[tokio::test]
+async fn rules_are_used_when_syncing() {
+    let (state, backend) = setup().await;
+    let guild = GuildId::new(GUILD);
+
+    state
+        .add_role(guild, 11, "supporter", SyncDirection::DiscordToGlobed)
+        .await
+        .unwrap();
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+
+    let rule = Rule::parse("(11 OR 12) AND NOT 13").unwrap();
+    state
+        .set_role_rule(guild, "supporter", Some(&rule))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        state.watched_roles.read()[&guild],
+        vec![RoleId::new(11), RoleId::new(12), RoleId::new(13)]
+    );
+    assert!(state.is_watched_role_changed(guild, &member(GUILD, 1, &[]), &member(GUILD, 1, &[13])));
+
+    assert_eq!(
+        state.sync_roles(&member(GUILD, 1, &[12])).await.unwrap(),
+        strings(&["supporter"])
+    );
+    assert!(state
+        .sync_roles(&member(GUILD, 1, &[12, 13]))
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(backend.account_roles(500).is_empty());
+
+    // without the rule, only the mapped role counts again
+    state.set_role_rule(guild, "supporter", None).await.unwrap();
+    assert_eq!(state.watched_roles.read()[&guild], vec![RoleId::new(11)]);
+    assert!(state
+        .sync_roles(&member(GUILD, 1, &[12]))
+        .await
+        .unwrap()
+        .is_empty());
+
+    assert!(state
+        .set_role_rule(guild, "missing", Some(&rule))
+        .await
+        .is_err());
+}
+
+fn boosting(mut member: auto_role_bot::serenity::Member) -> auto_role_bot::serenity::Member {
+    member.premium_since = Some(auto_role_bot::serenity::Timestamp::now());
+    member
+}
+
+#[test]
+fn booster_rule() {
+    let rule = Rule::parse("BOOSTER and not 13").unwrap();
+    assert_eq!(rule.to_string(), "booster AND NOT 13");
+    assert!(rule.uses_booster());
+    assert_eq!(rule.roles(), vec![RoleId::new(13)]);
+
+    assert!(rule.matches(&boosting(member(GUILD, 1, &[]))));
+    assert!(!rule.matches(&boosting(member(GUILD, 1, &[13]))));
+    assert!(!rule.matches(&member(GUILD, 1, &[])));
+}
+
+#[tokio::test]
+async fn booster_mapping_follows_premium_since() {
+    let (state, backend) = setup().await;
+    let guild = GuildId::new(GUILD);
+
+    state
+        .add_role(
+            guild,
+            auto_role_bot::db::BOOSTER_ROLE_ID,
+            "booster",
+            SyncDirection::DiscordToGlobed,
+        )
+        .await
+        .unwrap();
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+
+    assert!(state.watched_roles.read()[&guild].is_empty());
+
+    // boosting alone, without any role change, counts as a change
+    let old = member(GUILD, 1, &[]);
+    let new = boosting(member(GUILD, 1, &[]));
+    assert!(state.is_watched_role_changed(guild, &old, &new));
+    assert!(!state.is_watched_role_changed(GuildId::new(OTHER_GUILD), &old, &new));
+
+    assert_eq!(state.sync_roles(&new).await.unwrap(), strings(&["booster"]));
+    assert_eq!(backend.account_roles(500), strings(&["booster"]));
+
+    state.sync_roles(&old).await.unwrap();
+    assert!(backend.account_roles(500).is_empty());
+
+    state
+        .remove_role_by_globed_id(guild, "booster")
+        .await
+        .unwrap();
+    assert!(!state.is_watched_role_changed(guild, &old, &new));
+}
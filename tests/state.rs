@@ -0,0 +1,685 @@
+mod common;
+
+use auto_role_bot::{
+    backend::{RoleSyncRequest, RoleSyncRequestData},
+    db::{SyncDirection, UnlinkReason},
+    serenity::{GuildId, RoleId, UserId},
+    state::{LinkError, RoleRemoveError, RoleSyncError, SyncAllProgress, TransferError},
+};
+use common::*;
+use reqwest::StatusCode;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+const MOD_ROLE: u64 = 11;
+const VIP_ROLE: u64 = 12;
+const OTHER_ROLE: u64 = 21;
+
+async fn add_default_roles(state: &auto_role_bot::state::BotState) {
+    state
+        .add_role(
+            GuildId::new(GUILD),
+            MOD_ROLE as i64,
+            "mod",
+            SyncDirection::DiscordToGlobed,
+        )
+        .await
+        .unwrap();
+    state
+        .add_role(
+            GuildId::new(GUILD),
+            VIP_ROLE as i64,
+            "vip",
+            SyncDirection::DiscordToGlobed,
+        )
+        .await
+        .unwrap();
+    state
+        .add_role(
+            GuildId::new(OTHER_GUILD),
+            OTHER_ROLE as i64,
+            "helper",
+            SyncDirection::DiscordToGlobed,
+        )
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn add_linked_user_stores_link() {
+    let (state, _) = setup().await;
+
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+
+    assert_eq!(
+        state
+            .get_linked_gd_account(UserId::new(1))
+            .await
+            .unwrap()
+            .map(|x| x.get()),
+        Some(500)
+    );
+    assert_eq!(
+        state.get_linked_discord_account(500).await.unwrap(),
+        Some(UserId::new(1))
+    );
+    assert!(!state.is_linked(UserId::new(2)).await.unwrap());
+}
+
+#[tokio::test]
+async fn add_linked_user_conflicts() {
+    let (state, _) = setup().await;
+
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+
+    // same account, another discord user
+    match state.add_linked_user(UserId::new(2), 500).await {
+        Err(LinkError::LinkedToOther(id)) => assert_eq!(id, UserId::new(1)),
+        other => panic!("expected LinkedToOther, got {other:?}"),
+    }
+
+    // same discord user, another account
+    assert!(matches!(
+        state.add_linked_user(UserId::new(1), 501).await,
+        Err(LinkError::AlreadyLinked)
+    ));
+
+    // nothing changed in the database
+    let linked = state.get_all_linked_users().await.unwrap();
+    assert_eq!(linked.len(), 1);
+    assert_eq!((linked[0].id, linked[0].gd_account_id), (1, 500));
+}
+
+#[tokio::test]
+async fn unlink_user_removes_all_roles() {
+    let (state, backend) = setup().await;
+    add_default_roles(&state).await;
+
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+    state
+        .unlink_user(UserId::new(1), UnlinkReason::User)
+        .await
+        .unwrap();
+
+    assert!(!state.is_linked(UserId::new(1)).await.unwrap());
+    assert_eq!(
+        backend.sync_requests(),
+        vec![RoleSyncRequestData {
+            users: vec![RoleSyncRequest {
+                account_id: 500,
+                keep: vec![],
+                remove: strings(&["helper", "mod", "vip"]),
+            }],
+        }]
+    );
+
+    assert!(matches!(
+        state.unlink_user(UserId::new(1), UnlinkReason::User).await,
+        Err(RoleSyncError::NotLinked)
+    ));
+    assert_eq!(backend.sync_requests().len(), 1);
+}
+
+#[tokio::test]
+async fn unlinked_links_are_kept_as_history() {
+    let (state, _backend) = setup().await;
+    add_default_roles(&state).await;
+
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+    state
+        .unlink_user(UserId::new(1), UnlinkReason::Admin)
+        .await
+        .unwrap();
+
+    // the account is free again for someone else
+    state.add_linked_user(UserId::new(2), 500).await.unwrap();
+    assert_eq!(
+        state.get_linked_discord_account(500).await.unwrap(),
+        Some(UserId::new(2))
+    );
+    assert!(!state.is_linked(UserId::new(1)).await.unwrap());
+    assert_eq!(state.get_all_linked_users().await.unwrap().len(), 1);
+
+    let history = state.get_user_link_history(UserId::new(1)).await.unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].gd_account_id, 500);
+    assert!(!history[0].is_active());
+    assert_eq!(history[0].reason(), Some(UnlinkReason::Admin));
+
+    // newest first
+    let history = state.get_account_link_history(500).await.unwrap();
+    let users: Vec<i64> = history.iter().map(|x| x.id).collect();
+    assert_eq!(users, vec![2, 1]);
+    assert!(history[0].is_active());
+    assert_eq!(history[0].reason(), None);
+
+    // an active link still blocks linking the account twice
+    assert!(matches!(
+        state.add_linked_user(UserId::new(3), 500).await,
+        Err(LinkError::LinkedToOther(id)) if id == UserId::new(2)
+    ));
+}
+
+#[tokio::test]
+async fn transfer_moves_link_without_stripping_roles() {
+    let (state, backend) = setup().await;
+    add_default_roles(&state).await;
+
+    let (old, new) = (UserId::new(1), UserId::new(2));
+    state.add_linked_user(old, 500).await.unwrap();
+
+    // the old user left and is waiting to be unlinked
+    state
+        .start_unlink_grace(old, GuildId::new(GUILD))
+        .await
+        .unwrap();
+    backend.take_sync_requests();
+
+    assert_eq!(state.transfer_link(old, new).await.unwrap(), 500);
+    assert!(backend.sync_requests().is_empty());
+
+    assert!(!state.is_linked(old).await.unwrap());
+    assert_eq!(
+        state.get_linked_discord_account(500).await.unwrap(),
+        Some(new)
+    );
+    assert_eq!(
+        state.get_user_link_history(old).await.unwrap()[0].reason(),
+        Some(UnlinkReason::Transferred)
+    );
+
+    // the old grace period must not end the new link
+    assert!(!state.cancel_pending_unlink(old).await.unwrap());
+
+    assert!(matches!(
+        state.transfer_link(old, new).await,
+        Err(TransferError::NotLinked)
+    ));
+    assert!(matches!(
+        state.transfer_link(new, new).await,
+        Err(TransferError::SameUser)
+    ));
+
+    state.add_linked_user(UserId::new(3), 600).await.unwrap();
+    assert!(matches!(
+        state.transfer_link(new, UserId::new(3)).await,
+        Err(TransferError::AlreadyLinked)
+    ));
+    assert_eq!(
+        state.get_linked_discord_account(500).await.unwrap(),
+        Some(new)
+    );
+}
+
+#[tokio::test]
+async fn add_and_remove_roles() {
+    let (state, _) = setup().await;
+    add_default_roles(&state).await;
+
+    let guild = GuildId::new(GUILD);
+
+    let roles: Vec<_> = state
+        .get_guild_roles(guild)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| (r.id, r.discord_id))
+        .collect();
+    assert_eq!(
+        roles,
+        vec![
+            ("mod".to_owned(), MOD_ROLE as i64),
+            ("vip".to_owned(), VIP_ROLE as i64)
+        ]
+    );
+    assert_eq!(
+        state.watched_roles.read()[&guild],
+        vec![RoleId::new(MOD_ROLE), RoleId::new(VIP_ROLE)]
+    );
+
+    // duplicate globed role in the same guild
+    assert!(state
+        .add_role(guild, 99, "mod", SyncDirection::DiscordToGlobed)
+        .await
+        .is_err());
+
+    state.remove_role(guild, MOD_ROLE as i64).await.unwrap();
+    assert!(matches!(
+        state.remove_role(guild, MOD_ROLE as i64).await,
+        Err(RoleRemoveError::NotFound)
+    ));
+
+    state.remove_role_by_globed_id(guild, "vip").await.unwrap();
+    assert!(matches!(
+        state.remove_role_by_globed_id(guild, "vip").await,
+        Err(RoleRemoveError::NotFound)
+    ));
+
+    assert!(state.get_guild_roles(guild).await.unwrap().is_empty());
+    assert!(state.watched_roles.read()[&guild].is_empty());
+
+    // the other guild is untouched
+    assert_eq!(state.get_all_roles().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn role_sync_request_uses_member_roles() {
+    let (state, backend) = setup().await;
+    add_default_roles(&state).await;
+
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+
+    let member = member(GUILD, 1, &[VIP_ROLE, 999]);
+    let linked = &state.get_all_linked_users().await.unwrap()[0];
+    let roles = state.get_guild_roles(member.guild_id).await.unwrap();
+
+    let req = state.make_role_sync_request_with(&member, linked, &roles);
+    assert_eq!(
+        req,
+        RoleSyncRequest {
+            account_id: 500,
+            keep: strings(&["vip"]),
+            remove: strings(&["mod"]),
+        }
+    );
+
+    let kept = state.sync_roles(&member).await.unwrap();
+    assert_eq!(kept, strings(&["vip"]));
+    assert_eq!(
+        backend.sync_requests(),
+        vec![RoleSyncRequestData { users: vec![req] }]
+    );
+    assert_eq!(backend.account_roles(500), strings(&["vip"]));
+}
+
+#[tokio::test]
+async fn sync_roles_only_uses_roles_of_member_guild() {
+    let (state, backend) = setup().await;
+    add_default_roles(&state).await;
+
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+    state
+        .sync_roles(&member(OTHER_GUILD, 1, &[OTHER_ROLE, MOD_ROLE]))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        backend.take_sync_requests(),
+        vec![RoleSyncRequestData {
+            users: vec![RoleSyncRequest {
+                account_id: 500,
+                keep: strings(&["helper"]),
+                remove: vec![],
+            }],
+        }]
+    );
+}
+
+#[tokio::test]
+async fn role_mapped_in_two_guilds_is_kept_while_one_grants_it() {
+    let (state, backend) = setup().await;
+    add_default_roles(&state).await;
+    state
+        .add_role(
+            GuildId::new(OTHER_GUILD),
+            OTHER_ROLE as i64,
+            "vip",
+            SyncDirection::DiscordToGlobed,
+        )
+        .await
+        .unwrap();
+
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+
+    // vip in the first guild only
+    state
+        .sync_roles(&member(GUILD, 1, &[VIP_ROLE]))
+        .await
+        .unwrap();
+    backend.take_sync_requests();
+
+    // an update in the other guild must not take it away
+    state
+        .sync_roles(&member(OTHER_GUILD, 1, &[]))
+        .await
+        .unwrap();
+    assert_eq!(
+        backend.take_sync_requests()[0].users[0],
+        RoleSyncRequest {
+            account_id: 500,
+            keep: vec![],
+            remove: strings(&["helper"]),
+        }
+    );
+    assert_eq!(backend.account_roles(500), strings(&["vip"]));
+
+    // leaving the other guild leaves vip alone
+    state
+        .strip_guild_roles(UserId::new(1), GuildId::new(OTHER_GUILD))
+        .await
+        .unwrap();
+    assert_eq!(backend.account_roles(500), strings(&["vip"]));
+
+    // leaving the guild that grants it removes it
+    state
+        .strip_guild_roles(UserId::new(1), GuildId::new(GUILD))
+        .await
+        .unwrap();
+    assert!(backend.account_roles(500).is_empty());
+}
+
+#[tokio::test]
+async fn sync_roles_not_linked() {
+    let (state, backend) = setup().await;
+    add_default_roles(&state).await;
+
+    assert!(matches!(
+        state.sync_roles(&member(GUILD, 1, &[MOD_ROLE])).await,
+        Err(RoleSyncError::NotLinked)
+    ));
+    assert!(backend.sync_requests().is_empty());
+}
+
+#[tokio::test]
+async fn sync_roles_server_error() {
+    let (state, backend) = setup().await;
+    add_default_roles(&state).await;
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+
+    backend.fail_next_sync(StatusCode::BAD_REQUEST, "bad payload");
+
+    match state.sync_roles(&member(GUILD, 1, &[MOD_ROLE])).await {
+        Err(RoleSyncError::ServerUpdate((status, message))) => {
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(message, "bad payload");
+        }
+        other => panic!("expected ServerUpdate, got {other:?}"),
+    }
+    assert!(backend.sync_requests().is_empty());
+}
+
+#[tokio::test]
+async fn sync_roles_retries_transient_errors() {
+    let (state, backend) = setup().await;
+    add_default_roles(&state).await;
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+
+    backend.fail_next_sync(StatusCode::SERVICE_UNAVAILABLE, "restarting");
+    backend.fail_next_sync_with(RoleSyncError::RateLimited(Some(Duration::from_millis(4))));
+
+    let started = Instant::now();
+    state
+        .sync_roles(&member(GUILD, 1, &[MOD_ROLE]))
+        .await
+        .unwrap();
+
+    // the Retry-After delay was respected
+    assert!(started.elapsed() >= Duration::from_millis(4));
+    assert_eq!(backend.sync_requests().len(), 1);
+}
+
+#[tokio::test]
+async fn sync_roles_caps_retry_after() {
+    let (state, backend) = setup().await;
+    add_default_roles(&state).await;
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+
+    // waits for `sync.retry.max_delay` at most, not an hour
+    backend.fail_next_sync_with(RoleSyncError::RateLimited(Some(Duration::from_secs(3600))));
+
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        state.sync_roles(&member(GUILD, 1, &[MOD_ROLE])),
+    )
+    .await
+    .expect("Retry-After was not capped")
+    .unwrap();
+
+    assert_eq!(backend.sync_requests().len(), 1);
+}
+
+#[tokio::test]
+async fn sync_roles_gives_up_after_max_attempts() {
+    let (state, backend) = setup().await;
+    add_default_roles(&state).await;
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+
+    for _ in 0..3 {
+        backend.fail_next_sync(StatusCode::BAD_GATEWAY, "down");
+    }
+
+    match state.sync_roles(&member(GUILD, 1, &[MOD_ROLE])).await {
+        Err(RoleSyncError::RetriesExhausted(3, err)) => {
+            assert!(matches!(
+                *err,
+                RoleSyncError::ServerUpdate((StatusCode::BAD_GATEWAY, _))
+            ));
+        }
+        other => panic!("expected RetriesExhausted, got {other:?}"),
+    }
+    assert!(backend.sync_requests().is_empty());
+}
+
+#[tokio::test]
+async fn sync_roles_reports_attempts_on_permanent_error_after_retry() {
+    let (state, backend) = setup().await;
+    add_default_roles(&state).await;
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+
+    backend.fail_next_sync(StatusCode::SERVICE_UNAVAILABLE, "restarting");
+    backend.fail_next_sync(StatusCode::BAD_REQUEST, "bad payload");
+
+    let err = state
+        .sync_roles(&member(GUILD, 1, &[MOD_ROLE]))
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, RoleSyncError::RetriesExhausted(2, _)));
+    assert!(err.to_string().ends_with("(gave up after 2 attempts)"));
+}
+
+#[tokio::test]
+async fn link_user_verifies_code_and_syncs() {
+    let (state, backend) = setup().await;
+    add_default_roles(&state).await;
+    backend.add_user(500, "Player", 1234);
+
+    let member = member(GUILD, 1, &[MOD_ROLE]);
+
+    assert!(matches!(
+        state.link_user(&member, "player", Some(4321)).await,
+        Err(LinkError::UserNotFound)
+    ));
+    assert!(matches!(
+        state.link_user(&member, "not ascii ✓", Some(1234)).await,
+        Err(LinkError::InvalidUsername)
+    ));
+    assert!(!state.is_linked(member.user.id).await.unwrap());
+
+    let (user, roles) = state
+        .link_user(&member, "player", Some(1234))
+        .await
+        .unwrap();
+    assert_eq!((user.account_id, user.name.as_str()), (500, "Player"));
+    assert_eq!(roles, strings(&["mod"]));
+    assert_eq!(
+        backend.sync_requests(),
+        vec![RoleSyncRequestData {
+            users: vec![RoleSyncRequest {
+                account_id: 500,
+                keep: strings(&["mod"]),
+                remove: strings(&["vip"]),
+            }],
+        }]
+    );
+
+    assert!(matches!(
+        state.link_user(&member, "player", Some(1234)).await,
+        Err(LinkError::AlreadyLinked)
+    ));
+
+    // another discord user can't claim the same account
+    match state
+        .link_user(&common::member(GUILD, 2, &[]), "player", None)
+        .await
+    {
+        Err(LinkError::LinkedToOther(id)) => assert_eq!(id, UserId::new(1)),
+        other => panic!("expected LinkedToOther, got {other:?}"),
+    }
+    assert_eq!(backend.sync_requests().len(), 1);
+}
+
+#[tokio::test]
+async fn legacy_roles_assigned_to_single_guild() {
+    let db = test_database().await;
+    sqlx::query("INSERT INTO roles (guild_id, id, discord_id) VALUES (0, 'mod', 11)")
+        .execute(&db)
+        .await
+        .unwrap();
+
+    let mut config = test_config();
+    config.guilds.truncate(1);
+
+    let backend = std::sync::Arc::new(auto_role_bot::backend::MemoryBackend::new());
+    let state = auto_role_bot::state::BotState::with_backend(&config, db, backend)
+        .await
+        .unwrap();
+
+    let roles = state.get_guild_roles(GuildId::new(GUILD)).await.unwrap();
+    assert_eq!(roles.len(), 1);
+    assert_eq!(
+        state.watched_roles.read()[&GuildId::new(GUILD)],
+        vec![RoleId::new(11)]
+    );
+}
+
+fn sync_data(accounts: &[i32]) -> RoleSyncRequestData {
+    RoleSyncRequestData {
+        users: accounts
+            .iter()
+            .map(|&account_id| RoleSyncRequest {
+                account_id,
+                keep: strings(&["mod"]),
+                remove: Vec::new(),
+            })
+            .collect(),
+    }
+}
+
+#[tokio::test]
+async fn chunked_sync_splits_requests() {
+    let mut config = test_config();
+    config.sync.chunk_size = 2;
+
+    let (state, backend) = setup_with_config(config).await;
+
+    let (progress, progress_rx) = watch::channel(SyncAllProgress::default());
+    let mut sync = state.chunked_sync(&progress);
+
+    // full chunks are sent right away, not when the sync finishes
+    sync.push(sync_data(&[1, 2, 3])).await;
+    assert_eq!(backend.sync_requests().len(), 1);
+    assert_eq!(progress_rx.borrow().chunks_sent, 1);
+
+    sync.push(sync_data(&[4, 5])).await;
+    assert_eq!(backend.sync_requests().len(), 2);
+
+    let summary = sync.finish().await;
+
+    assert_eq!((summary.succeeded, summary.failed), (5, 0));
+    assert!(summary.failed_chunks.is_empty());
+    assert!(summary.is_complete());
+    assert_eq!(progress_rx.borrow().chunks_sent, 3);
+
+    let sizes: Vec<usize> = backend
+        .sync_requests()
+        .iter()
+        .map(|x| x.users.len())
+        .collect();
+    assert_eq!(sizes, vec![2, 2, 1]);
+}
+
+#[tokio::test]
+async fn chunked_sync_reports_failed_chunks() {
+    let mut config = test_config();
+    config.sync.chunk_size = 2;
+
+    let (state, backend) = setup_with_config(config).await;
+
+    let mut data = sync_data(&[1, 2, 3, 4, 5]);
+    data.users[2].keep.push("bad".to_owned());
+    backend.reject_role("bad");
+
+    let progress = watch::Sender::default();
+    let mut sync = state.chunked_sync(&progress);
+    sync.push(data).await;
+    let summary = sync.finish().await;
+
+    assert_eq!((summary.succeeded, summary.failed), (3, 2));
+    assert!(!summary.is_complete());
+    assert_eq!(summary.failed_chunks.len(), 1);
+
+    let failure = &summary.failed_chunks[0];
+    assert_eq!((failure.index, failure.users), (1, 2));
+    assert!(matches!(failure.error, RoleSyncError::ServerUpdate(_)));
+
+    // the other chunks still went through
+    assert_eq!(backend.sync_requests().len(), 2);
+    assert_eq!(backend.account_roles(5), strings(&["mod"]));
+    assert!(backend.account_roles(3).is_empty());
+}
+
+#[tokio::test]
+async fn forced_chunked_sync_sends_unchanged_users() {
+    let (state, backend) = setup().await;
+
+    let progress = watch::Sender::default();
+    let mut sync = state.chunked_sync(&progress);
+    sync.push(sync_data(&[1, 2])).await;
+    sync.finish().await;
+
+    // the server lost the roles, but the bot still remembers sending them
+    backend.set_account_roles(1, &[]);
+
+    let mut sync = state.chunked_sync(&progress);
+    sync.push(sync_data(&[1, 2])).await;
+    let summary = sync.finish().await;
+    assert_eq!((summary.succeeded, summary.unchanged), (0, 2));
+    assert!(backend.account_roles(1).is_empty());
+
+    let mut sync = state.chunked_sync(&progress).force(true);
+    sync.push(sync_data(&[1, 2])).await;
+    let summary = sync.finish().await;
+    assert_eq!((summary.succeeded, summary.unchanged), (2, 0));
+    assert_eq!(backend.account_roles(1), strings(&["mod"]));
+}
+
+#[tokio::test]
+async fn forced_chunked_sync_counts_drift() {
+    let (state, _backend) = setup().await;
+
+    let progress = watch::Sender::default();
+    let mut sync = state.chunked_sync(&progress);
+    sync.push(sync_data(&[1, 2, 3])).await;
+    assert_eq!(sync.finish().await.drifted, 3);
+
+    // one member got a new role since
+    let mut data = sync_data(&[1, 2, 3]);
+    data.users[0].keep.push("vip".to_owned());
+
+    let mut sync = state.chunked_sync(&progress).force(true);
+    sync.push(data).await;
+    let summary = sync.finish().await;
+    assert_eq!((summary.succeeded, summary.drifted), (3, 1));
+}
+
+#[tokio::test]
+async fn mass_syncs_do_not_overlap() {
+    let (state, _backend) = setup().await;
+
+    let guard = state.try_lock_mass_sync().unwrap();
+    assert!(state.try_lock_mass_sync().is_none());
+
+    drop(guard);
+    assert!(state.try_lock_mass_sync().is_some());
+}
@@ -0,0 +1,186 @@
+mod common;
+
+use auto_role_bot::{
+    backend::{RoleSyncRequest, RoleSyncRequestData},
+    db::{SyncDirection, UnlinkReason},
+    serenity::{GuildId, UserId},
+    state::{BotState, RoleSyncError},
+};
+use common::*;
+use reqwest::StatusCode;
+
+fn request(account_id: i32, keep: &[&str], remove: &[&str]) -> RoleSyncRequest {
+    RoleSyncRequest {
+        account_id,
+        keep: strings(keep),
+        remove: strings(remove),
+    }
+}
+
+async fn queued(state: &BotState) -> Vec<RoleSyncRequest> {
+    state
+        .get_queued_syncs()
+        .await
+        .unwrap()
+        .iter()
+        .map(|x| x.to_request().unwrap())
+        .collect()
+}
+
+async fn setup_linked() -> (
+    BotState,
+    std::sync::Arc<auto_role_bot::backend::MemoryBackend>,
+) {
+    let (state, backend) = setup().await;
+    let guild = GuildId::new(GUILD);
+    state
+        .add_role(guild, 11, "mod", SyncDirection::DiscordToGlobed)
+        .await
+        .unwrap();
+    state
+        .add_role(guild, 12, "vip", SyncDirection::DiscordToGlobed)
+        .await
+        .unwrap();
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+
+    (state, backend)
+}
+
+fn fail_all_attempts(backend: &auto_role_bot::backend::MemoryBackend) {
+    for _ in 0..3 {
+        backend.fail_next_sync(StatusCode::SERVICE_UNAVAILABLE, "down");
+    }
+}
+
+#[tokio::test]
+async fn failed_auto_sync_is_queued_and_drained() {
+    let (state, backend) = setup_linked().await;
+
+    fail_all_attempts(&backend);
+    state
+        .auto_sync_roles(&member(GUILD, 1, &[11]))
+        .await
+        .unwrap();
+
+    assert!(backend.sync_requests().is_empty());
+    assert_eq!(queued(&state).await, vec![request(500, &["mod"], &["vip"])]);
+
+    assert_eq!(state.process_sync_queue().await.unwrap(), 1);
+    assert!(queued(&state).await.is_empty());
+    assert_eq!(
+        backend.sync_requests(),
+        vec![RoleSyncRequestData {
+            users: vec![request(500, &["mod"], &["vip"])]
+        }]
+    );
+
+    // nothing left to do
+    assert_eq!(state.process_sync_queue().await.unwrap(), 0);
+    assert_eq!(backend.sync_requests().len(), 1);
+}
+
+#[tokio::test]
+async fn queued_entries_merge_with_latest_state_winning() {
+    let (state, _) = setup().await;
+
+    state
+        .enqueue_sync(&RoleSyncRequestData {
+            users: vec![request(500, &["a"], &["b", "c"]), request(600, &[], &["a"])],
+        })
+        .await
+        .unwrap();
+
+    state
+        .enqueue_sync(&RoleSyncRequestData {
+            users: vec![request(500, &["b"], &["a"])],
+        })
+        .await
+        .unwrap();
+
+    let mut entries = queued(&state).await;
+    entries.sort_by_key(|x| x.account_id);
+
+    assert_eq!(
+        entries,
+        vec![request(500, &["b"], &["a", "c"]), request(600, &[], &["a"])]
+    );
+}
+
+#[tokio::test]
+async fn direct_sync_settles_queued_entry() {
+    let (state, backend) = setup_linked().await;
+
+    state
+        .enqueue_sync(&RoleSyncRequestData {
+            users: vec![request(500, &["mod", "helper"], &["vip"])],
+        })
+        .await
+        .unwrap();
+
+    state.sync_roles(&member(GUILD, 1, &[12])).await.unwrap();
+
+    // only the role that wasn't part of the direct sync is left
+    assert_eq!(queued(&state).await, vec![request(500, &["helper"], &[])]);
+
+    state.process_sync_queue().await.unwrap();
+    assert_eq!(backend.account_roles(500), strings(&["helper", "vip"]));
+}
+
+#[tokio::test]
+async fn unreachable_server_keeps_entries() {
+    let (state, backend) = setup_linked().await;
+
+    state
+        .enqueue_sync(&RoleSyncRequestData {
+            users: vec![request(500, &["mod"], &[])],
+        })
+        .await
+        .unwrap();
+
+    fail_all_attempts(&backend);
+    assert!(matches!(
+        state.process_sync_queue().await,
+        Err(RoleSyncError::RetriesExhausted(3, _))
+    ));
+
+    let entries = state.get_queued_syncs().await.unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].attempts, 1);
+}
+
+#[tokio::test]
+async fn rejected_entries_are_dropped_individually() {
+    let (state, backend) = setup().await;
+
+    state
+        .enqueue_sync(&RoleSyncRequestData {
+            users: vec![request(500, &["mod"], &[]), request(600, &["bad"], &[])],
+        })
+        .await
+        .unwrap();
+
+    // the batch is rejected, then every user is retried on its own
+    backend.reject_role("bad");
+
+    assert_eq!(state.process_sync_queue().await.unwrap(), 1);
+    assert!(queued(&state).await.is_empty());
+    assert_eq!(backend.account_roles(500), strings(&["mod"]));
+    assert!(backend.account_roles(600).is_empty());
+}
+
+#[tokio::test]
+async fn failed_unlink_is_queued() {
+    let (state, backend) = setup_linked().await;
+
+    fail_all_attempts(&backend);
+    state
+        .unlink_user(UserId::new(1), UnlinkReason::User)
+        .await
+        .unwrap();
+
+    assert!(!state.is_linked(UserId::new(1)).await.unwrap());
+    assert_eq!(
+        queued(&state).await,
+        vec![request(500, &[], &["mod", "vip"])]
+    );
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use std::time::Duration;
+
+use auto_role_bot::{
+    backend::{RoleSyncRequest, RoleSyncRequestData},
+    commands::{begin_syncall, syncall_reply},
+    serenity,
+    state::{BotState, RoleSyncError, SyncAllSummary},
+};
+use common::*;
+use tokio::sync::watch;
+
+fn sync_data(accounts: &[i32]) -> RoleSyncRequestData {
+    RoleSyncRequestData {
+        users: accounts
+            .iter()
+            .map(|&account_id| RoleSyncRequest {
+                account_id,
+                keep: strings(&["mod"]),
+                remove: Vec::new(),
+            })
+            .collect(),
+    }
+}
+
+async fn run_sync(state: &BotState, data: RoleSyncRequestData) -> SyncAllSummary {
+    let progress = watch::Sender::default();
+    let mut sync = state.chunked_sync(&progress);
+    sync.push(data).await;
+    sync.finish().await
+}
+
+#[tokio::test]
+async fn syncall_refuses_while_another_sync_runs() {
+    let (state, _backend) = setup().await;
+
+    let running = state.try_lock_mass_sync().unwrap();
+
+    let busy = begin_syncall(&state).unwrap_err();
+    assert!(busy.contains("already running"), "{busy}");
+
+    drop(running);
+
+    let guard = begin_syncall(&state).unwrap();
+    assert!(state.try_lock_mass_sync().is_none());
+    drop(guard);
+}
+
+#[tokio::test]
+async fn syncall_reply_after_success() {
+    let (state, _backend) = setup().await;
+
+    let summary = run_sync(&state, sync_data(&[1, 2])).await;
+    let reply = syncall_reply(&Ok(summary), Duration::from_millis(1500));
+    assert_eq!(
+        reply,
+        "✅ Successfully synced roles of 2 people, 0 were already up to date! (took 1.5s)"
+    );
+
+    // nothing changed since the last sync
+    let summary = run_sync(&state, sync_data(&[1, 2])).await;
+    let reply = syncall_reply(&Ok(summary), Duration::from_secs(1));
+    assert!(
+        reply.contains("synced roles of 0 people, 2 were already up to date"),
+        "{reply}"
+    );
+}
+
+#[tokio::test]
+async fn syncall_reply_lists_failed_chunks() {
+    let mut config = test_config();
+    config.sync.chunk_size = 2;
+
+    let (state, backend) = setup_with_config(config).await;
+    backend.reject_role("bad");
+
+    let mut data = sync_data(&[1, 2, 3, 4, 5]);
+    data.users[3].keep.push("bad".to_owned());
+
+    let summary = run_sync(&state, data).await;
+    let reply = syncall_reply(&Ok(summary), Duration::from_secs(2));
+
+    assert!(
+        reply.starts_with(":warning: Synced roles of 3 out of 5 people in 2.0s"),
+        "{reply}"
+    );
+    assert!(
+        reply.contains(
+            "* chunk 2 (2 users): Server returned error (code 400 Bad Request): unknown role: bad"
+        ),
+        "{reply}"
+    );
+    assert!(!reply.contains("chunk 1 "), "{reply}");
+}
+
+#[tokio::test]
+async fn syncall_reply_reports_incomplete_scan() {
+    let (state, _backend) = setup().await;
+
+    let mut summary = run_sync(&state, sync_data(&[1])).await;
+    summary.scan_error = Some(serenity::Error::Other("discord is down"));
+
+    let reply = syncall_reply(&Ok(summary), Duration::from_secs(1));
+    assert!(
+        reply.starts_with(":warning: Synced roles of 1 people"),
+        "{reply}"
+    );
+    assert!(
+        reply
+            .contains("not all members could be fetched, so some were not synced: discord is down"),
+        "{reply}"
+    );
+}
+
+#[test]
+fn syncall_reply_on_error() {
+    let reply = syncall_reply(&Err(RoleSyncError::NotLinked), Duration::ZERO);
+    assert_eq!(reply, ":x: Error while syncing members: User not linked");
+}
@@ -0,0 +1,132 @@
+mod common;
+
+use auto_role_bot::{
+    backend::{MemoryBackend, RoleSyncRequest, RoleSyncRequestData},
+    db::SyncDirection,
+    serenity::{GuildId, UserId},
+    state::BotState,
+};
+use common::*;
+use reqwest::StatusCode;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+async fn setup_linked() -> (BotState, Arc<MemoryBackend>) {
+    let (state, backend) = setup().await;
+    let guild = GuildId::new(GUILD);
+    state
+        .add_role(guild, 11, "mod", SyncDirection::DiscordToGlobed)
+        .await
+        .unwrap();
+    state
+        .add_role(guild, 12, "vip", SyncDirection::DiscordToGlobed)
+        .await
+        .unwrap();
+    state.add_linked_user(UserId::new(1), 500).await.unwrap();
+
+    (state, backend)
+}
+
+#[tokio::test]
+async fn unchanged_roles_are_not_synced_again() {
+    let (state, backend) = setup_linked().await;
+    assert_eq!(state.get_synced_roles(500).await.unwrap(), None);
+
+    state
+        .auto_sync_roles(&member(GUILD, 1, &[11]))
+        .await
+        .unwrap();
+    assert_eq!(
+        state.get_synced_roles(500).await.unwrap(),
+        Some(strings(&["mod"]))
+    );
+
+    state
+        .auto_sync_roles(&member(GUILD, 1, &[11]))
+        .await
+        .unwrap();
+    assert_eq!(backend.sync_requests().len(), 1);
+
+    state
+        .auto_sync_roles(&member(GUILD, 1, &[12]))
+        .await
+        .unwrap();
+    assert_eq!(backend.sync_requests().len(), 2);
+    assert_eq!(
+        state.get_synced_roles(500).await.unwrap(),
+        Some(strings(&["vip"]))
+    );
+}
+
+#[tokio::test]
+async fn manual_sync_always_contacts_the_server() {
+    let (state, backend) = setup_linked().await;
+    let member = member(GUILD, 1, &[11]);
+
+    state.sync_roles(&member).await.unwrap();
+    state.sync_roles(&member).await.unwrap();
+
+    assert_eq!(backend.sync_requests().len(), 2);
+}
+
+#[tokio::test]
+async fn failed_sync_does_not_update_snapshot() {
+    let (state, backend) = setup_linked().await;
+
+    state
+        .auto_sync_roles(&member(GUILD, 1, &[11]))
+        .await
+        .unwrap();
+
+    // the server is down, the change gets queued
+    for _ in 0..3 {
+        backend.fail_next_sync(StatusCode::SERVICE_UNAVAILABLE, "down");
+    }
+    state.auto_sync_roles(&member(GUILD, 1, &[])).await.unwrap();
+    assert_eq!(
+        state.get_synced_roles(500).await.unwrap(),
+        Some(strings(&["mod"]))
+    );
+
+    // matches the snapshot, but still has to be sent so the queued removal doesn't win
+    state
+        .auto_sync_roles(&member(GUILD, 1, &[11]))
+        .await
+        .unwrap();
+    assert_eq!(backend.sync_requests().len(), 2);
+    assert!(state.get_queued_syncs().await.unwrap().is_empty());
+    assert_eq!(backend.account_roles(500), strings(&["mod"]));
+}
+
+#[tokio::test]
+async fn chunked_sync_counts_unchanged_users() {
+    let (state, backend) = setup_linked().await;
+
+    let data = RoleSyncRequestData {
+        users: vec![
+            RoleSyncRequest {
+                account_id: 500,
+                keep: strings(&["mod"]),
+                remove: strings(&["vip"]),
+            },
+            RoleSyncRequest {
+                account_id: 600,
+                keep: strings(&["vip"]),
+                remove: strings(&["mod"]),
+            },
+        ],
+    };
+
+    let progress = watch::Sender::default();
+
+    let mut sync = state.chunked_sync(&progress);
+    sync.push(data.clone()).await;
+    let summary = sync.finish().await;
+    assert_eq!((summary.succeeded, summary.unchanged), (2, 0));
+
+    let mut sync = state.chunked_sync(&progress);
+    sync.push(data).await;
+    let summary = sync.finish().await;
+    assert_eq!((summary.succeeded, summary.unchanged), (0, 2));
+    assert_eq!(backend.sync_requests().len(), 1);
+}